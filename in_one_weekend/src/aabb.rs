@@ -14,6 +14,12 @@ impl AABB {
 	pub fn new(p1: Point3, p2: Point3) -> AABB {
 		AABB{p1, p2}
 	}
+	pub fn min(&self) -> Point3 {
+		self.p1
+	}
+	pub fn max(&self) -> Point3 {
+		self.p2
+	}
 	pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
 		let (tx1_, tx2_) = (
 			(self.p1.0 - r.orig.0) / r.dir.0, 
@@ -85,17 +91,15 @@ fn overlap_test() {
 #[cfg(test)]
 #[test]
 fn intersect_test() {
-    use std::f64::{NEG_INFINITY, INFINITY};
-
 	let bb = AABB{p1: Vec3(0.0, 0.0, 0.0), p2: Vec3(1.0, 1.0, 1.0)};
 
-	assert!(bb.hit(&Ray{orig: Vec3(0.0, 0.0, 0.0), dir: Vec3(1.0, 1.0, 1.0)}, NEG_INFINITY, INFINITY));
-	assert!(!bb.hit(&Ray{orig: Vec3(2.0, 0.0, 0.0), dir: Vec3(1.0, 1.0, 1.0)}, NEG_INFINITY, INFINITY));
-	assert!(bb.hit(&Ray{orig: Vec3(2.0, 2.0, 2.0), dir: Vec3(1.0, 1.0, 1.0)}, NEG_INFINITY, INFINITY));
+	assert!(bb.hit(&Ray { orig: Vec3(0.0, 0.0, 0.0), dir: Vec3(1.0, 1.0, 1.0), time: 0.0 }, f64::NEG_INFINITY, f64::INFINITY));
+	assert!(!bb.hit(&Ray { orig: Vec3(2.0, 0.0, 0.0), dir: Vec3(1.0, 1.0, 1.0), time: 0.0 }, f64::NEG_INFINITY, f64::INFINITY));
+	assert!(bb.hit(&Ray { orig: Vec3(2.0, 2.0, 2.0), dir: Vec3(1.0, 1.0, 1.0), time: 0.0 }, f64::NEG_INFINITY, f64::INFINITY));
 
-	assert!(!bb.hit(&Ray{orig: Vec3(0.0, 0.0, 0.0), dir: Vec3(1.0, 0.0, 0.0)}, NEG_INFINITY, INFINITY));
-	assert!(!bb.hit(&Ray{orig: Vec3(0.0, 1.0, 0.0), dir: Vec3(1.0, 0.0, 0.0)}, NEG_INFINITY, INFINITY));
+	assert!(!bb.hit(&Ray { orig: Vec3(0.0, 0.0, 0.0), dir: Vec3(1.0, 0.0, 0.0), time: 0.0 }, f64::NEG_INFINITY, f64::INFINITY));
+	assert!(!bb.hit(&Ray { orig: Vec3(0.0, 1.0, 0.0), dir: Vec3(1.0, 0.0, 0.0), time: 0.0 }, f64::NEG_INFINITY, f64::INFINITY));
 
-	assert!(!bb.hit(&Ray{orig: Vec3(0.0, 0.0, 0.0), dir: Vec3(1.0, 1.0, 1.0)}, 10.0, INFINITY));
-	assert!(!bb.hit(&Ray{orig: Vec3(0.0, 0.0, 0.0), dir: Vec3(1.0, 1.0, 1.0)}, NEG_INFINITY, -0.1));
+	assert!(!bb.hit(&Ray { orig: Vec3(0.0, 0.0, 0.0), dir: Vec3(1.0, 1.0, 1.0), time: 0.0 }, 10.0, f64::INFINITY));
+	assert!(!bb.hit(&Ray { orig: Vec3(0.0, 0.0, 0.0), dir: Vec3(1.0, 1.0, 1.0), time: 0.0 }, f64::NEG_INFINITY, -0.1));
 }
\ No newline at end of file
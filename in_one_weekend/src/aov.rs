@@ -0,0 +1,85 @@
+// Auxiliary output variables (AOVs): images of the surfaces seen through
+// every pixel, written next to the rendered image for compositing and used
+// to guide the denoiser.
+use crate::vec3::*;
+use crate::output::Film;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Aov {
+	// Color of the surface, without lighting.
+	Albedo,
+	// Shading normal facing the camera, components in [-1, 1].
+	Normal,
+	// Distance from the camera, infinite for the background.
+	Depth,
+	// World coordinates.
+	Position,
+	// Number of the object in the scene, counted from 1, 0 for the background.
+	Id,
+}
+
+impl Aov {
+	pub const NAMES: [&'static str; 5] = ["albedo", "normal", "depth", "position", "id"];
+
+	pub fn parse(s: &str) -> Option<Aov> {
+		match s {
+			"albedo" => Some(Aov::Albedo),
+			"normal" => Some(Aov::Normal),
+			"depth" => Some(Aov::Depth),
+			"position" => Some(Aov::Position),
+			"id" => Some(Aov::Id),
+			_ => None,
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		Aov::NAMES[self as usize]
+	}
+}
+
+// The AOVs of a frame, stored like Film pixels. Albedo and normal are
+// averaged over several rays through the pixel, which smooths edges. The
+// other values are those of the first ray, since averages of them are
+// meaningless at the edges of objects.
+pub struct Features {
+	pub albedo: Vec<Color>,
+	pub normal: Vec<Vec3>,
+	pub depth: Vec<f64>,
+	pub position: Vec<Point3>,
+	pub id: Vec<usize>,
+}
+
+impl Features {
+	// The AOV as a linear image of the given size, single values are
+	// stored in all three channels.
+	pub fn film(&self, aov: Aov, width: usize, height: usize) -> Film {
+		let gray = |x: f64| { Vec3(x, x, x) };
+		let pixels = match aov {
+			Aov::Albedo => self.albedo.clone(),
+			Aov::Normal => self.normal.clone(),
+			Aov::Depth => self.depth.iter().map(|d| gray(*d)).collect(),
+			Aov::Position => self.position.clone(),
+			Aov::Id => self.id.iter().map(|id| gray(*id as f64)).collect(),
+		};
+		Film { width, height, pixels }
+	}
+}
+
+#[test]
+fn aov_test() {
+	for name in Aov::NAMES {
+		assert_eq!(Aov::parse(name).unwrap().name(), name);
+	}
+	assert!(Aov::parse("beauty").is_none());
+
+	let f = Features {
+		albedo: vec![Vec3(0.5, 0.5, 0.5), Vec3(0.7, 0.8, 1.0)],
+		normal: vec![Vec3(0.0, 1.0, 0.0), Vec3(0.0, 0.0, 0.0)],
+		depth: vec![2.5, f64::INFINITY],
+		position: vec![Vec3(1.0, 0.0, 2.0), Vec3(0.0, 0.0, 0.0)],
+		id: vec![3, 0],
+	};
+	assert_eq!(f.film(Aov::Depth, 2, 1).pixels, [Vec3(2.5, 2.5, 2.5), Vec3(f64::INFINITY, f64::INFINITY, f64::INFINITY)]);
+	assert_eq!(f.film(Aov::Id, 2, 1).pixels, [Vec3(3.0, 3.0, 3.0), Vec3(0.0, 0.0, 0.0)]);
+	assert_eq!(f.film(Aov::Normal, 2, 1).pixels, f.normal);
+}
@@ -0,0 +1,210 @@
+use crate::aabb::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+use crate::hit::*;
+use crate::rng::Rng;
+
+// Axis-aligned box. Rotated boxes are made by wrapping it into a Transform.
+pub struct BoxShape {
+	pub material: Box<dyn Material>,
+	// Opposite corners, `min` has the smaller coordinates.
+	pub min: Point3,
+	pub max: Point3,
+}
+
+fn axis(v: Vec3, a: usize) -> f64 {
+	match a {
+		0 => v.0,
+		1 => v.1,
+		_ => v.2,
+	}
+}
+
+// Entry or exit point of a ray through the box.
+struct Crossing {
+	t: f64,
+	// Axis the crossed face is perpendicular to.
+	axis: usize,
+	// Whether the crossed face is the one at `max` on its axis.
+	at_max: bool,
+}
+
+impl BoxShape {
+	pub fn new(p0: Point3, p1: Point3, material: Box<dyn Material>) -> BoxShape {
+		BoxShape {
+			min: Vec3(p0.0.min(p1.0), p0.1.min(p1.1), p0.2.min(p1.2)),
+			max: Vec3(p0.0.max(p1.0), p0.1.max(p1.1), p0.2.max(p1.2)),
+			material,
+		}
+	}
+
+	// Slab intersection. Returns where the ray line enters and leaves the box.
+	fn crossings(&self, r: &Ray) -> Option<(Crossing, Crossing)> {
+		let mut enter = Crossing { t: f64::NEG_INFINITY, axis: 0, at_max: false };
+		let mut exit = Crossing { t: f64::INFINITY, axis: 0, at_max: false };
+		for a in 0..3 {
+			let inv_d = 1.0 / axis(r.dir, a);
+			let t0 = (axis(self.min, a) - axis(r.orig, a)) * inv_d;
+			let t1 = (axis(self.max, a) - axis(r.orig, a)) * inv_d;
+			// A ray going in the negative direction enters through the face at max.
+			let (near, far, enters_at_max) = if inv_d < 0.0 { (t1, t0, true) } else { (t0, t1, false) };
+			if near > enter.t {
+				enter = Crossing { t: near, axis: a, at_max: enters_at_max };
+			}
+			if far < exit.t {
+				exit = Crossing { t: far, axis: a, at_max: !enters_at_max };
+			}
+		}
+		if enter.t > exit.t {
+			return None;
+		}
+		Some((enter, exit))
+	}
+
+	fn face_area(&self, a: usize) -> f64 {
+		let d = self.max - self.min;
+		match a {
+			0 => d.1 * d.2,
+			1 => d.0 * d.2,
+			_ => d.0 * d.1,
+		}
+	}
+
+	fn area(&self) -> f64 {
+		2.0 * (0..3).map(|a| self.face_area(a)).sum::<f64>()
+	}
+}
+
+impl Hittable for BoxShape {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+		let (enter, exit) = self.crossings(r)?;
+		// The exit point is hit when the ray starts inside the box.
+		let c = if enter.t >= t_min && enter.t <= t_max {
+			enter
+		} else if exit.t >= t_min && exit.t <= t_max {
+			exit
+		} else {
+			return None;
+		};
+		let p = r.at(c.t);
+		let mut outward_normal = Vec3(0.0, 0.0, 0.0);
+		let sign = if c.at_max { 1.0 } else { -1.0 };
+		match c.axis {
+			0 => outward_normal.0 = sign,
+			1 => outward_normal.1 = sign,
+			_ => outward_normal.2 = sign,
+		}
+		// Face coordinates as for the rectangle with the same orientation.
+		let (u, v) = match c.axis {
+			0 => (1, 2),
+			1 => (0, 2),
+			_ => (0, 1),
+		};
+		let coord = |a: usize| { (axis(p, a) - axis(self.min, a)) / (axis(self.max, a) - axis(self.min, a)) };
+		let mut hr = HitRecord {
+			p,
+			normal: outward_normal,
+			t: c.t,
+			material: self.material.as_ref(),
+			front_face: false,
+			object_id: 0,
+			coord: Vec2(coord(u), coord(v)),
+		};
+		hr.set_face_normal(r, outward_normal);
+		Some(hr)
+	}
+
+	fn bounding_box(&self) -> Option<AABB> {
+		Some(AABB::new(self.min, self.max))
+	}
+
+	// Uniformly distributed point on the surface of the box.
+	fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		let mut x = random_f64(rng, 0.0, self.area() / 2.0);
+		let mut a = 0;
+		while a < 2 && x > self.face_area(a) {
+			x -= self.face_area(a);
+			a += 1;
+		}
+		let mut p = Vec3(
+			random_f64(rng, self.min.0, self.max.0),
+			random_f64(rng, self.min.1, self.max.1),
+			random_f64(rng, self.min.2, self.max.2),
+		);
+		let side = if random_f64(rng, 0.0, 1.0) < 0.5 { self.min } else { self.max };
+		match a {
+			0 => p.0 = side.0,
+			1 => p.1 = side.1,
+			_ => p.2 = side.2,
+		}
+		p - *origin
+	}
+
+	// Sums the densities of all points on the surface in direction `dir`:
+	// both the entry and the exit point can be sampled.
+	fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
+		let r = Ray { orig: *origin, dir: *dir, time: 0.0 };
+		let (enter, exit) = match self.crossings(&r) {
+			Some(x) => x,
+			None => return 0.0,
+		};
+		let area = self.area();
+		[enter, exit].iter().filter(|c| c.t > 0.0001 && c.t.is_finite()).map(|c| {
+			let d_squared = c.t * c.t * dir.length_squared();
+			let cos = axis(*dir, c.axis).abs() / dir.length();
+			d_squared / (cos * area)
+		}).sum()
+	}
+
+	fn pick_lights(&self) -> Vec<&dyn Hittable> {
+		if self.material.is_light() {
+			vec![self]
+		} else {
+			vec![]
+		}
+	}
+
+	fn power(&self) -> f64 {
+		diffuse_power(self.material.as_ref(), self.area(), &((self.min + self.max) / 2.0))
+	}
+}
+
+#[cfg(test)]
+fn test_box() -> BoxShape {
+	use crate::metal::*;
+	use crate::texture::*;
+
+	BoxShape::new(Vec3(1.0, 2.0, 3.0), Vec3(0.0, 0.0, 0.0), Box::new(DiffuseLight { emit: Box::new(SolidColor { color: Vec3(1.0, 1.0, 1.0) }) }))
+}
+
+#[test]
+fn hit_test() {
+	let b = test_box();
+	assert_eq!(b.bounding_box(), Some(AABB::new(Vec3(0.0, 0.0, 0.0), Vec3(1.0, 2.0, 3.0))));
+
+	let hr = b.hit(&Ray { orig: Vec3(0.5, 1.0, -1.0), dir: Vec3(0.0, 0.0, 1.0), time: 0.0 }, 0.0, f64::INFINITY).unwrap();
+	assert_eq!((hr.t, hr.normal, hr.front_face, hr.coord), (1.0, Vec3(0.0, 0.0, -1.0), true, Vec2(0.5, 0.5)));
+
+	// From the inside the far face is hit from the back.
+	let hr = b.hit(&Ray { orig: Vec3(0.5, 1.0, 1.0), dir: Vec3(-1.0, 0.0, 0.0), time: 0.0 }, 0.0, f64::INFINITY).unwrap();
+	assert_eq!((hr.t, hr.normal, hr.front_face), (0.5, Vec3(1.0, 0.0, 0.0), false));
+
+	assert!(b.hit(&Ray { orig: Vec3(2.0, 1.0, -1.0), dir: Vec3(0.0, 0.0, 1.0), time: 0.0 }, 0.0, f64::INFINITY).is_none());
+	assert!(b.hit(&Ray { orig: Vec3(0.5, 1.0, -1.0), dir: Vec3(0.0, 0.0, 1.0), time: 0.0 }, 0.0, 0.5).is_none());
+}
+
+#[test]
+fn pdf_test() {
+	let b = test_box();
+	assert_eq!(b.pick_lights().len(), 1);
+	let origin = Vec3(5.0, 4.0, -3.0);
+	let mut rng = crate::rng::scene_rng(0);
+	for _ in 0..10 {
+		let v = b.gen_random_point(&origin, &mut rng);
+		let p = origin + v;
+		assert!((p - Vec3(0.5, 1.0, 1.5)).length() <= Vec3(0.5, 1.0, 1.5).length() + 1e-9);
+		assert!(b.pdf_eval(&origin, &v) > 0.0);
+	}
+	assert_eq!(b.pdf_eval(&origin, &Vec3(1.0, 0.0, 0.0)), 0.0);
+}
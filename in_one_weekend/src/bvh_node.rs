@@ -1,11 +1,10 @@
 use std::cmp::Ordering;
 
-use rand::random;
-
 use crate::aabb::*;
 use crate::vec3::*;
 use crate::hit::*;
 use crate::ray::*;
+use crate::rng::Rng;
 
 // Bounded volume hierarchy.
 pub struct BVHNode {
@@ -18,13 +17,13 @@ impl Hittable for BVHNode {
 	fn bounding_box(&self) -> Option<AABB> {
 		Some(self.bbox.clone())
 	}
-	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
 		if !self.bbox.hit(r, t_min, t_max) {
 			return None;
 		}
 
 		let mut first_hit_at = t_max;
-		let mut hr: Option<HitRecord> = None;
+		let mut hr: Option<HitRecord<'_>> = None;
 		// Try the left child. Then try the right child, but only take right if it was hit before the left child.
 		if let Some(h_left) = self.child0.hit(r, t_min, t_max) {
 			first_hit_at = h_left.t;
@@ -38,7 +37,7 @@ impl Hittable for BVHNode {
 		hr
 	}
 
-	fn gen_random_point(&self, _origin: &Vec3) -> Vec3 {
+	fn gen_random_point(&self, _origin: &Vec3, _rng: &mut Rng) -> Vec3 {
 		panic!("should not call random point on BVHNode");
 	}
 
@@ -54,8 +53,16 @@ impl Hittable for BVHNode {
 
 impl BVHNode {
 	pub fn new(mut objs: Vec<Box<dyn Hittable>>) -> BVHNode {
-		let axis = random::<usize>() % 3;
-		let comparator = |h1: &Box<dyn Hittable>, h2: &Box<dyn Hittable>| {
+		// Splitting along the longest side keeps the tree the same from run to run.
+		let bbox = objs.iter().filter_map(|o| o.bounding_box()).reduce(|a, b| a.surrounding_box(&b));
+		let axis = match bbox {
+			Some(b) => {
+				let d = b.max() - b.min();
+				if d.0 >= d.1 && d.0 >= d.2 { 0 } else if d.1 >= d.2 { 1 } else { 2 }
+			}
+			None => 0,
+		};
+		let comparator = |h1: &dyn Hittable, h2: &dyn Hittable| {
 			if let (Some(b1), Some(b2)) = (h1.bounding_box(), h2.bounding_box()) {
 				AABB::compare_axis(&b1, &b2, axis)
 			} else {
@@ -72,14 +79,14 @@ impl BVHNode {
 				let a = objs.pop().unwrap();
 				let b = objs.pop().unwrap();
 
-				if comparator(&a, &b) == Ordering::Less {
+				if comparator(a.as_ref(), b.as_ref()) == Ordering::Less {
 					(a, Some(b))
 				} else {
 					(b, Some(a))
 				}
 			}
 			_ => {
-				objs.sort_unstable_by(comparator);
+				objs.sort_unstable_by(|a, b| comparator(a.as_ref(), b.as_ref()));
 				let mid = object_span/2;
 				let sr = objs.split_off(mid);
 				let x = Box::new(BVHNode::new(objs)) as Box<dyn Hittable>;
@@ -87,7 +94,10 @@ impl BVHNode {
 				(x, Some(y))
 			}
 		};
-		let rb = if child1.is_none() {child0.bounding_box()} else {child1.as_ref().unwrap().bounding_box()};
+		let rb = match &child1 {
+			Some(c1) => c1.bounding_box(),
+			None => child0.bounding_box(),
+		};
 		if let (Some(b1), Some(b2)) = (child0.bounding_box(), rb) {
 			BVHNode{ bbox: b1.surrounding_box(&b2), child0, child1}
 		} else {
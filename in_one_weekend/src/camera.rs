@@ -1,7 +1,89 @@
 use std::f64::consts::PI;
+use std::sync::Arc;
+
+use rand::Rng as _;
 
 use crate::vec3::*;
 use crate::ray::*;
+use crate::hit::*;
+use crate::scene::View;
+use crate::rng::Rng;
+
+// Shape of the lens opening, which out-of-focus highlights (bokeh) take.
+#[derive(Clone, Default)]
+pub enum Aperture {
+    #[default]
+    Circle,
+    // A regular polygon with a corner `rotation_deg` degrees counter-clockwise
+    // from the right, as formed by the blades of a diaphragm.
+    Polygon { blades: usize, rotation_deg: f64 },
+    // Shaped like the bright parts of an image.
+    Mask(Arc<ApertureMask>),
+}
+
+impl Aperture {
+    // A random point of the opening, within the unit disk for the circle and
+    // polygons and the square [-1, 1]^2 for masks.
+    fn sample(&self, rng: &mut Rng) -> Vec3 {
+        match self {
+            Aperture::Circle => random_in_unit_disk(rng),
+            Aperture::Polygon { blades, rotation_deg } => {
+                // A random point in one of the triangles between the center and two corners.
+                let corner = |k: usize| {
+                    let a = degrees_to_radians(*rotation_deg) + 2.0 * PI * k as f64 / *blades as f64;
+                    Vec3(a.cos(), a.sin(), 0.0)
+                };
+                let k = rng.gen_range(0..*blades);
+                let (r1, r2) = (rng.gen::<f64>().sqrt(), rng.gen::<f64>());
+                r1 * ((1.0 - r2) * corner(k) + r2 * corner(k + 1))
+            }
+            Aperture::Mask(mask) => mask.sample(rng),
+        }
+    }
+}
+
+// A grayscale image as the lens opening, light passes in proportion to the
+// brightness of its pixels.
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    // Cumulative brightness of the pixels before each pixel, rows top to bottom.
+    cdf: Vec<f64>,
+    total: f64,
+}
+
+impl ApertureMask {
+    pub fn new(width: usize, height: usize, values: &[f64]) -> Result<ApertureMask, String> {
+        let mut cdf = Vec::with_capacity(width * height);
+        let mut total = 0.0;
+        for v in values {
+            cdf.push(total);
+            total += v.max(0.0);
+        }
+        if total <= 0.0 {
+            return Err("the aperture mask is black and lets no light through".to_string());
+        }
+        Ok(ApertureMask { width, height, cdf, total })
+    }
+
+    pub fn load(path: &str) -> Result<ApertureMask, String> {
+        let img = image::open(path).map_err(|e| format!("failed to load '{}': {}", path, e))?.into_rgb32f();
+        let values = img.pixels().map(|p| luminance(Vec3(p[0] as f64, p[1] as f64, p[2] as f64))).collect::<Vec<_>>();
+        ApertureMask::new(img.width() as usize, img.height() as usize, &values)
+            .map_err(|e| format!("'{}': {}", path, e))
+    }
+
+    // The longer side of the image spans [-1, 1].
+    fn sample(&self, rng: &mut Rng) -> Vec3 {
+        let target = rng.gen::<f64>() * self.total;
+        let p = self.cdf.partition_point(|c| *c <= target).saturating_sub(1);
+        let (i, j) = (p % self.width, p / self.width);
+        let size = self.width.max(self.height) as f64;
+        let x = (2.0 * (i as f64 + rng.gen::<f64>()) - self.width as f64) / size;
+        let y = (self.height as f64 - 2.0 * (j as f64 + rng.gen::<f64>())) / size;
+        Vec3(x, y, 0.0)
+    }
+}
 
 pub struct Camera {
     origin: Point3,
@@ -9,14 +91,36 @@ pub struct Camera {
     horizontal: Vec3,
     vertical: Vec3,
     u: Vec3,
-    v: Vec3, 
+    v: Vec3,
     lens_radius: f64,
+    aperture: Aperture,
+    // A point and the normal of the plane in focus, if it is tilted. Otherwise
+    // the plane of the image corners is in focus.
+    focal_plane: Option<(Point3, Vec3)>,
+    // Rays are sent at random times between the shutter opening and closing.
+    time0: f64,
+    time1: f64,
 }
 impl Camera {
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius*random_in_unit_disk();
+    pub fn get_ray(&self, s: f64, t: f64, rng: &mut Rng) -> Ray {
+        let rd = self.lens_radius*self.aperture.sample(rng);
+        let time = if self.time1 > self.time0 { rng.gen_range(self.time0..self.time1) } else { self.time0 };
         let offset = self.u * rd.0 + self.v*rd.1;
-        Ray { orig: self.origin+offset, dir: self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset}
+        let target = self.lower_left_corner + s*self.horizontal + t*self.vertical;
+        let target = match self.focal_plane {
+            None => target,
+            Some((p, n)) => {
+                let d = target - self.origin;
+                let k = dot(p - self.origin, n) / dot(d, n);
+                if !(k > 0.0 && k.is_finite()) {
+                    // The plane in focus is not in front of the camera in this
+                    // direction, focus at infinity.
+                    return Ray { orig: self.origin+offset, dir: d, time };
+                }
+                self.origin + k*d
+            }
+        };
+        Ray { orig: self.origin+offset, dir: target - self.origin - offset, time }
     }
 }
 
@@ -24,36 +128,96 @@ fn degrees_to_radians(deg: f64) -> f64 {
     deg * PI / 180.0
 }
 
-pub fn build_camera(
-    look_from: Point3,
-    look_at: Point3,
-    v_up: Vec3,
-    vfov_deg: f64,
-    aspect_ratio: f64,
-    aperture: f64,
-    focus_dist: f64,
-) -> Camera {
-    let theta = degrees_to_radians(vfov_deg);
+// The camera of view `v`. Autofocus looks into `world`.
+pub fn build_camera(v: &View, aspect_ratio: f64, world: &dyn Hittable) -> Camera {
+    let theta = degrees_to_radians(v.vfov_deg);
     let h = (theta/2.0).tan();
     let viewport_height = 2.0 * h;
     let viewport_width = aspect_ratio * viewport_height;
-    
-    let w = unit_vector(look_from - look_at);
-    let u = unit_vector(cross(v_up, w));
-    let v = cross(w, u);
 
-    let origin = look_from;
+    let w = unit_vector(v.look_from - v.look_at);
+    let u = unit_vector(cross(v.v_up, w));
+    let vv = cross(w, u);
+
+    let origin = v.look_from;
+    // Direction to the image center at distance 1 along the view direction.
+    let center = v.shift.0*viewport_width*u + v.shift.1*viewport_height*vv - w;
+    let mut focus_dist = v.dist_to_focus;
+    if v.autofocus {
+        if let Some(hr) = world.hit(&Ray { orig: origin, dir: center, time: v.shutter_open }, 0.001, f64::INFINITY) {
+            focus_dist = dot(hr.p - origin, -1.0*w);
+        }
+    }
     let horizontal = focus_dist * viewport_width*u;
-    let vertical = focus_dist * viewport_height*v;
-    let lower_left_corner = origin - horizontal/2.0 - vertical/2.0 - focus_dist * w;
-    let lens_radius = aperture / 2.0;
+    let vertical = focus_dist * viewport_height*vv;
+    let lower_left_corner = origin + focus_dist * center - horizontal/2.0 - vertical/2.0;
+    let lens_radius = v.aperture / 2.0;
+    // The plane in focus turns around the horizontal line through the
+    // focused point in the image center.
+    let focal_plane = if v.tilt_deg != 0.0 {
+        let (sin, cos) = degrees_to_radians(v.tilt_deg).sin_cos();
+        Some((origin + focus_dist * center, cos*w + sin*vv))
+    } else {
+        None
+    };
 
     Camera{
         origin,
         horizontal,
         vertical,
         lower_left_corner,
-        u, v,
-        lens_radius
+        u, v: vv,
+        lens_radius,
+        aperture: v.aperture_shape.clone(),
+        focal_plane,
+        time0: v.shutter_open,
+        time1: v.shutter_close,
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn aperture_test() {
+    let mut rng = crate::rng::scene_rng(0);
+    // Samples of a square rotated by 45 degrees stay within |x| + |y| <= 1 and fill it.
+    let square = Aperture::Polygon { blades: 4, rotation_deg: 0.0 };
+    let points = (0..1000).map(|_| square.sample(&mut rng)).collect::<Vec<_>>();
+    assert!(points.iter().all(|p| p.0.abs() + p.1.abs() <= 1.0 + 1e-9));
+    assert!(points.iter().any(|p| p.0 > 0.5) && points.iter().any(|p| p.1 < -0.5));
+
+    // A mask with only the top right pixel open.
+    let mask = Aperture::Mask(Arc::new(ApertureMask::new(2, 2, &[0.0, 1.0, 0.0, 0.0]).unwrap()));
+    assert!((0..100).all(|_| { let p = mask.sample(&mut rng); p.0 >= 0.0 && p.1 >= 0.0 }));
+    assert!(ApertureMask::new(1, 1, &[0.0]).is_err());
+}
+
+#[test]
+fn focus_test() {
+    use crate::sphere::Sphere;
+    use crate::lambertian::gray;
+    let world = Sphere { center: Vec3(0.0, 0.0, -7.0), radius: 1.0, material: Box::new(gray()) };
+    let v = View {
+        look_from: Vec3(0.0, 0.0, 0.0), look_at: Vec3(0.0, 0.0, -1.0), aperture: 1.0, autofocus: true,
+        ..View::default()
+    };
+    let mut rng = crate::rng::scene_rng(0);
+    // All rays through the image center meet at the front of the sphere.
+    let cam = build_camera(&v, 1.0, &world);
+    for _ in 0..10 {
+        let r = cam.get_ray(0.5, 0.5, &mut rng);
+        assert!((r.orig + r.dir - Vec3(0.0, 0.0, -6.0)).near_zero());
+    }
+
+    // Tilted, the rays through the top of the image meet farther away than
+    // those through the bottom.
+    let cam = build_camera(&View { tilt_deg: 30.0, ..v.clone() }, 1.0, &world);
+    let meet = |t: f64, rng: &mut Rng| { let r = cam.get_ray(0.5, t, rng); r.orig + r.dir };
+    assert!((meet(0.5, &mut rng) - Vec3(0.0, 0.0, -6.0)).near_zero());
+    let (top, bottom) = (meet(0.9, &mut rng), meet(0.1, &mut rng));
+    assert!((meet(0.9, &mut rng) - top).near_zero());
+    assert!(top.2 < -6.0 && bottom.2 > -6.0, "{:?} {:?}", top, bottom);
+
+    // Shifting moves the image without turning the camera.
+    let cam = build_camera(&View { shift: Vec2(0.0, 0.5), autofocus: false, aperture: 0.0, ..v }, 1.0, &world);
+    let r = cam.get_ray(0.5, 0.0, &mut rng);
+    assert!(r.dir.1.abs() < 1e-9 && r.dir.2 < 0.0);
+}
@@ -0,0 +1,432 @@
+// Command-line interface of the renderer.
+use std::sync::Arc;
+
+use crate::vec3::*;
+use crate::scene::*;
+use crate::scenes;
+use crate::output::Format;
+use crate::denoise::Denoise;
+use crate::aov::Aov;
+use crate::tonemap::*;
+use crate::lights::LightSampling;
+use crate::environment::EnvironmentMap;
+use crate::sky::*;
+use crate::camera::*;
+
+pub const USAGE: &str = "\
+Usage: in_one_weekend [OPTIONS] [SCENE]
+
+Renders SCENE, which is either a scene file or the name of a built-in scene
+(default: random). Options override the values set by the scene.
+
+Options:
+  -w, --width <N>          Image width in pixels
+      --height <N>         Image height in pixels, changes the aspect ratio
+      --aspect-ratio <X>   Width divided by height
+  -s, --spp <N>            Samples per pixel, the most samples with --adaptive
+      --adaptive <ERR>     Stop sampling pixels once the estimated error of
+                           their brightness (0 to 1) is below ERR
+      --min-spp <N>        Fewest samples per pixel with --adaptive
+      --heat-map <PATH>    Write an image of the samples taken per pixel
+      --aov <NAMES>        Also write auxiliary images, comma separated:
+                           albedo, normal, depth, position and id (object
+                           number). They are written as EXR next to the
+                           output, e.g. out.depth.exr for out.png
+  -d, --depth <N>          Maximum number of ray bounces
+      --light-sampling <NAME>
+                           How to choose the light to sample: uniform, power
+                           or tree (near and bright lights, the default)
+      --denoise <NAME>     Denoise the images: none or bilateral (a joint
+                           bilateral filter guided by albedo and normals)
+      --exposure <STOPS>   Brighten (or darken if negative) the images
+      --tonemap <NAME>     Map bright colors to displayable ones: clamp,
+                           reinhard or aces (filmic)
+      --dither             Dither 8 and 16-bit images against banding
+      --background <R,G,B> Color of rays that hit nothing
+      --environment <PATH> Light the scene with an equirectangular HDR or
+                           EXR image instead of the background color
+      --sky <ELEV,AZIM>    Light the scene with a clear sky and the sun at
+                           ELEV degrees above the horizon and AZIM degrees
+                           from -z towards +x
+      --look-from <X,Y,Z>  Camera position
+      --look-at <X,Y,Z>    Point the camera looks at
+      --vfov <DEG>         Vertical field of view in degrees
+      --aperture <X>       Lens aperture, 0 for a pinhole camera
+      --focus-dist <X>     Distance to the plane in focus
+      --autofocus          Focus on what is seen in the image center
+      --blades <N>         Polygonal aperture with N blades instead of a circle
+      --aperture-mask <PATH>
+                           Aperture shaped like the bright parts of an image
+      --tilt <DEG>         Tilt the plane in focus, receding towards the top
+                           of the image for positive angles
+      --shift <X,Y>        Shift the image by fractions of its size
+      --shutter <OPEN,CLOSE>
+                           Blur moving objects over the times between OPEN
+                           and CLOSE, their motion takes from 0 to 1
+      --seed <N>           Seed of the random numbers (default: 0), renders
+                           with the same seed are identical
+  -j, --threads <N>        Number of render threads (default: one per core)
+  -o, --output <PATH>      Output file (default: out.gif)
+  -f, --format <FORMAT>    Output format (default: from the output extension):
+                             gif: animation with a 256 color palette
+                             png, png16: 8 or 16 bits per channel
+                             ppm, ppm-ascii: binary or plain text pixmap
+                             hdr, exr: linear floating-point color
+                           Animations in still formats are written one file per frame
+      --save-temps <N>     Write an intermediate image every N samples, 0 to disable
+      --temp-path <PATH>   Path of the intermediate image (default: tmp.png)
+      --checkpoint <PATH>  Save the samples taken so far to PATH every
+                           --save-temps samples and at the end
+      --resume             Continue from the checkpoint if it exists, also
+                           to add samples to a finished render with a
+                           larger --spp. The scene must be the same
+      --no-progress        Do not show the progress bar
+      --preview <PORT>     Instead of writing images, show the scene at
+                           http://127.0.0.1:PORT/ while it renders. The
+                           view can be changed with the keyboard there
+      --list-scenes        List the built-in scenes and exit
+  -h, --help               Print this help and exit
+";
+
+#[derive(Default, Debug)]
+pub struct Options {
+    // Scene file or name of a built-in scene.
+    pub scene: Option<String>,
+    pub image_width: Option<usize>,
+    pub image_height: Option<usize>,
+    pub aspect_ratio: Option<f64>,
+    pub samples_per_pixel: Option<usize>,
+    pub adaptive_threshold: Option<f64>,
+    pub min_samples: Option<usize>,
+    pub heat_map: Option<String>,
+    pub aovs: Option<Vec<Aov>>,
+    pub max_depth: Option<i32>,
+    pub light_sampling: Option<LightSampling>,
+    pub background: Option<Vec3>,
+    pub environment: Option<String>,
+    pub sky: Option<(f64, f64)>,
+    pub denoise: Option<Denoise>,
+    pub exposure: Option<f64>,
+    pub operator: Option<Operator>,
+    pub dither: bool,
+    pub look_from: Option<Vec3>,
+    pub look_at: Option<Vec3>,
+    pub vfov_deg: Option<f64>,
+    pub aperture: Option<f64>,
+    pub dist_to_focus: Option<f64>,
+    pub autofocus: bool,
+    pub blades: Option<usize>,
+    pub aperture_mask: Option<String>,
+    pub tilt_deg: Option<f64>,
+    pub shift: Option<Vec2>,
+    pub shutter: Option<(f64, f64)>,
+    pub seed: Option<u64>,
+    pub threads: Option<usize>,
+    pub output: Option<String>,
+    pub format: Option<Format>,
+    pub save_temps: Option<usize>,
+    pub temp_path: Option<String>,
+    pub checkpoint: Option<String>,
+    pub resume: bool,
+    pub no_progress: bool,
+    pub preview: Option<u16>,
+    pub list_scenes: bool,
+    pub help: bool,
+}
+
+impl Options {
+    // Parses the command-line arguments, not including the program name.
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut o = Options::default();
+        while let Some(arg) = args.next() {
+            // Both `--opt value` and `--opt=value` are accepted.
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((n, v)) if n.starts_with("--") => (n.to_string(), Some(v.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || -> Result<String, String> {
+                inline_value.clone().or_else(|| args.next())
+                    .ok_or_else(|| format!("'{}' requires a value", name))
+            };
+            match name.as_str() {
+                "-w" | "--width" => o.image_width = Some(positive(&name, &value()?)?),
+                "--height" => o.image_height = Some(positive(&name, &value()?)?),
+                "--aspect-ratio" => o.aspect_ratio = Some(positive_f64(&name, &value()?)?),
+                "-s" | "--spp" => o.samples_per_pixel = Some(positive(&name, &value()?)?),
+                "--adaptive" => o.adaptive_threshold = Some(positive_f64(&name, &value()?)?),
+                "--min-spp" => o.min_samples = Some(positive(&name, &value()?)?),
+                "--heat-map" => o.heat_map = Some(value()?),
+                "--aov" => {
+                    o.aovs = Some(value()?.split(',').map(|v| {
+                        Aov::parse(v).ok_or_else(|| format!("unknown AOV '{}'", v))
+                    }).collect::<Result<_, _>>()?);
+                }
+                "-d" | "--depth" => o.max_depth = Some(positive(&name, &value()?)? as i32),
+                "--light-sampling" => {
+                    let v = value()?;
+                    o.light_sampling = Some(LightSampling::parse(&v).ok_or_else(|| format!("unknown light sampling '{}'", v))?);
+                }
+                "--denoise" => {
+                    let v = value()?;
+                    o.denoise = Some(Denoise::parse(&v).ok_or_else(|| format!("unknown denoiser '{}'", v))?);
+                }
+                "--exposure" => o.exposure = Some(number(&name, &value()?)?),
+                "--tonemap" => {
+                    let v = value()?;
+                    o.operator = Some(Operator::parse(&v).ok_or_else(|| format!("unknown tone mapping '{}'", v))?);
+                }
+                "--dither" => o.dither = true,
+                "--background" => o.background = Some(vec3(&name, &value()?)?),
+                "--environment" => o.environment = Some(value()?),
+                "--sky" => {
+                    let v = value()?;
+                    let (elevation, azimuth) = match v.split_once(',') {
+                        Some((e, a)) => (number(&name, e)?, number(&name, a)?),
+                        None => return Err(format!("'{}' expects ELEV,AZIM, got '{}'", name, v)),
+                    };
+                    if !(0.0..=90.0).contains(&elevation) {
+                        return Err(format!("'{}': the elevation must be between 0 and 90 degrees, got {}", name, elevation));
+                    }
+                    o.sky = Some((elevation, azimuth));
+                }
+                "--look-from" => o.look_from = Some(vec3(&name, &value()?)?),
+                "--look-at" => o.look_at = Some(vec3(&name, &value()?)?),
+                "--vfov" => {
+                    let x = number(&name, &value()?)?;
+                    if x <= 0.0 || x >= 180.0 {
+                        return Err(format!("'{}' must be between 0 and 180 degrees, got {}", name, x));
+                    }
+                    o.vfov_deg = Some(x);
+                }
+                "--aperture" => {
+                    let x = number(&name, &value()?)?;
+                    if x < 0.0 {
+                        return Err(format!("'{}' must not be negative, got {}", name, x));
+                    }
+                    o.aperture = Some(x);
+                }
+                "--focus-dist" => o.dist_to_focus = Some(positive_f64(&name, &value()?)?),
+                "--autofocus" => o.autofocus = true,
+                "--blades" => {
+                    let x = positive(&name, &value()?)?;
+                    if x < 3 {
+                        return Err(format!("'{}' must be at least 3, got {}", name, x));
+                    }
+                    o.blades = Some(x);
+                }
+                "--aperture-mask" => o.aperture_mask = Some(value()?),
+                "--tilt" => {
+                    let x = number(&name, &value()?)?;
+                    if x.abs() >= 90.0 {
+                        return Err(format!("'{}' must be between -90 and 90 degrees, got {}", name, x));
+                    }
+                    o.tilt_deg = Some(x);
+                }
+                "--shutter" => {
+                    let v = value()?;
+                    let (open, close) = match v.split_once(',') {
+                        Some((a, b)) => (number(&name, a)?, number(&name, b)?),
+                        None => return Err(format!("'{}' expects OPEN,CLOSE, got '{}'", name, v)),
+                    };
+                    if close < open {
+                        return Err(format!("'{}': the shutter must not close before it opens, got '{}'", name, v));
+                    }
+                    o.shutter = Some((open, close));
+                }
+                "--shift" => {
+                    let v = value()?;
+                    o.shift = Some(match v.split_once(',') {
+                        Some((x, y)) => Vec2(number(&name, x)?, number(&name, y)?),
+                        None => return Err(format!("'{}' expects X,Y, got '{}'", name, v)),
+                    });
+                }
+                "--seed" => {
+                    let v = value()?;
+                    o.seed = Some(v.parse().map_err(|_| {
+                        format!("'{}' expects a non-negative integer, got '{}'", name, v)
+                    })?);
+                }
+                "-j" | "--threads" => o.threads = Some(positive(&name, &value()?)?),
+                "-o" | "--output" => o.output = Some(value()?),
+                "-f" | "--format" => {
+                    let v = value()?;
+                    o.format = Some(Format::parse(&v).ok_or_else(|| format!("unknown format '{}'", v))?);
+                }
+                "--save-temps" => {
+                    let v = value()?;
+                    o.save_temps = Some(v.parse().map_err(|_| {
+                        format!("'{}' expects a non-negative integer, got '{}'", name, v)
+                    })?);
+                }
+                "--temp-path" => o.temp_path = Some(value()?),
+                "--checkpoint" => o.checkpoint = Some(value()?),
+                "--resume" => o.resume = true,
+                "--no-progress" => o.no_progress = true,
+                "--preview" => {
+                    let v = value()?;
+                    o.preview = Some(v.parse().map_err(|_| format!("'{}' expects a port number, got '{}'", name, v))?);
+                }
+                "--list-scenes" => o.list_scenes = true,
+                "-h" | "--help" => o.help = true,
+                _ if name.starts_with('-') && name.len() > 1 => {
+                    return Err(format!("unknown option '{}'", name));
+                }
+                _ => {
+                    if o.scene.is_some() {
+                        return Err(format!("unexpected argument '{}'", name));
+                    }
+                    o.scene = Some(arg);
+                }
+            }
+        }
+        if o.blades.is_some() && o.aperture_mask.is_some() {
+            return Err("'--blades' and '--aperture-mask' can not be combined".to_string());
+        }
+        if o.resume && o.checkpoint.is_none() {
+            return Err("'--resume' requires '--checkpoint'".to_string());
+        }
+        Ok(o)
+    }
+
+    pub fn output(&self) -> &str {
+        self.output.as_deref().unwrap_or("out.gif")
+    }
+
+    // The format given with `--format`, otherwise guessed from the output extension.
+    pub fn format(&self) -> Result<Format, String> {
+        if let Some(f) = self.format {
+            return Ok(f);
+        }
+        Format::from_path(self.output()).ok_or_else(|| {
+            format!("can not guess the format of '{}', use --format", self.output())
+        })
+    }
+
+    // Overrides the scene defaults with the values given on the command line.
+    pub fn apply(&self, s: &mut Scene, v: &mut View) -> Result<(), String> {
+        if let Some(x) = self.aspect_ratio { s.aspect_ratio = x; }
+        if let Some(x) = self.image_width { s.image_width = x; }
+        if let Some(h) = self.image_height {
+            // The height only changes the aspect ratio, the width stays as set.
+            s.aspect_ratio = s.image_width as f64 / h as f64;
+        }
+        if let Some(x) = self.samples_per_pixel { s.samples_per_pixel = x; }
+        if let Some(x) = self.adaptive_threshold { s.adaptive_threshold = x; }
+        if let Some(x) = self.min_samples { s.min_samples = x; }
+        if let Some(x) = self.max_depth { s.max_depth = x; }
+        if let Some(x) = self.light_sampling { s.light_sampling = x; }
+        if let Some(x) = self.background { s.background = x; }
+        if let Some(x) = &self.environment { s.environment = Some(Box::new(EnvironmentMap::load(x, 1.0)?)); }
+        if let Some((e, a)) = self.sky { s.environment = Some(Box::new(Sky::new(sun_direction(e, a), 3.0, 0.3, true))); }
+        if let Some(x) = self.denoise { s.denoise = x; }
+        if let Some(x) = &self.aovs { s.aovs = x.clone(); }
+        if let Some(x) = self.exposure { s.tone_map.exposure = x; }
+        if let Some(x) = self.operator { s.tone_map.operator = x; }
+        if self.dither { s.tone_map.dither = true; }
+        if let Some(x) = self.save_temps { s.save_temps = x; }
+        if let Some(x) = &self.temp_path { s.temp_path = x.clone(); }
+        if let Some(x) = self.seed { s.seed = x; }
+        if let Some(x) = self.look_from { v.look_from = x; }
+        if let Some(x) = self.look_at { v.look_at = x; }
+        if let Some(x) = self.vfov_deg { v.vfov_deg = x; }
+        if let Some(x) = self.aperture { v.aperture = x; }
+        if let Some(x) = self.dist_to_focus { v.dist_to_focus = x; }
+        if self.autofocus { v.autofocus = true; }
+        if let Some(blades) = self.blades { v.aperture_shape = Aperture::Polygon { blades, rotation_deg: 0.0 }; }
+        if let Some(x) = &self.aperture_mask { v.aperture_shape = Aperture::Mask(Arc::new(ApertureMask::load(x)?)); }
+        if let Some(x) = self.tilt_deg { v.tilt_deg = x; }
+        if let Some(x) = self.shift { v.shift = x; }
+        if let Some((open, close)) = self.shutter { (v.shutter_open, v.shutter_close) = (open, close); }
+
+        if s.image_height() == 0 {
+            return Err(format!("image height is 0 with width {} and aspect ratio {}", s.image_width, s.aspect_ratio));
+        }
+        if (v.look_from - v.look_at).near_zero() {
+            return Err("the camera looks at its own position".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub fn scene_list() -> String {
+    scenes::NAMES.join("\n")
+}
+
+fn number(name: &str, s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(x),
+        _ => Err(format!("'{}' expects a number, got '{}'", name, s)),
+    }
+}
+
+fn positive_f64(name: &str, s: &str) -> Result<f64, String> {
+    let x = number(name, s)?;
+    if x <= 0.0 {
+        return Err(format!("'{}' must be positive, got {}", name, s));
+    }
+    Ok(x)
+}
+
+fn positive(name: &str, s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err(format!("'{}' must be positive, got 0", name)),
+        Ok(x) if x <= i32::MAX as usize => Ok(x),
+        _ => Err(format!("'{}' expects a positive integer, got '{}'", name, s)),
+    }
+}
+
+fn vec3(name: &str, s: &str) -> Result<Vec3, String> {
+    let xs = s.split(',').map(|x| number(name, x)).collect::<Result<Vec<f64>, String>>()?;
+    match xs[..] {
+        [x, y, z] => Ok(Vec3(x, y, z)),
+        _ => Err(format!("'{}' expects X,Y,Z, got '{}'", name, s)),
+    }
+}
+
+#[cfg(test)]
+fn parse_args(args: &[&str]) -> Result<Options, String> {
+    Options::parse(args.iter().map(|s| s.to_string()))
+}
+
+#[test]
+fn parse_test() {
+    let o = parse_args(&["-w", "100", "--spp=8", "cornell_box", "--look-from", "1,2,3", "-o", "x.png", "--seed", "7"]).unwrap();
+    assert_eq!(o.scene.as_deref(), Some("cornell_box"));
+    assert_eq!(o.image_width, Some(100));
+    assert_eq!(o.samples_per_pixel, Some(8));
+    assert_eq!(o.seed, Some(7));
+    assert_eq!(o.aovs, None);
+    assert_eq!(parse_args(&["--aov", "depth,id"]).unwrap().aovs, Some(vec![Aov::Depth, Aov::Id]));
+    assert_eq!(o.look_from, Some(Vec3(1.0, 2.0, 3.0)));
+    assert_eq!(o.format(), Ok(Format::Png));
+
+    let mut s = Scene::default();
+    let mut v = View::default();
+    parse_args(&["--width", "200", "--height", "100"]).unwrap().apply(&mut s, &mut v).unwrap();
+    assert_eq!((s.image_width, s.image_height()), (200, 100));
+}
+
+#[test]
+fn validation_test() {
+    assert_eq!(parse_args(&["--width", "0"]).unwrap_err(), "'--width' must be positive, got 0");
+    assert_eq!(parse_args(&["--aperture", "-1"]).unwrap_err(), "'--aperture' must not be negative, got -1");
+    assert_eq!(parse_args(&["--spp"]).unwrap_err(), "'--spp' requires a value");
+    assert_eq!(parse_args(&["--vfov", "180"]).unwrap_err(), "'--vfov' must be between 0 and 180 degrees, got 180");
+    assert_eq!(parse_args(&["--look-at", "1,2"]).unwrap_err(), "'--look-at' expects X,Y,Z, got '1,2'");
+    assert_eq!(parse_args(&["--frobnicate"]).unwrap_err(), "unknown option '--frobnicate'");
+    assert_eq!(parse_args(&["--aov", "albedo,beauty"]).unwrap_err(), "unknown AOV 'beauty'");
+    assert_eq!(parse_args(&["--resume"]).unwrap_err(), "'--resume' requires '--checkpoint'");
+    assert_eq!(parse_args(&["--tonemap", "filmic"]).unwrap_err(), "unknown tone mapping 'filmic'");
+    assert_eq!(parse_args(&["--light-sampling", "random"]).unwrap_err(), "unknown light sampling 'random'");
+    assert_eq!(parse_args(&["--blades", "2"]).unwrap_err(), "'--blades' must be at least 3, got 2");
+    assert_eq!(parse_args(&["--shutter", "1,0"]).unwrap_err(), "'--shutter': the shutter must not close before it opens, got '1,0'");
+    assert_eq!(parse_args(&["--shift", "0.1"]).unwrap_err(), "'--shift' expects X,Y, got '0.1'");
+    assert_eq!(parse_args(&["--tilt", "90"]).unwrap_err(), "'--tilt' must be between -90 and 90 degrees, got 90");
+    assert_eq!(parse_args(&["--sky", "30"]).unwrap_err(), "'--sky' expects ELEV,AZIM, got '30'");
+    assert_eq!(parse_args(&["--sky", "-10,0"]).unwrap_err(), "'--sky': the elevation must be between 0 and 90 degrees, got -10");
+    assert!(parse_args(&["-o", "out.bmp"]).unwrap().format().is_err());
+
+    let mut s = Scene::default();
+    let mut v = View::default();
+    assert!(parse_args(&["--width", "1", "--aspect-ratio", "3"]).unwrap().apply(&mut s, &mut v).is_err());
+}
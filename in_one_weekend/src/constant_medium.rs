@@ -0,0 +1,106 @@
+use crate::aabb::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+use crate::hit::*;
+use crate::rng;
+
+// Volume of constant density filling a closed boundary, e.g. smoke or fog.
+// The boundary does not have to be convex, its normals have to point outwards.
+pub struct ConstantMedium {
+	boundary: Box<dyn Hittable>,
+	neg_inv_density: f64,
+	phase_function: Box<dyn Material>,
+}
+
+// Offset used to step over the boundary after crossing it.
+const EPS: f64 = 0.0001;
+
+impl ConstantMedium {
+	pub fn new(boundary: Box<dyn Hittable>, density: f64, phase_function: Box<dyn Material>) -> ConstantMedium {
+		ConstantMedium { boundary, neg_inv_density: -1.0 / density, phase_function }
+	}
+}
+
+impl Hittable for ConstantMedium {
+	// A hit is a scattering event inside the volume. The free-flight distance
+	// is sampled once and used up by the segments of the ray inside the boundary.
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+		let ray_length = r.dir.length();
+		// `hit` has no generator of its own. The ray is random already, so the
+		// free-flight distance is derived from it to keep renders reproducible.
+		let bits = [r.orig.0, r.orig.1, r.orig.2, r.dir.0, r.dir.1, r.dir.2, t_min].map(f64::to_bits);
+		let mut hit_distance = self.neg_inv_density * (1.0 - rng::unit_f64(rng::hash(&bits))).ln();
+		let mut t = t_min;
+		while t < t_max {
+			let h = self.boundary.hit(r, t, f64::INFINITY)?;
+			// A back face means the ray starts inside the volume.
+			let (enter, exit) = if h.front_face {
+				(h.t, self.boundary.hit(r, h.t + EPS, f64::INFINITY)?.t)
+			} else {
+				(t, h.t)
+			};
+			if enter >= t_max {
+				return None;
+			}
+			let inside = (exit.min(t_max) - enter) * ray_length;
+			if hit_distance < inside {
+				let t = enter + hit_distance / ray_length;
+				return Some(HitRecord {
+					p: r.at(t),
+					// Arbitrary, not used by the phase function.
+					normal: Vec3(1.0, 0.0, 0.0),
+					material: self.phase_function.as_ref(),
+					t,
+					front_face: true,
+					object_id: 0,
+					coord: Vec2(0.0, 0.0),
+				});
+			}
+			hit_distance -= inside;
+			t = exit + EPS;
+		}
+		None
+	}
+
+	fn bounding_box(&self) -> Option<AABB> {
+		self.boundary.bounding_box()
+	}
+}
+
+#[test]
+fn free_flight_test() {
+	use crate::box_shape::*;
+	use crate::isotropic::*;
+	use crate::texture::*;
+
+	let white = || Box::new(Isotropic { albedo: Box::new(SolidColor { color: Vec3(1.0, 1.0, 1.0) }) });
+	// Two slabs of thickness 1 along x with a gap between them.
+	let slab = |x: f64| Box::new(BoxShape::new(Vec3(x, -10.0, -10.0), Vec3(x + 1.0, 10.0, 10.0), white()));
+	let list = crate::hit::HittableList { objects: vec![slab(0.0), slab(2.0)] };
+	let medium = ConstantMedium::new(Box::new(list), 1.0, white());
+
+	let n = 10000;
+	let mut in_first = 0;
+	let mut in_second = 0;
+	for i in 0..n {
+		// The free-flight distance is a function of the ray, so every ray is different.
+		let r = Ray { orig: Vec3(-1.0, i as f64 * 0.001, 0.0), dir: Vec3(2.0, 0.0, 0.0), time: 0.0 };
+		if let Some(hr) = medium.hit(&r, 0.001, f64::INFINITY) {
+			assert!(hr.p.0 >= 0.0 && hr.p.0 <= 3.0 && !(1.0..2.0).contains(&hr.p.0), "{:?}", hr.p);
+			if hr.p.0 < 1.0 { in_first += 1 } else { in_second += 1 }
+		}
+	}
+	// Transmittance through a unit of optical depth is 1/e.
+	let t = (-1.0f64).exp();
+	assert!((in_first as f64 / n as f64 - (1.0 - t)).abs() < 0.02);
+	assert!((in_second as f64 / n as f64 - t * (1.0 - t)).abs() < 0.02);
+
+	// Rays starting inside the volume scatter before leaving it.
+	let r = Ray { orig: Vec3(0.5, 0.0, 0.0), dir: Vec3(0.0, 0.0, 1.0), time: 0.0 };
+	let medium = ConstantMedium::new(slab(0.0), 1e6, white());
+	assert!(medium.hit(&r, 0.001, f64::INFINITY).unwrap().t < 0.01);
+	// Nothing is hit before the ray reaches the volume.
+	let r = Ray { orig: Vec3(-1.0, 0.0, 0.0), dir: Vec3(2.0, 0.0, 0.0), time: 0.0 };
+	assert!(medium.hit(&r, 0.001, 0.49).is_none());
+}
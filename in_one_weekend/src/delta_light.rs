@@ -0,0 +1,143 @@
+// Idealised lights of no size: point lights, spotlights and directional
+// lights. No ray can hit them, they only light the surfaces they are sampled
+// from with shadow rays.
+use std::f64::consts::PI;
+
+use crate::vec3::*;
+
+pub trait DeltaLight: Sync {
+	// The direction from `p` towards the light (a unit vector), the distance
+	// to it, infinite for lights infinitely far away, and the light arriving
+	// at `p` if nothing is in between.
+	fn illuminate(&self, p: &Point3) -> (Vec3, f64, Color);
+	// Position of the light, None if it is infinitely far away.
+	fn position(&self) -> Option<Point3>;
+	// Power emitted, so that bright lights are sampled more often.
+	fn power(&self) -> f64;
+}
+
+// Shines `intensity` in all directions from `position`.
+pub struct PointLight {
+	pub position: Point3,
+	pub intensity: Color,
+}
+
+impl DeltaLight for PointLight {
+	fn illuminate(&self, p: &Point3) -> (Vec3, f64, Color) {
+		let d = self.position - *p;
+		let dist = d.length();
+		(d / dist, dist, self.intensity / (dist * dist))
+	}
+
+	fn position(&self) -> Option<Point3> {
+		Some(self.position)
+	}
+
+	fn power(&self) -> f64 {
+		4.0 * PI * luminance(self.intensity)
+	}
+}
+
+// Shines `intensity` from `position` into a cone around `dir`. The light
+// fades out smoothly towards the edge of the cone.
+pub struct SpotLight {
+	position: Point3,
+	dir: Vec3,
+	intensity: Color,
+	// Cosines of the angles from `dir` where the light starts fading and
+	// where it ends.
+	cos_falloff: f64,
+	cos_max: f64,
+}
+
+impl SpotLight {
+	// A cone `angle_deg` degrees wide from its axis to its edge, fading over
+	// the outer `falloff_deg` degrees.
+	pub fn new(position: Point3, dir: Vec3, intensity: Color, angle_deg: f64, falloff_deg: f64) -> SpotLight {
+		SpotLight {
+			position,
+			dir: unit_vector(dir),
+			intensity,
+			cos_falloff: (angle_deg - falloff_deg).max(0.0).to_radians().cos(),
+			cos_max: angle_deg.to_radians().cos(),
+		}
+	}
+
+	// Fraction of the intensity sent at an angle with cosine `cos` from the axis.
+	fn falloff(&self, cos: f64) -> f64 {
+		if cos >= self.cos_falloff {
+			return 1.0;
+		}
+		if cos <= self.cos_max {
+			return 0.0;
+		}
+		let t = (cos - self.cos_max) / (self.cos_falloff - self.cos_max);
+		t * t * (3.0 - 2.0 * t)
+	}
+}
+
+impl DeltaLight for SpotLight {
+	fn illuminate(&self, p: &Point3) -> (Vec3, f64, Color) {
+		let d = self.position - *p;
+		let dist = d.length();
+		let dir = d / dist;
+		(dir, dist, self.falloff(dot(-1.0 * dir, self.dir)) * self.intensity / (dist * dist))
+	}
+
+	fn position(&self) -> Option<Point3> {
+		Some(self.position)
+	}
+
+	// The fading edge counted as half as bright.
+	fn power(&self) -> f64 {
+		2.0 * PI * (1.0 - (self.cos_falloff + self.cos_max) / 2.0) * luminance(self.intensity)
+	}
+}
+
+// Parallel light travelling along `dir`, like sunlight, with `irradiance` on
+// surfaces facing it.
+pub struct DirectionalLight {
+	dir: Vec3,
+	irradiance: Color,
+}
+
+impl DirectionalLight {
+	pub fn new(dir: Vec3, irradiance: Color) -> DirectionalLight {
+		DirectionalLight { dir: unit_vector(dir), irradiance }
+	}
+}
+
+impl DeltaLight for DirectionalLight {
+	fn illuminate(&self, _p: &Point3) -> (Vec3, f64, Color) {
+		(-1.0 * self.dir, f64::INFINITY, self.irradiance)
+	}
+
+	fn position(&self) -> Option<Point3> {
+		None
+	}
+
+	// Not needed, the light is chosen like the environment.
+	fn power(&self) -> f64 {
+		0.0
+	}
+}
+
+#[test]
+fn delta_light_test() {
+	let white = Vec3(1.0, 1.0, 1.0);
+	let point = PointLight { position: Vec3(0.0, 2.0, 0.0), intensity: white };
+	let (dir, dist, color) = point.illuminate(&Vec3(0.0, 0.0, 0.0));
+	assert!((dir - Vec3(0.0, 1.0, 0.0)).near_zero() && dist == 2.0 && color == Vec3(0.25, 0.25, 0.25));
+
+	// Full brightness inside the inner cone, fading to nothing at the edge.
+	let spot = SpotLight::new(Vec3(0.0, 1.0, 0.0), Vec3(0.0, -1.0, 0.0), white, 45.0, 15.0);
+	let at = |x: f64| luminance(spot.illuminate(&Vec3(x, 0.0, 0.0)).2) * (1.0 + x * x);
+	assert!((at(0.0) - 1.0).abs() < 1e-12 && (at(0.5) - 1.0).abs() < 1e-12);
+	assert!(at(0.7) > 0.0 && at(0.7) < 1.0);
+	assert_eq!(at(1.01), 0.0);
+	assert!(spot.power() > 0.0 && spot.power() < point.power() / 2.0);
+
+	let sun = DirectionalLight::new(Vec3(0.0, -2.0, 0.0), white);
+	let (dir, dist, color) = sun.illuminate(&Vec3(5.0, 0.0, 3.0));
+	assert!((dir - Vec3(0.0, 1.0, 0.0)).near_zero() && dist.is_infinite() && color == white);
+}
@@ -0,0 +1,186 @@
+// Denoising of rendered frames.
+//
+// Denoisers are guided by features of the surfaces seen through every pixel,
+// which are much less noisy than the rendered colors and show where the
+// edges of objects and textures are.
+use rayon::prelude::*;
+
+use crate::vec3::*;
+use crate::output::*;
+use crate::aov::Features;
+
+pub trait Denoiser: Sync {
+	fn denoise(&self, film: &Film, features: &Features) -> Film;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Denoise {
+	#[default]
+	None,
+	// Joint bilateral filter with the default parameters.
+	Bilateral,
+}
+
+impl Denoise {
+	pub const NAMES: [&'static str; 2] = ["none", "bilateral"];
+
+	pub fn parse(s: &str) -> Option<Denoise> {
+		match s {
+			"none" => Some(Denoise::None),
+			"bilateral" => Some(Denoise::Bilateral),
+			_ => None,
+		}
+	}
+
+	pub fn denoiser(self) -> Option<Box<dyn Denoiser>> {
+		match self {
+			Denoise::None => None,
+			Denoise::Bilateral => Some(Box::new(JointBilateral::default())),
+		}
+	}
+}
+
+// Averages every pixel with its neighbors, weighted by how close they are and
+// how similar their colors, albedos and normals are.
+//
+// The filter works on the lighting only: colors are divided by the albedo
+// before filtering and multiplied afterwards, so textures stay sharp. Single
+// very bright pixels (fireflies) are removed by a 3x3 median before the
+// first pass.
+pub struct JointBilateral {
+	// Pixels up to `radius` away in both directions are averaged.
+	pub radius: usize,
+	pub sigma_spatial: f64,
+	// Differences of gamma-corrected lighting.
+	pub sigma_color: f64,
+	pub sigma_albedo: f64,
+	pub sigma_normal: f64,
+}
+
+impl Default for JointBilateral {
+	fn default() -> JointBilateral {
+		JointBilateral { radius: 6, sigma_spatial: 3.0, sigma_color: 0.25, sigma_albedo: 0.1, sigma_normal: 0.2 }
+	}
+}
+
+// Albedos below this are treated as black, their colors are not divided.
+const MIN_ALBEDO: f64 = 0.01;
+
+fn demodulate(c: Color, albedo: Color) -> Color {
+	let d = |c: f64, a: f64| { if a > MIN_ALBEDO { c / a } else { c } };
+	Vec3(d(c.0, albedo.0), d(c.1, albedo.1), d(c.2, albedo.2))
+}
+
+fn modulate(c: Color, albedo: Color) -> Color {
+	let m = |c: f64, a: f64| { if a > MIN_ALBEDO { c * a } else { c } };
+	Vec3(m(c.0, albedo.0), m(c.1, albedo.1), m(c.2, albedo.2))
+}
+
+fn gamma(c: Color) -> Color {
+	Vec3(c.0.max(0.0).sqrt(), c.1.max(0.0).sqrt(), c.2.max(0.0).sqrt())
+}
+
+// Channel-wise median of the 3x3 neighborhood.
+fn median3x3(img: &[Color], width: usize, height: usize, i: usize, j: usize) -> Color {
+	let mut rs = vec![];
+	let mut gs = vec![];
+	let mut bs = vec![];
+	for y in j.saturating_sub(1)..(j + 2).min(height) {
+		for x in i.saturating_sub(1)..(i + 2).min(width) {
+			let c = img[y*width + x];
+			rs.push(c.0);
+			gs.push(c.1);
+			bs.push(c.2);
+		}
+	}
+	let median = |v: &mut Vec<f64>| {
+		v.sort_by(|a, b| a.total_cmp(b));
+		v[v.len() / 2]
+	};
+	Vec3(median(&mut rs), median(&mut gs), median(&mut bs))
+}
+
+impl JointBilateral {
+	// One pass of the filter over `lighting`. Colors are compared to `center`,
+	// or not at all if it is None.
+	fn filter(&self, lighting: &[Color], center: Option<&[Color]>, width: usize, height: usize, features: &Features) -> Vec<Color> {
+		let weight = |d_squared: f64, sigma: f64| { (-d_squared / (2.0 * sigma * sigma)).exp() };
+		let r = self.radius as isize;
+
+		(0..width*height).into_par_iter().map(|p| {
+			let (i, j) = (p % width, p / width);
+			let mut sum = Vec3(0.0, 0.0, 0.0);
+			let mut total = 0.0;
+			for dy in -r..=r {
+				for dx in -r..=r {
+					let (x, y) = (i as isize + dx, j as isize + dy);
+					if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+						continue;
+					}
+					let q = y as usize * width + x as usize;
+					let mut w = weight((dx*dx + dy*dy) as f64, self.sigma_spatial)
+						* weight((features.albedo[q] - features.albedo[p]).length_squared(), self.sigma_albedo)
+						* weight((features.normal[q] - features.normal[p]).length_squared(), self.sigma_normal);
+					if let Some(center) = center {
+						w *= weight((lighting[q] - center[p]).length_squared(), self.sigma_color);
+					}
+					sum = sum + w * lighting[q];
+					total += w;
+				}
+			}
+			if total > 0.0 { sum / total } else { lighting[p] }
+		}).collect()
+	}
+}
+
+impl Denoiser for JointBilateral {
+	// The first pass only uses the features and gives an estimate of the
+	// lighting around every pixel that is free of fireflies. The second pass
+	// compares colors to it, which keeps shadow edges the features do not show.
+	fn denoise(&self, film: &Film, features: &Features) -> Film {
+		let (width, height) = (film.width, film.height);
+		let lighting: Vec<Color> = film.pixels.iter().zip(&features.albedo)
+			.map(|(c, a)| gamma(demodulate(*c, *a))).collect();
+		let median: Vec<Color> = (0..width*height)
+			.map(|p| median3x3(&lighting, width, height, p % width, p / width)).collect();
+		let guide = self.filter(&median, None, width, height, features);
+		let filtered = self.filter(&lighting, Some(&guide), width, height, features);
+		let pixels = filtered.iter().zip(&features.albedo).map(|(c, a)| modulate(*c * *c, *a)).collect();
+		Film { width, height, pixels }
+	}
+}
+
+#[test]
+fn bilateral_test() {
+	// Noisy flat lighting on two differently colored halves, with a firefly.
+	let (width, height) = (8, 4);
+	let albedo: Vec<Color> = (0..width*height)
+		.map(|p| if p % width < 4 { Vec3(0.8, 0.2, 0.2) } else { Vec3(0.2, 0.2, 0.8) }).collect();
+	let normal = vec![Vec3(0.0, 0.0, 1.0); width*height];
+	let mut pixels: Vec<Color> = albedo.iter().enumerate()
+		.map(|(p, a)| (if p % 2 == 0 { 0.4 } else { 0.6 }) * *a).collect();
+	pixels[10] = 50.0 * albedo[10];
+	let film = Film { width, height, pixels };
+
+	let features = Features {
+		albedo: albedo.clone(),
+		normal,
+		depth: vec![1.0; width*height],
+		position: vec![Vec3(0.0, 0.0, 0.0); width*height],
+		id: vec![1; width*height],
+	};
+	let out = JointBilateral::default().denoise(&film, &features);
+	for (c, a) in out.pixels.iter().zip(&albedo) {
+		// The lighting is smoothed to about 0.5 and the colors of the halves do not mix.
+		assert!((*c - 0.5 * *a).length() < 0.05, "{:?} {:?}", c, a);
+	}
+}
+
+#[test]
+fn parse_test() {
+	for name in Denoise::NAMES {
+		assert!(Denoise::parse(name).is_some());
+	}
+	assert!(Denoise::parse("magic").is_none());
+	assert!(Denoise::None.denoiser().is_none());
+}
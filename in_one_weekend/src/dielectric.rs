@@ -1,8 +1,8 @@
-use rand::random;
+use rand::Rng as _;
 use crate::material::*;
 use crate::vec3::*;
-use crate::ray::*;
 use crate::hit::*;
+use crate::rng::Rng;
 
 pub struct Dielectric {
 	pub ir: f64,  // Index of Refraction
@@ -26,22 +26,23 @@ fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
 }
 
 impl Material for Dielectric {
-	fn scatter(&self, r_in: &Ray, hr: &HitRecord, _lights: &[&dyn Hittable])
-	    -> Option<(Vec3, Color)> {
+	fn sample(&self, hr: &HitRecord, wo: Vec3, rng: &mut Rng) -> Option<BsdfSample> {
 		let attenuation = Vec3(1.0, 1.0, 1.0);
 		let refraction_ratio = if hr.front_face {1.0/self.ir} else {self.ir};
-		let unit_direction = unit_vector(r_in.dir);
+		let unit_direction = -1.0 * unit_vector(wo);
 
 		let mut cos_theta = dot(-1.0 * unit_direction, hr.normal);
 		cos_theta = if cos_theta > 1.0 {1.0} else {cos_theta};
 		let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
 
 		let cannot_refract = refraction_ratio * sin_theta > 1.0;
-		let direction = if cannot_refract || reflectance(cos_theta, refraction_ratio) > random::<f64>() {
+		let direction = if cannot_refract || reflectance(cos_theta, refraction_ratio) > rng.gen::<f64>() {
 			reflect(unit_direction, hr.normal)
 		} else {
 			refract(unit_direction, hr.normal, refraction_ratio)
 		};
-		Some((direction, attenuation))
+		Some(BsdfSample { dir: direction, weight: attenuation, pdf: 0.0 })
 	}
+
+	fn is_specular(&self) -> bool { true }
 }
\ No newline at end of file
@@ -0,0 +1,231 @@
+// Light arriving from infinitely far away, seen by rays that hit nothing.
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+
+use image::codecs::hdr::HdrDecoder;
+use rand::Rng as _;
+
+use crate::aabb::*;
+use crate::hit::*;
+use crate::ray::*;
+use crate::vec3::*;
+use crate::rng::Rng;
+
+pub trait Environment: Sync {
+	// Light arriving from direction `dir`, which need not be normalized.
+	fn color(&self, dir: &Vec3) -> Color;
+
+	// The environment as a light to be sampled, if it varies by direction.
+	fn light(&self) -> Option<&dyn Hittable> { None }
+}
+
+// The same color in all directions.
+impl Environment for Color {
+	fn color(&self, _dir: &Vec3) -> Color {
+		*self
+	}
+}
+
+// An environment from an equirectangular (latitude-longitude) image: the top
+// row is straight up (+y), the bottom row straight down and the center of the
+// image is in direction -z, with +x to its right.
+//
+// As a light the map is sampled proportional to the brightness of its pixels
+// times the solid angle they cover, with a row CDF and a CDF per row.
+pub struct EnvironmentMap {
+	width: usize,
+	height: usize,
+	// Rows top to bottom.
+	pixels: Vec<Color>,
+	// Cumulative weights, `row_cdf[j]` of the rows before row j, `cdf[j*width + i]`
+	// of the pixels before pixel i in row j. Both start at 0 and are not normalized.
+	row_cdf: Vec<f64>,
+	cdf: Vec<f64>,
+}
+
+// Image coordinates in [0, 1]^2 of direction `d`, u to the right, v down.
+fn direction_to_uv(d: &Vec3) -> (f64, f64) {
+	let d = unit_vector(*d);
+	let u = 0.5 + d.0.atan2(-d.2) / (2.0 * PI);
+	let v = d.1.clamp(-1.0, 1.0).acos() / PI;
+	(u, v)
+}
+
+fn uv_to_direction(u: f64, v: f64) -> Vec3 {
+	let (sin_phi, cos_phi) = (2.0 * PI * (u - 0.5)).sin_cos();
+	let (sin_theta, cos_theta) = (PI * v).sin_cos();
+	Vec3(sin_theta * sin_phi, cos_theta, -sin_theta * cos_phi)
+}
+
+// Index of the interval of the cumulative weights `cdf` that `x` in [0, 1)
+// falls into, with `total` the sum of all weights.
+fn find_interval(cdf: &[f64], total: f64, x: f64) -> usize {
+	let target = x * total;
+	// The last interval starting at or before the target.
+	cdf.partition_point(|c| *c <= target).saturating_sub(1)
+}
+
+impl EnvironmentMap {
+	// Rows of `pixels` are top to bottom.
+	pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> EnvironmentMap {
+		let mut row_cdf = Vec::with_capacity(height);
+		let mut cdf = Vec::with_capacity(width * height);
+		let mut row_total = 0.0;
+		for j in 0..height {
+			// Rows near the poles cover a smaller solid angle.
+			let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+			let mut total = 0.0;
+			for i in 0..width {
+				cdf.push(total);
+				total += luminance(pixels[j*width + i]).max(0.0);
+			}
+			row_cdf.push(row_total);
+			row_total += total * sin_theta;
+		}
+		row_cdf.push(row_total);
+		EnvironmentMap { width, height, pixels, row_cdf, cdf }
+	}
+
+	pub fn load(path: &str, scale: f64) -> Result<EnvironmentMap, String> {
+		let error = |e: image::ImageError| { format!("failed to load '{}': {}", path, e) };
+		let (width, height, rgb) = if image::ImageFormat::from_path(path).ok() == Some(image::ImageFormat::Hdr) {
+			// The generic decoder converts HDR images to 8 bits.
+			let file = File::open(path).map_err(|e| format!("failed to load '{}': {}", path, e))?;
+			let decoder = HdrDecoder::new(BufReader::new(file)).map_err(error)?;
+			let meta = decoder.metadata();
+			let rgb = decoder.read_image_hdr().map_err(error)?.iter().map(|p| p.0).collect::<Vec<_>>();
+			(meta.width, meta.height, rgb)
+		} else {
+			let img = image::open(path).map_err(error)?.into_rgb32f();
+			(img.width(), img.height(), img.pixels().map(|p| p.0).collect())
+		};
+		let pixels = rgb.iter().map(|p| scale * Vec3(p[0] as f64, p[1] as f64, p[2] as f64)).collect();
+		Ok(EnvironmentMap::new(width as usize, height as usize, pixels))
+	}
+
+	// Rotates the map around the y axis, counter-clockwise by `deg` degrees when seen from above.
+	pub fn rotate_y(mut self, deg: f64) -> EnvironmentMap {
+		let shift = (deg / 360.0 * self.width as f64).round().rem_euclid(self.width as f64) as usize;
+		for row in self.pixels.chunks_mut(self.width) {
+			row.rotate_left(shift);
+		}
+		EnvironmentMap::new(self.width, self.height, self.pixels)
+	}
+
+	fn total(&self) -> f64 {
+		self.row_cdf[self.height]
+	}
+
+	fn pixel(&self, i: usize, j: usize) -> Color {
+		self.pixels[j*self.width + i]
+	}
+
+	// The pixel that direction `dir` falls into.
+	fn pixel_index(&self, dir: &Vec3) -> (usize, usize) {
+		let (u, v) = direction_to_uv(dir);
+		(((u * self.width as f64) as usize).min(self.width - 1), ((v * self.height as f64) as usize).min(self.height - 1))
+	}
+
+	fn row_total(&self, j: usize) -> f64 {
+		let last = self.cdf[j*self.width + self.width - 1];
+		last + luminance(self.pixel(self.width - 1, j)).max(0.0)
+	}
+}
+
+impl Environment for EnvironmentMap {
+	// The color of the pixel the direction falls into. Interpolating between
+	// pixels would spread bright pixels into directions that are rarely sampled.
+	fn color(&self, dir: &Vec3) -> Color {
+		let (i, j) = self.pixel_index(dir);
+		self.pixel(i, j)
+	}
+
+	fn light(&self) -> Option<&dyn Hittable> {
+		if self.total() > 0.0 { Some(self) } else { None }
+	}
+}
+
+impl Hittable for EnvironmentMap {
+	// The environment is only seen by rays that hit nothing else.
+	fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord<'_>> {
+		None
+	}
+
+	fn bounding_box(&self) -> Option<AABB> {
+		None
+	}
+
+	// Returns a direction, the environment is infinitely far away.
+	fn gen_random_point(&self, _origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		let j = find_interval(&self.row_cdf[..self.height], self.total(), rng.gen::<f64>());
+		let row = &self.cdf[j*self.width..(j + 1)*self.width];
+		let i = find_interval(row, self.row_total(j), rng.gen::<f64>());
+		let u = (i as f64 + rng.gen::<f64>()) / self.width as f64;
+		let v = (j as f64 + rng.gen::<f64>()) / self.height as f64;
+		uv_to_direction(u, v)
+	}
+
+	fn pdf_eval(&self, _origin: &Vec3, dir: &Vec3) -> f64 {
+		let (_, v) = direction_to_uv(dir);
+		let sin_theta = (PI * v).sin();
+		if sin_theta <= 0.0 {
+			return 0.0;
+		}
+		let (i, j) = self.pixel_index(dir);
+		let row_sin_theta = (PI * (j as f64 + 0.5) / self.height as f64).sin();
+		let p_pixel = luminance(self.pixel(i, j)).max(0.0) * row_sin_theta / self.total();
+		// Pixels are sampled uniformly in (u, v), which covers 2 pi^2 sin(theta) of solid angle.
+		p_pixel * (self.width * self.height) as f64 / (2.0 * PI * PI * sin_theta)
+	}
+}
+
+#[cfg(test)]
+fn test_map() -> EnvironmentMap {
+	// Dim everywhere, with a bright spot right of the center.
+	let (width, height) = (16, 8);
+	let mut pixels = vec![Vec3(0.01, 0.01, 0.01); width*height];
+	pixels[3*width + 10] = Vec3(50.0, 40.0, 30.0);
+	EnvironmentMap::new(width, height, pixels)
+}
+
+#[test]
+fn uv_test() {
+	assert!((uv_to_direction(0.5, 0.5) - Vec3(0.0, 0.0, -1.0)).near_zero());
+	assert!((uv_to_direction(0.75, 0.5) - Vec3(1.0, 0.0, 0.0)).near_zero());
+	assert!((uv_to_direction(0.3, 0.0) - Vec3(0.0, 1.0, 0.0)).near_zero());
+	let (u, v) = direction_to_uv(&uv_to_direction(0.3, 0.7));
+	assert!((u - 0.3).abs() < 1e-9 && (v - 0.7).abs() < 1e-9);
+}
+
+#[test]
+fn sampling_test() {
+	let map = test_map();
+	let origin = Vec3(0.0, 0.0, 0.0);
+	let mut rng = crate::rng::scene_rng(0);
+
+	// The PDF integrates to 1 over the sphere.
+	let n = 100000;
+	let integral = (0..n).map(|_| map.pdf_eval(&origin, &random_unit_vector(&mut rng))).sum::<f64>() * 4.0 * PI / n as f64;
+	assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+
+	// Most samples go to the bright spot.
+	let spot = (0..1000).filter(|_| {
+		let (u, v) = direction_to_uv(&map.gen_random_point(&origin, &mut rng));
+		((u * 16.0) as usize, (v * 8.0) as usize) == (10, 3)
+	}).count();
+	assert!(spot > 950, "{}", spot);
+	assert!(map.light().is_some());
+	assert!(EnvironmentMap::new(2, 1, vec![Vec3(0.0, 0.0, 0.0); 2]).light().is_none());
+}
+
+#[test]
+fn color_test() {
+	let map = test_map();
+	let spot = uv_to_direction(10.5 / 16.0, 3.5 / 8.0);
+	assert!((map.color(&spot) - Vec3(50.0, 40.0, 30.0)).length() < 1e-6);
+	assert!((map.color(&Vec3(0.0, -1.0, 0.0)) - Vec3(0.01, 0.01, 0.01)).near_zero());
+	// Rotating by 90 degrees moves the spot from +x to -z.
+	let rotated = test_map().rotate_y(90.0);
+	assert!((rotated.color(&uv_to_direction(6.5 / 16.0, 3.5 / 8.0)) - Vec3(50.0, 40.0, 30.0)).length() < 1e-6);
+}
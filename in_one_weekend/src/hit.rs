@@ -2,17 +2,21 @@ use crate::vec3::*;
 use crate::material::*;
 use crate::aabb::*;
 use crate::ray::*;
-use rand::random;
+use crate::rng::Rng;
+use rand::Rng as _;
 
 pub struct HitRecord<'a> {
     // Point where the hit happened.
     pub p: Point3,
     // Normal vector to the surface at point `p`.
     pub normal: Vec3,
-    pub material: &'a Box<dyn Material>,
+    pub material: &'a dyn Material,
     pub t: f64,
     pub front_face: bool,
     pub coord: Vec2,
+    // Number of the scene object that was hit, counted from 1. Set by
+    // ObjectId, 0 for objects that are not numbered.
+    pub object_id: usize,
 }
 
 impl HitRecord<'_> {
@@ -21,11 +25,12 @@ impl HitRecord<'_> {
         self.normal = if self.front_face { outward_normal } else { outward_normal * (-1.0) }
     }
 }
-pub trait Hittable: Sync {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+pub trait Hittable: Sync + Send {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
     fn bounding_box(&self) -> Option<AABB>;
-	fn gen_random_point(&self, origin: &Vec3) -> Vec3 {
+	fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
         let _ = origin;
+        let _ = rng;
         Vec3(0.0, 0.0, 0.0)
     }
     fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
@@ -35,6 +40,36 @@ pub trait Hittable: Sync {
     }
     // Used to collect all lights that are behind this Hittable.
     fn pick_lights(&self) -> Vec<&dyn Hittable> { vec![] }
+    // Estimated power emitted by a light, so that bright lights are sampled
+    // more often. 0 if not known.
+    fn power(&self) -> f64 { 0.0 }
+}
+
+// Gives all hits of `object` the object number `id`.
+pub struct ObjectId {
+    pub object: Box<dyn Hittable>,
+    pub id: usize,
+}
+
+impl ObjectId {
+    // Numbers the objects of a scene in order, starting with 1.
+    pub fn number(objects: Vec<Box<dyn Hittable>>) -> Vec<Box<dyn Hittable>> {
+        objects.into_iter().enumerate()
+            .map(|(i, object)| Box::new(ObjectId { object, id: i + 1 }) as Box<dyn Hittable>).collect()
+    }
+}
+
+impl Hittable for ObjectId {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut hr = self.object.hit(r, t_min, t_max)?;
+        hr.object_id = self.id;
+        Some(hr)
+    }
+    fn bounding_box(&self) -> Option<AABB> { self.object.bounding_box() }
+    fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 { self.object.gen_random_point(origin, rng) }
+    fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 { self.object.pdf_eval(origin, dir) }
+    fn pick_lights(&self) -> Vec<&dyn Hittable> { self.object.pick_lights() }
+    fn power(&self) -> f64 { self.object.power() }
 }
 
 pub struct HittableList {
@@ -42,8 +77,8 @@ pub struct HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut temp_rec: Option<HitRecord>  = None;
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut temp_rec: Option<HitRecord<'_>>  = None;
         let mut closest_so_far = t_max;
         for obj in &self.objects {
             if let Some(hr) = obj.hit(r, t_min, closest_so_far) {
@@ -54,7 +89,7 @@ impl Hittable for HittableList {
         temp_rec
     }
     fn bounding_box(&self) -> Option<AABB> {
-        if self.objects.len() <= 0 { return None }
+        if self.objects.is_empty() { return None }
         let mut bb;
         if let Some(x) = self.objects[0].bounding_box() {
             bb = x;
@@ -74,8 +109,11 @@ impl Hittable for HittableList {
         let w = 1.0/self.objects.len() as f64;
         self.objects.iter().map(|x| {w*x.pdf_eval(origin, dir)} ).sum()
     }
-    fn gen_random_point(&self, origin: &Vec3) -> Vec3 {
-        let n = random::<usize>() % self.objects.len();
-        self.objects[n].gen_random_point(origin)
+    fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+        let n = rng.gen_range(0..self.objects.len());
+        self.objects[n].gen_random_point(origin, rng)
+    }
+    fn pick_lights(&self) -> Vec<&dyn Hittable> {
+        self.objects.iter().flat_map(|x| x.pick_lights()).collect()
     }
 }
@@ -0,0 +1,33 @@
+use std::f64::consts::PI;
+
+use crate::vec3::*;
+use crate::hit::*;
+use crate::texture::*;
+use crate::material::*;
+use crate::rng::Rng;
+
+// Phase function of a participating medium that scatters uniformly in all
+// directions. Used with ConstantMedium.
+pub struct Isotropic {
+	pub albedo: Box<dyn Texture>,
+}
+
+impl Material for Isotropic {
+	fn eval(&self, hr: &HitRecord, _wi: Vec3, _wo: Vec3) -> Color {
+		self.albedo.value(hr.coord, &hr.p) / (4.0 * PI)
+	}
+
+	fn pdf(&self, _hr: &HitRecord, _wi: Vec3, _wo: Vec3) -> f64 {
+		1.0 / (4.0 * PI)
+	}
+
+	fn sample(&self, hr: &HitRecord, _wo: Vec3, rng: &mut Rng) -> Option<BsdfSample> {
+		// The direction is sampled from the phase function itself, so the
+		// phase function and the pdf cancel out.
+		Some(BsdfSample { dir: random_unit_vector(rng), weight: self.albedo.value(hr.coord, &hr.p), pdf: 1.0 / (4.0 * PI) })
+	}
+
+	fn albedo(&self, coord: Vec2, p: &Point3) -> Color {
+		self.albedo.value(coord, p)
+	}
+}
@@ -2,39 +2,38 @@ use std::f64::consts::PI;
 
 use crate::vec3::*;
 use crate::hit::*;
-use crate::ray::*;
 use crate::texture::*;
 use crate::material::*;
 use crate::pdf::*;
+use crate::rng::Rng;
 
 pub struct Lambertian {
 	pub albedo: Box<dyn Texture>,
 }
- 
-// Scattering PDF evaluated for scattering into `scattered` at `hr`.
-fn scattering_pdf(hr: &HitRecord, scattered_dir: &Vec3) -> f64 {
-	let cosine = dot(hr.normal, unit_vector(*scattered_dir));
-	if cosine > 0.0 {
-		cosine / PI
-	} else {
-		0.0
+
+impl Material for Lambertian {
+	fn eval(&self, hr: &HitRecord, wi: Vec3, _wo: Vec3) -> Color {
+		self.albedo.value(hr.coord, &hr.p) * (dot(hr.normal, wi).max(0.0) / PI)
 	}
-}
 
+	fn pdf(&self, hr: &HitRecord, wi: Vec3, _wo: Vec3) -> f64 {
+		CosinePDF{normal: &hr.normal}.eval(&wi)
+	}
 
-impl Material for Lambertian {
-	fn scatter(&self, _r_in: &Ray, hr: &HitRecord, lights: &[&dyn Hittable])
-	    -> Option<(Vec3, Color)> {
+	fn sample(&self, hr: &HitRecord, _wo: Vec3, rng: &mut Rng) -> Option<BsdfSample> {
 		let cos_pdf = CosinePDF{normal: &hr.normal};
-		let (scattered_dir, pdf_val) = if lights.len() > 0 {
-		    gen_eval(&hr.p, &cos_pdf, 0.5, lights)
-		} else {
-		    gen_eval(&hr.p, &cos_pdf, 1.0, lights)
-		};
+		let dir = cos_pdf.gen(rng);
+		// Sampled in proportion to the cosine, which cancels out.
+		Some(BsdfSample { dir, weight: self.albedo.value(hr.coord, &hr.p), pdf: cos_pdf.eval(&dir) })
+	}
 
-		let color_contribution = self.albedo.value(hr.coord, &hr.p) *
-			scattering_pdf(&hr, &scattered_dir) *
-			(1.0/pdf_val);
-		Some((scattered_dir, color_contribution))
+	fn albedo(&self, coord: Vec2, p: &Point3) -> Color {
+		self.albedo.value(coord, p)
 	}
 }
+
+// A plain gray surface for tests.
+#[cfg(test)]
+pub fn gray() -> Lambertian {
+	Lambertian { albedo: Box::new(SolidColor { color: Vec3(0.5, 0.5, 0.5) }) }
+}
@@ -0,0 +1,38 @@
+// Path tracer following the "Ray Tracing in One Weekend" book series.
+pub mod vec3;
+pub mod dielectric;
+pub mod bvh_node;
+pub mod camera;
+pub mod hit;
+pub mod metal;
+pub mod aabb;
+pub mod ray;
+pub mod sphere;
+pub mod texture;
+pub mod perlin;
+pub mod rectangle;
+pub mod material;
+pub mod lambertian;
+pub mod pdf;
+pub mod scene;
+pub mod scene_file;
+pub mod scenes;
+pub mod cli;
+pub mod output;
+pub mod triangle;
+pub mod mesh;
+pub mod transform;
+pub mod box_shape;
+pub mod isotropic;
+pub mod constant_medium;
+pub mod rng;
+pub mod render;
+pub mod denoise;
+pub mod aov;
+pub mod tonemap;
+pub mod preview;
+pub mod environment;
+pub mod sky;
+pub mod microfacet;
+pub mod lights;
+pub mod delta_light;
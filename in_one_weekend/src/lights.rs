@@ -0,0 +1,423 @@
+// Choosing which light to sample at a point.
+//
+// Lights infinitely far away (the environment, the sun and directional
+// lights) are chosen as
+// often as all other lights together. Among the other lights the choice is
+// uniform, in proportion to their power, or by a tree of lights that
+// estimates what each group of lights contributes at the point from its
+// power and distance.
+use rand::Rng as _;
+
+use crate::aabb::*;
+use crate::hit::*;
+use crate::delta_light::DeltaLight;
+use crate::vec3::*;
+use crate::rng::Rng;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum LightSampling {
+	// Every light equally often, whatever it emits.
+	Uniform,
+	// In proportion to the power of the lights.
+	Power,
+	// Near and bright lights more often.
+	#[default]
+	Tree,
+}
+
+impl LightSampling {
+	pub const NAMES: [&'static str; 3] = ["uniform", "power", "tree"];
+
+	pub fn parse(s: &str) -> Option<LightSampling> {
+		match s {
+			"uniform" => Some(LightSampling::Uniform),
+			"power" => Some(LightSampling::Power),
+			"tree" => Some(LightSampling::Tree),
+			_ => None,
+		}
+	}
+}
+
+// A light to sample: an object or the environment, which rays can hit, or a
+// light rays never hit.
+#[derive(Clone, Copy)]
+pub enum Light<'a> {
+	Object(&'a dyn Hittable),
+	Delta(&'a dyn DeltaLight),
+}
+
+impl Light<'_> {
+	fn bounding_box(&self) -> Option<AABB> {
+		match self {
+			Light::Object(l) => l.bounding_box(),
+			Light::Delta(l) => l.position().map(|p| AABB::new(p, p)),
+		}
+	}
+
+	fn power(&self) -> f64 {
+		match self {
+			Light::Object(l) => l.power(),
+			Light::Delta(l) => l.power(),
+		}
+	}
+}
+
+// The lights of a scene and how to choose among them, the objects first
+// and then the delta lights.
+pub struct Lights<'a> {
+	lights: Vec<Light<'a>>,
+	// Lights without a bounding box, infinitely far away.
+	infinite: Vec<usize>,
+	// All other lights, chosen by `selection`.
+	finite: Vec<usize>,
+	// Position of every light in `infinite` or `finite`.
+	slot: Vec<usize>,
+	// Over the finite lights, also to find them by position.
+	tree: LightTree,
+	selection: Selection,
+}
+
+enum Selection {
+	Uniform,
+	Power(AliasTable),
+	Tree,
+}
+
+impl<'a> Lights<'a> {
+	pub fn new(objects: Vec<&'a dyn Hittable>, delta: Vec<&'a dyn DeltaLight>, sampling: LightSampling) -> Lights<'a> {
+		let lights = objects.into_iter().map(Light::Object)
+			.chain(delta.into_iter().map(Light::Delta)).collect::<Vec<_>>();
+		let mut infinite = vec![];
+		let mut finite = vec![];
+		let mut slot = vec![];
+		for (i, l) in lights.iter().enumerate() {
+			let group = if l.bounding_box().is_some() { &mut finite } else { &mut infinite };
+			slot.push(group.len());
+			group.push(i);
+		}
+		let powers = known_powers(&finite.iter().map(|i| lights[*i].power()).collect::<Vec<_>>());
+		let boxes = finite.iter().map(|i| lights[*i].bounding_box().unwrap()).collect::<Vec<_>>();
+		let tree = LightTree::new(&boxes, &powers);
+		let selection = match sampling {
+			LightSampling::Uniform => Selection::Uniform,
+			LightSampling::Power => Selection::Power(AliasTable::new(&powers)),
+			LightSampling::Tree => Selection::Tree,
+		};
+		Lights { lights, infinite, finite, slot, tree, selection }
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.lights.is_empty()
+	}
+
+	pub fn len(&self) -> usize {
+		self.lights.len()
+	}
+
+	pub fn get(&self, i: usize) -> Light<'a> {
+		self.lights[i]
+	}
+
+	// Number of groups chosen equally often: each infinite light and all
+	// finite lights together.
+	fn groups(&self) -> usize {
+		self.infinite.len() + if self.finite.is_empty() { 0 } else { 1 }
+	}
+
+	// Chooses the light to sample at `p`. Returns its index and the
+	// probability of choosing it. There must be lights.
+	pub fn choose(&self, p: &Point3, rng: &mut Rng) -> (usize, f64) {
+		if let Selection::Uniform = self.selection {
+			return (rng.gen_range(0..self.lights.len()), 1.0 / self.lights.len() as f64);
+		}
+		let groups = self.groups();
+		let g = rng.gen_range(0..groups);
+		if g < self.infinite.len() {
+			return (self.infinite[g], 1.0 / groups as f64);
+		}
+		let (j, prob) = match &self.selection {
+			Selection::Power(table) => table.sample(rng),
+			Selection::Tree => self.tree.sample(p, rng),
+			Selection::Uniform => unreachable!(),
+		};
+		(self.finite[j], prob / groups as f64)
+	}
+
+	// Probability of `choose` choosing light `i` at `p`.
+	pub fn probability(&self, p: &Point3, i: usize) -> f64 {
+		if let Selection::Uniform = self.selection {
+			return 1.0 / self.lights.len() as f64;
+		}
+		let prob = if self.lights[i].bounding_box().is_none() {
+			1.0
+		} else {
+			match &self.selection {
+				Selection::Power(table) => table.probability(self.slot[i]),
+				Selection::Tree => self.tree.probability(p, self.slot[i]),
+				Selection::Uniform => unreachable!(),
+			}
+		};
+		prob / self.groups() as f64
+	}
+
+	// The objects among the lights that could be at `p`, the infinite ones if
+	// None, with their indices.
+	pub fn at(&self, p: Option<Point3>) -> Vec<(usize, &'a dyn Hittable)> {
+		let candidates = match p {
+			Some(p) => {
+				let mut found = vec![];
+				if !self.finite.is_empty() {
+					self.tree.containing(0, &p, &mut found);
+				}
+				found.iter().map(|j| self.finite[*j]).collect()
+			}
+			None => self.infinite.clone(),
+		};
+		candidates.into_iter().filter_map(|i| match self.lights[i] {
+			Light::Object(l) => Some((i, l)),
+			Light::Delta(_) => None,
+		}).collect()
+	}
+}
+
+// Lights whose power is not known get the average power of the others, so
+// that every light is chosen sometimes.
+fn known_powers(powers: &[f64]) -> Vec<f64> {
+	let known = powers.iter().filter(|p| **p > 0.0 && p.is_finite()).collect::<Vec<_>>();
+	let fallback = if known.is_empty() { 1.0 } else { known.iter().copied().sum::<f64>() / known.len() as f64 };
+	powers.iter().map(|p| if *p > 0.0 && p.is_finite() { *p } else { fallback }).collect()
+}
+
+// Chooses among n items in proportion to their weights in constant time
+// (Walker's alias method). Every item has a bucket of probability 1/n, holding
+// the item itself with probability `prob` and its `alias` otherwise.
+struct AliasTable {
+	prob: Vec<f64>,
+	alias: Vec<usize>,
+	// Probability of choosing each item.
+	pmf: Vec<f64>,
+}
+
+impl AliasTable {
+	fn new(weights: &[f64]) -> AliasTable {
+		let n = weights.len();
+		let total = weights.iter().sum::<f64>();
+		let pmf = weights.iter().map(|w| w / total).collect::<Vec<_>>();
+		let mut scaled = pmf.iter().map(|p| p * n as f64).collect::<Vec<_>>();
+		let mut prob = vec![1.0; n];
+		let mut alias = (0..n).collect::<Vec<_>>();
+		let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|i| scaled[*i] < 1.0);
+		// Fills the buckets of the small items with large ones.
+		while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+			prob[s] = scaled[s];
+			alias[s] = l;
+			scaled[l] -= 1.0 - scaled[s];
+			if scaled[l] < 1.0 {
+				large.pop();
+				small.push(l);
+			}
+		}
+		// What is left is full, up to rounding.
+		AliasTable { prob, alias, pmf }
+	}
+
+	fn sample(&self, rng: &mut Rng) -> (usize, f64) {
+		let i = rng.gen_range(0..self.prob.len());
+		let i = if rng.gen::<f64>() < self.prob[i] { i } else { self.alias[i] };
+		(i, self.pmf[i])
+	}
+
+	fn probability(&self, i: usize) -> f64 {
+		self.pmf[i]
+	}
+}
+
+// A binary tree over the lights, each node bounding its lights and summing
+// their power. Going down from the root, a child is chosen in proportion to
+// an estimate of how much light it sends to the point.
+struct LightTree {
+	nodes: Vec<Node>,
+	// Node of every light.
+	leaves: Vec<usize>,
+}
+
+struct Node {
+	bbox: AABB,
+	power: f64,
+	parent: Option<usize>,
+	children: Option<(usize, usize)>,
+	// The light of a leaf.
+	light: usize,
+}
+
+impl LightTree {
+	fn new(boxes: &[AABB], powers: &[f64]) -> LightTree {
+		let mut tree = LightTree { nodes: vec![], leaves: vec![0; boxes.len()] };
+		if !boxes.is_empty() {
+			tree.build(boxes, powers, (0..boxes.len()).collect(), None);
+		}
+		tree
+	}
+
+	// Adds the node of `lights`, splitting them in halves along the longest
+	// side of the box around their centers.
+	fn build(&mut self, boxes: &[AABB], powers: &[f64], mut lights: Vec<usize>, parent: Option<usize>) -> usize {
+		let center = |i: usize| (boxes[i].min() + boxes[i].max()) / 2.0;
+		let bbox = lights.iter().map(|i| boxes[*i].clone()).reduce(|a, b| a.surrounding_box(&b)).unwrap();
+		let power = lights.iter().map(|i| powers[*i]).sum();
+		let node = self.nodes.len();
+		self.nodes.push(Node { bbox, power, parent, children: None, light: lights[0] });
+		if lights.len() == 1 {
+			self.leaves[lights[0]] = node;
+			return node;
+		}
+		let centers = lights.iter().map(|i| { let c = center(*i); AABB::new(c, c) })
+			.reduce(|a, b| a.surrounding_box(&b)).unwrap();
+		let d = centers.max() - centers.min();
+		let axis = if d.0 >= d.1 && d.0 >= d.2 { 0 } else if d.1 >= d.2 { 1 } else { 2 };
+		let key = |i: &usize| { let c = center(*i); [c.0, c.1, c.2][axis] };
+		lights.sort_by(|a, b| key(a).total_cmp(&key(b)));
+		let right = lights.split_off(lights.len() / 2);
+		let l = self.build(boxes, powers, lights, Some(node));
+		let r = self.build(boxes, powers, right, Some(node));
+		self.nodes[node].children = Some((l, r));
+		node
+	}
+
+	// Power over the squared distance to the box, but no closer than half its
+	// diagonal, where the lights inside could be anywhere.
+	fn importance(&self, node: usize, p: &Point3) -> f64 {
+		let n = &self.nodes[node];
+		let center = (n.bbox.min() + n.bbox.max()) / 2.0;
+		let d2 = (center - *p).length_squared();
+		let r2 = (n.bbox.max() - n.bbox.min()).length_squared() / 4.0;
+		n.power / d2.max(r2).max(1e-12)
+	}
+
+	// Probability of choosing the left child of `node` at `p`.
+	fn left_probability(&self, node: usize, p: &Point3) -> f64 {
+		let (l, r) = self.nodes[node].children.unwrap();
+		let (a, b) = (self.importance(l, p), self.importance(r, p));
+		if a + b > 0.0 { a / (a + b) } else { 0.5 }
+	}
+
+	// Adds the lights below `node` whose boxes hold `p`, up to rounding.
+	fn containing(&self, node: usize, p: &Point3, found: &mut Vec<usize>) {
+		let n = &self.nodes[node];
+		let (min, max) = (n.bbox.min(), n.bbox.max());
+		let eps = 1e-6 * (1.0 + p.0.abs() + p.1.abs() + p.2.abs());
+		let inside = |x: f64, a: f64, b: f64| x >= a - eps && x <= b + eps;
+		if !(inside(p.0, min.0, max.0) && inside(p.1, min.1, max.1) && inside(p.2, min.2, max.2)) {
+			return;
+		}
+		match n.children {
+			Some((l, r)) => {
+				self.containing(l, p, found);
+				self.containing(r, p, found);
+			}
+			None => found.push(n.light),
+		}
+	}
+
+	fn sample(&self, p: &Point3, rng: &mut Rng) -> (usize, f64) {
+		let mut node = 0;
+		let mut prob = 1.0;
+		while let Some((l, r)) = self.nodes[node].children {
+			let pl = self.left_probability(node, p);
+			if rng.gen::<f64>() < pl {
+				node = l;
+				prob *= pl;
+			} else {
+				node = r;
+				prob *= 1.0 - pl;
+			}
+		}
+		(self.nodes[node].light, prob)
+	}
+
+	fn probability(&self, p: &Point3, light: usize) -> f64 {
+		let mut node = self.leaves[light];
+		let mut prob = 1.0;
+		while let Some(parent) = self.nodes[node].parent {
+			let pl = self.left_probability(parent, p);
+			prob *= if self.nodes[parent].children.unwrap().0 == node { pl } else { 1.0 - pl };
+			node = parent;
+		}
+		prob
+	}
+}
+
+#[test]
+fn alias_table_test() {
+	let weights = [1.0, 7.0, 0.5, 1.5];
+	let table = AliasTable::new(&weights);
+	let mut rng = crate::rng::scene_rng(0);
+	let n = 100000;
+	let mut counts = [0; 4];
+	for _ in 0..n {
+		let (i, p) = table.sample(&mut rng);
+		assert_eq!(p, weights[i] / 10.0);
+		counts[i] += 1;
+	}
+	for i in 0..4 {
+		assert!((counts[i] as f64 / n as f64 - weights[i] / 10.0).abs() < 0.01, "{:?}", counts);
+	}
+}
+
+#[test]
+fn light_tree_test() {
+	let boxes = (0..8).map(|i| {
+		let c = Vec3(i as f64, 0.0, 0.0);
+		AABB::new(c - Vec3(0.1, 0.1, 0.1), c + Vec3(0.1, 0.1, 0.1))
+	}).collect::<Vec<_>>();
+	let tree = LightTree::new(&boxes, &[1.0; 8]);
+	let mut rng = crate::rng::scene_rng(0);
+	for p in [Vec3(0.0, 1.0, 0.0), Vec3(7.0, 0.5, 0.0), Vec3(3.5, 10.0, 0.0)] {
+		// The probabilities add up to one and agree with sampling.
+		let total = (0..8).map(|i| tree.probability(&p, i)).sum::<f64>();
+		assert!((total - 1.0).abs() < 1e-9, "{}", total);
+		for _ in 0..100 {
+			let (i, prob) = tree.sample(&p, &mut rng);
+			assert!((prob / tree.probability(&p, i) - 1.0).abs() < 1e-12);
+		}
+	}
+	// Near lights are chosen more often.
+	let p = Vec3(0.0, 1.0, 0.0);
+	assert!(tree.probability(&p, 0) > 10.0 * tree.probability(&p, 7));
+}
+
+#[test]
+fn lights_test() {
+	use crate::sphere::Sphere;
+	use crate::metal::DiffuseLight;
+	use crate::texture::SolidColor;
+	use crate::environment::Environment;
+	use crate::delta_light::*;
+
+	let light = |center: Vec3, emit: f64| Sphere {
+		center, radius: 0.5,
+		material: Box::new(DiffuseLight { emit: Box::new(SolidColor { color: Vec3(emit, emit, emit) }) }),
+	};
+	let (bright, dim) = (light(Vec3(0.0, 0.0, 0.0), 100.0), light(Vec3(5.0, 0.0, 0.0), 1.0));
+	let sky = crate::sky::Sky::new(Vec3(0.0, 1.0, 0.0), 3.0, 0.3, true);
+	let all: Vec<&dyn Hittable> = vec![&bright, &dim, sky.light().unwrap()];
+	let point = PointLight { position: Vec3(2.5, 0.5, 0.0), intensity: Vec3(1.0, 1.0, 1.0) };
+	let directional = DirectionalLight::new(Vec3(0.0, -1.0, 0.0), Vec3(1.0, 1.0, 1.0));
+	let delta: Vec<&dyn DeltaLight> = vec![&point, &directional];
+	let p = Vec3(2.5, 1.0, 0.0);
+	for sampling in [LightSampling::Uniform, LightSampling::Power, LightSampling::Tree] {
+		let lights = Lights::new(all.clone(), delta.clone(), sampling);
+		let total = (0..5).map(|i| lights.probability(&p, i)).sum::<f64>();
+		assert!((total - 1.0).abs() < 1e-9, "{:?} {}", sampling, total);
+		if sampling != LightSampling::Uniform {
+			// The sun and the directional light are chosen a third of the time
+			// each, the bright light more often than the dim one.
+			assert_eq!(lights.probability(&p, 2), 1.0 / 3.0);
+			assert_eq!(lights.probability(&p, 4), 1.0 / 3.0);
+			assert!(lights.probability(&p, 0) > lights.probability(&p, 1));
+		}
+		// Only the objects can be hit.
+		assert_eq!(lights.at(None).iter().map(|l| l.0).collect::<Vec<_>>(), vec![2]);
+		assert!(lights.at(Some(Vec3(2.5, 0.5, 0.0))).is_empty());
+	}
+}
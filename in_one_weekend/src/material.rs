@@ -1,19 +1,53 @@
+use std::f64::consts::PI;
+
 use crate::vec3::*;
 use crate::hit::*;
-use crate::ray::*;
-
-pub trait Material: Sync {
-	// Scatters the light. Returns the scattering direction and the color
-	// contribution of this scattering.
-	// Returns None when the ray was absorbed.
-	fn scatter(&self, r_in: &Ray, hr: &HitRecord, lights: &[&dyn Hittable])
-	    -> Option<(Vec3, Color)> {
-		let _ = r_in;
+use crate::rng::Rng;
+
+// A direction sampled by `Material::sample`.
+pub struct BsdfSample {
+	pub dir: Vec3,
+	// The scattered light per light arriving from `dir`, `eval` divided by `pdf`.
+	pub weight: Color,
+	// Density of sampling `dir` per solid angle, not defined for specular materials.
+	pub pdf: f64,
+}
+
+// How a material scatters light. Directions point away from the surface at
+// `hr`, `wo` towards where the light goes (the viewer) and `wi` towards
+// where it comes from.
+pub trait Material: Sync + Send {
+	// Light scattered towards `wo` per light arriving from `wi` and per solid
+	// angle, the BSDF including the cosine of `wi` with the surface.
+	fn eval(&self, hr: &HitRecord, wi: Vec3, wo: Vec3) -> Color {
+		let _ = hr;
+		let _ = wi;
+		let _ = wo;
+		Vec3(0.0, 0.0, 0.0)
+	}
+
+	// Density of `sample` returning `wi`.
+	fn pdf(&self, hr: &HitRecord, wi: Vec3, wo: Vec3) -> f64 {
+		let _ = hr;
+		let _ = wi;
+		let _ = wo;
+		0.0
+	}
+
+	// Samples the direction `wi` light comes from, preferring those that
+	// contribute much. Returns None when the light was absorbed.
+	fn sample(&self, hr: &HitRecord, wo: Vec3, rng: &mut Rng) -> Option<BsdfSample> {
 		let _ = hr;
-		let _ = lights;
+		let _ = wo;
+		let _ = rng;
 		None
 	}
 
+	// Whether the material only scatters into single directions, like mirrors
+	// and clear glass. `eval` and `pdf` are zero then and only `sample` finds
+	// the directions.
+	fn is_specular(&self) -> bool { false }
+
 	// Returns color of the absorbed light. Only makes sense for lights.
 	fn emitted(&self, coord: Vec2, p: &Point3) -> Color {
 		let _ = coord;
@@ -21,5 +55,19 @@ pub trait Material: Sync {
 		Vec3(0.0, 0.0, 0.0)
 	}
 
-	fn is_light(&self) -> bool { return false; }
-}
\ No newline at end of file
+	fn is_light(&self) -> bool { false }
+
+	// Fraction of the light that the surface reflects, without the directional
+	// details. Used as a guide for denoising.
+	fn albedo(&self, coord: Vec2, p: &Point3) -> Color {
+		let _ = coord;
+		let _ = p;
+		Vec3(1.0, 1.0, 1.0)
+	}
+}
+
+// Power emitted by `area` of a diffuse light of `material`, taking the
+// emission at `p` for all of it.
+pub fn diffuse_power(material: &dyn Material, area: f64, p: &Point3) -> f64 {
+	PI * area * luminance(material.emitted(Vec2(0.5, 0.5), p))
+}
@@ -0,0 +1,364 @@
+// Triangle meshes loaded from Wavefront OBJ files.
+//
+// Supported are vertices, normals and texture coordinates, polygonal faces
+// (split into triangle fans), and materials from MTL files referenced with
+// `mtllib`. MTL materials are mapped to the materials of this renderer:
+//   emissive (`Ke`)                          -> DiffuseLight
+//   transparent (`d` < 1, `Tr` > 0, illum 4, 6, 7) -> Dielectric with index `Ni`
+//   mirror (illum 3 or more specular than diffuse) -> Metal, fuzz from `Ns`
+//   anything else                            -> Lambertian with `Kd` or `map_Kd`
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::bvh_node::*;
+use crate::dielectric::*;
+use crate::hit::*;
+use crate::lambertian::*;
+use crate::material::*;
+use crate::metal::*;
+use crate::ray::*;
+use crate::rng::Rng;
+use crate::texture::*;
+use crate::transform::Affine;
+use crate::triangle;
+use crate::vec3::*;
+
+struct MeshData {
+	positions: Vec<Point3>,
+	normals: Vec<Vec3>,
+	uvs: Vec<Vec2>,
+	materials: Vec<Box<dyn Material>>,
+}
+
+// A triangle of a mesh, referring to the shared mesh data by indices.
+struct MeshTriangle {
+	mesh: Arc<MeshData>,
+	v: [usize; 3],
+	n: Option<[usize; 3]>,
+	uv: Option<[usize; 3]>,
+	material: usize,
+}
+
+impl MeshTriangle {
+	fn vertices(&self) -> [Point3; 3] {
+		[self.mesh.positions[self.v[0]], self.mesh.positions[self.v[1]], self.mesh.positions[self.v[2]]]
+	}
+}
+
+impl Hittable for MeshTriangle {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+		let v = self.vertices();
+		let (t, u, w) = triangle::intersect(&v, r, t_min, t_max)?;
+		let m = &self.mesh;
+		let normals = self.n.map(|n| [m.normals[n[0]], m.normals[n[1]], m.normals[n[2]]]);
+		let coord = match self.uv {
+			Some(uv) => {
+				let (a, b, c) = (m.uvs[uv[0]], m.uvs[uv[1]], m.uvs[uv[2]]);
+				Vec2((1.0 - u - w) * a.0 + u * b.0 + w * c.0, (1.0 - u - w) * a.1 + u * b.1 + w * c.1)
+			}
+			None => Vec2(u, w),
+		};
+		Some(triangle::hit_record(r, t, (u, w), &v, normals, coord, m.materials[self.material].as_ref()))
+	}
+	fn bounding_box(&self) -> Option<AABB> {
+		Some(triangle::bounding_box(&self.vertices()))
+	}
+	fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		triangle::random_point(&self.vertices(), origin, rng)
+	}
+	fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
+		triangle::pdf_value(&self.vertices(), origin, dir)
+	}
+	fn pick_lights(&self) -> Vec<&dyn Hittable> {
+		if self.mesh.materials[self.material].is_light() {
+			vec![self]
+		} else {
+			vec![]
+		}
+	}
+	fn power(&self) -> f64 {
+		let v = self.vertices();
+		diffuse_power(self.mesh.materials[self.material].as_ref(), triangle::area(&v), &((v[0] + v[1] + v[2]) / 3.0))
+	}
+}
+
+pub struct TriangleMesh {
+	bvh: BVHNode,
+}
+
+impl Hittable for TriangleMesh {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+		self.bvh.hit(r, t_min, t_max)
+	}
+	fn bounding_box(&self) -> Option<AABB> {
+		self.bvh.bounding_box()
+	}
+	fn pick_lights(&self) -> Vec<&dyn Hittable> {
+		self.bvh.pick_lights()
+	}
+}
+
+impl TriangleMesh {
+	// Loads an OBJ file placed in the world by `to_world`. Faces without a
+	// material from an MTL file use `default_material`.
+	pub fn load(path: &str, default_material: Box<dyn Material>, to_world: &Affine) -> Result<TriangleMesh, String> {
+		let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+		let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+		let obj = parse_obj(&text, dir, default_material).map_err(|e| format!("{}:{}", path, e))?;
+		if obj.faces.is_empty() {
+			return Err(format!("{}: no faces", path));
+		}
+		TriangleMesh::new(obj, to_world).ok_or_else(|| "the transformation is not invertible".to_string())
+	}
+
+	// The vertices are moved rather than the mesh wrapped into a Transform,
+	// so that every emissive triangle is sampled as a light of its own.
+	// Returns None if `to_world` is not invertible.
+	fn new(obj: Obj, to_world: &Affine) -> Option<TriangleMesh> {
+		let to_object = to_world.inverse()?;
+		let mut data = obj.data;
+		for p in &mut data.positions {
+			*p = to_world.point(*p);
+		}
+		for n in &mut data.normals {
+			*n = unit_vector(to_object.transpose_vector(*n));
+		}
+		let mesh = Arc::new(data);
+		let triangles = obj.faces.into_iter().map(|f| {
+			Box::new(MeshTriangle { mesh: mesh.clone(), v: f.v, n: f.n, uv: f.uv, material: f.material }) as Box<dyn Hittable>
+		}).collect();
+		Some(TriangleMesh { bvh: BVHNode::new(triangles) })
+	}
+}
+
+struct Face {
+	v: [usize; 3],
+	n: Option<[usize; 3]>,
+	uv: Option<[usize; 3]>,
+	material: usize,
+}
+
+struct Obj {
+	data: MeshData,
+	faces: Vec<Face>,
+}
+
+// Parses the text of an OBJ file. Errors are prefixed with the line number.
+fn parse_obj(text: &str, dir: &Path, default_material: Box<dyn Material>) -> Result<Obj, String> {
+	let mut data = MeshData { positions: vec![], normals: vec![], uvs: vec![], materials: vec![default_material] };
+	let mut faces = vec![];
+	// Material names from MTL files and their index in `data.materials`.
+	let mut names: Vec<(String, usize)> = vec![];
+	let mut material = 0;
+	for (i, line) in text.lines().enumerate() {
+		let err = |e: String| format!("{}: {}", i + 1, e);
+		let mut words = line.split('#').next().unwrap().split_whitespace();
+		match words.next() {
+			Some("v") => data.positions.push(floats::<3>(words).map(|x| Vec3(x[0], x[1], x[2])).map_err(err)?),
+			Some("vn") => data.normals.push(floats::<3>(words).map(|x| unit_vector(Vec3(x[0], x[1], x[2]))).map_err(err)?),
+			// The optional third texture coordinate is ignored.
+			Some("vt") => data.uvs.push(floats::<2>(words.take(2)).map(|x| Vec2(x[0], x[1])).map_err(err)?),
+			Some("f") => {
+				let rest: Vec<&str> = words.collect();
+				if rest.len() < 3 {
+					return Err(err("a face needs at least three vertices".to_string()));
+				}
+				let corners = rest.iter().map(|c| corner(c, &data)).collect::<Result<Vec<_>, String>>().map_err(err)?;
+				for k in 1..corners.len()-1 {
+					let (a, b, c) = (corners[0], corners[k], corners[k+1]);
+					let all = |f: fn(&Corner) -> Option<usize>| {
+						Some([f(&a)?, f(&b)?, f(&c)?])
+					};
+					faces.push(Face { v: [a.0, b.0, c.0], uv: all(|x| x.1), n: all(|x| x.2), material });
+				}
+			}
+			Some("mtllib") => {
+				for file in words {
+					let path = dir.join(file);
+					let text = std::fs::read_to_string(&path).map_err(|e| err(format!("{}: {}", path.display(), e)))?;
+					let mtl_dir = path.parent().unwrap_or(Path::new(""));
+					for (name, m) in parse_mtl(&text, mtl_dir).map_err(|e| err(format!("{}:{}", path.display(), e)))? {
+						names.push((name, data.materials.len()));
+						data.materials.push(m.build());
+					}
+				}
+			}
+			Some("usemtl") => {
+				let name = words.next().unwrap_or("");
+				material = names.iter().rev().find(|(n, _)| n == name).map(|(_, m)| *m)
+					.ok_or_else(|| err(format!("undefined material '{}'", name)))?;
+			}
+			_ => {}
+		}
+	}
+	Ok(Obj { data, faces })
+}
+
+// Indices of the position, texture coordinates and normal of a face corner.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+// Parses a face corner `v`, `v/vt`, `v//vn` or `v/vt/vn` into 0-based indices.
+// Negative indices count from the last element defined so far.
+fn corner(s: &str, data: &MeshData) -> Result<Corner, String> {
+	let mut parts = s.split('/');
+	let index = |p: Option<&str>, len: usize| -> Result<Option<usize>, String> {
+		match p {
+			None | Some("") => Ok(None),
+			Some(x) => {
+				let i = x.parse::<i64>().map_err(|_| format!("bad index '{}'", x))?;
+				let idx = if i < 0 { len as i64 + i } else { i - 1 };
+				if idx < 0 || idx >= len as i64 {
+					return Err(format!("index {} out of range", i));
+				}
+				Ok(Some(idx as usize))
+			}
+		}
+	};
+	let v = index(parts.next(), data.positions.len())?.ok_or_else(|| format!("bad face corner '{}'", s))?;
+	let uv = index(parts.next(), data.uvs.len())?;
+	let n = index(parts.next(), data.normals.len())?;
+	Ok((v, uv, n))
+}
+
+fn floats<'a, const N: usize>(words: impl Iterator<Item = &'a str>) -> Result<[f64; N], String> {
+	let xs = words.map(|w| w.parse::<f64>().map_err(|_| format!("expected a number, got '{}'", w)))
+		.collect::<Result<Vec<f64>, String>>()?;
+	xs.try_into().map_err(|xs: Vec<f64>| format!("expected {} numbers, got {}", N, xs.len()))
+}
+
+// The properties of an MTL material that are used to pick a renderer material.
+struct Mtl {
+	kd: Color,
+	ks: Color,
+	ke: Color,
+	ns: f64,
+	ni: f64,
+	dissolve: f64,
+	illum: i32,
+	map_kd: Option<ImageTexture>,
+}
+
+impl Mtl {
+	fn build(self) -> Box<dyn Material> {
+		let max = |c: Color| { c.0.max(c.1).max(c.2) };
+		if max(self.ke) > 0.0 {
+			Box::new(DiffuseLight { emit: Box::new(SolidColor { color: self.ke }) })
+		} else if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7) {
+			Box::new(Dielectric { ir: self.ni })
+		} else if self.illum == 3 || max(self.ks) > max(self.kd) {
+			// Rougher for lower Phong exponents, a common conversion to a roughness.
+			let fuzz = (2.0 / (self.ns + 2.0)).sqrt();
+			Box::new(Metal { albedo: self.ks, fuzz })
+		} else {
+			let albedo: Box<dyn Texture> = match self.map_kd {
+				Some(t) => Box::new(t),
+				None => Box::new(SolidColor { color: self.kd }),
+			};
+			Box::new(Lambertian { albedo })
+		}
+	}
+}
+
+fn parse_mtl(text: &str, dir: &Path) -> Result<Vec<(String, Mtl)>, String> {
+	let mut materials: Vec<(String, Mtl)> = vec![];
+	for (i, line) in text.lines().enumerate() {
+		let err = |e: String| format!("{}: {}", i + 1, e);
+		let mut words = line.split('#').next().unwrap().split_whitespace();
+		let key = match words.next() {
+			Some(k) => k,
+			None => continue,
+		};
+		if key == "newmtl" {
+			let name = words.next().ok_or_else(|| err("missing material name".to_string()))?;
+			materials.push((name.to_string(), Mtl {
+				kd: Vec3(0.8, 0.8, 0.8), ks: Vec3(0.0, 0.0, 0.0), ke: Vec3(0.0, 0.0, 0.0),
+				ns: 0.0, ni: 1.5, dissolve: 1.0, illum: 2, map_kd: None,
+			}));
+			continue;
+		}
+		let m = match materials.last_mut() {
+			Some((_, m)) => m,
+			None => return Err(err(format!("'{}' before 'newmtl'", key))),
+		};
+		let color = |words| floats::<3>(words).map(|x| Vec3(x[0], x[1], x[2])).map_err(err);
+		let number = |words| floats::<1>(words).map(|x| x[0]).map_err(err);
+		match key {
+			"Kd" => m.kd = color(words)?,
+			"Ks" => m.ks = color(words)?,
+			"Ke" => m.ke = color(words)?,
+			"Ns" => m.ns = number(words)?,
+			"Ni" => m.ni = number(words)?,
+			"d" => m.dissolve = number(words)?,
+			"Tr" => m.dissolve = 1.0 - number(words)?,
+			"illum" => m.illum = number(words)? as i32,
+			"map_Kd" => {
+				// Options before the file name are not supported, the last word is the file.
+				let file = words.last().ok_or_else(|| err("missing file name".to_string()))?;
+				let path = dir.join(file);
+				let texture = ImageTexture::new(&path.to_string_lossy())
+					.map_err(|e| err(format!("failed to load image '{}': {}", path.display(), e)))?;
+				m.map_kd = Some(texture);
+			}
+			_ => {}
+		}
+	}
+	Ok(materials)
+}
+
+#[test]
+fn parse_obj_test() {
+	let obj = parse_obj("
+		v 0 0 0
+		v 1 0 0
+		v 1 1 0
+		v 0 1 0
+		vn 0 0 2
+		vt 0 0
+		f 1//1 2//1 3//1 4//1
+		f -4/1 -3/1 -2/1
+	", Path::new(""), Box::new(gray())).unwrap();
+
+	assert_eq!(obj.faces.len(), 3);
+	assert_eq!(obj.faces[1].v, [0, 2, 3]);
+	assert_eq!(obj.faces[1].n, Some([0, 0, 0]));
+	assert_eq!(obj.faces[1].uv, None);
+	assert_eq!(obj.faces[2].v, [0, 1, 2]);
+	assert_eq!(obj.faces[2].uv, Some([0, 0, 0]));
+	assert_eq!(obj.data.normals[0], Vec3(0.0, 0.0, 1.0));
+
+	assert_eq!(parse_obj("v 0 0 0\nf 1 2 3", Path::new(""), Box::new(gray())).err(), Some("2: index 2 out of range".to_string()));
+}
+
+#[test]
+fn light_test() {
+	use crate::rectangle::*;
+	use crate::texture::*;
+
+	let light = || Box::new(DiffuseLight { emit: Box::new(SolidColor { color: Vec3(1.0, 1.0, 1.0) }) });
+	let obj = parse_obj("
+		v -1 0 -1
+		v 1 0 -1
+		v 1 0 1
+		v -1 0 1
+		vn 0 1 0
+		f 1//1 2//1 3//1 4//1
+	", Path::new(""), light()).unwrap();
+	let place = Affine::scaling(Vec3(2.0, 1.0, 0.5)).then(&Affine::translation(Vec3(0.0, 3.0, 0.0)));
+	let mesh = TriangleMesh::new(obj, &place).unwrap();
+	let expected = XZRect { p1: Vec2(-2.0, -0.5), p2: Vec2(2.0, 0.5), k: 3.0, material: light() };
+
+	// Both triangles are lights, each sampled half as often as the square.
+	let lights = mesh.pick_lights();
+	assert_eq!(lights.len(), 2);
+	let origin = Vec3(0.5, 0.0, 0.2);
+	let mut rng = crate::rng::scene_rng(0);
+	for i in 0..10 {
+		let dir = lights[i % 2].gen_random_point(&origin, &mut rng);
+		let pdf = lights.iter().map(|l| 0.5 * l.pdf_eval(&origin, &dir)).sum::<f64>();
+		assert!((expected.pdf_eval(&origin, &dir) - pdf).abs() < 1e-9);
+	}
+	let hr = mesh.hit(&Ray { orig: origin, dir: Vec3(0.0, 1.0, 0.0), time: 0.0 }, 0.0, f64::INFINITY).unwrap();
+	assert_eq!(hr.t, 3.0);
+	assert_near(hr.normal, Vec3(0.0, -1.0, 0.0));
+}
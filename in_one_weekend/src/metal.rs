@@ -1,8 +1,10 @@
+use std::f64::consts::PI;
+
 use crate::vec3::*;
 use crate::hit::*;
-use crate::ray::*;
 use crate::texture::*;
 use crate::material::*;
+use crate::rng::Rng;
 
 pub struct DiffuseLight {
 	pub emit: Box<dyn Texture>,
@@ -12,7 +14,11 @@ impl Material for DiffuseLight {
 	fn emitted(&self, coord: Vec2, p: &Point3) -> Color {
 		self.emit.value(coord, p)
 	}
-	fn is_light(&self) -> bool { return true; }
+	fn is_light(&self) -> bool { true }
+	fn albedo(&self, coord: Vec2, p: &Point3) -> Color {
+		let c = self.emit.value(coord, p);
+		Vec3(c.0.min(1.0), c.1.min(1.0), c.2.min(1.0))
+	}
 }
 
 pub struct Metal {
@@ -20,16 +26,75 @@ pub struct Metal {
 	pub fuzz: f64,
 }
 
+impl Metal {
+	// Density of the direction of the mirror direction `reflected` moved by a
+	// random point of the ball of radius `fuzz`: the part of the ball's volume
+	// in the cone around `wi`.
+	fn fuzz_pdf(&self, reflected: Vec3, wi: Vec3) -> f64 {
+		let cos = dot(reflected, wi);
+		let d2 = self.fuzz * self.fuzz - (1.0 - cos * cos);
+		if d2 <= 0.0 {
+			return 0.0;
+		}
+		// Distances along `wi` where it enters and leaves the ball.
+		let far = cos + d2.sqrt();
+		let near = (cos - d2.sqrt()).max(0.0);
+		if far <= 0.0 {
+			return 0.0;
+		}
+		(far.powi(3) - near.powi(3)) / (4.0 * PI * self.fuzz.powi(3))
+	}
+}
+
 impl Material for Metal {
-	fn scatter(&self, r_in: &Ray, hr: &HitRecord, _lights: &[&dyn Hittable])
-	    -> Option<(Vec3, Color)> {
-		let reflected = reflect(unit_vector(r_in.dir), hr.normal);
-		let scattered_dir = reflected + self.fuzz*random_in_unit_sphere();
-		if dot(scattered_dir, hr.normal) > 0.0 {
-			Some((scattered_dir, self.albedo))
+	// Directions below the surface are absorbed, so the light scattered in
+	// other directions is the albedo times their density.
+	fn eval(&self, hr: &HitRecord, wi: Vec3, wo: Vec3) -> Color {
+		if dot(wi, hr.normal) > 0.0 { self.albedo * self.pdf(hr, wi, wo) } else { Vec3(0.0, 0.0, 0.0) }
+	}
+
+	fn pdf(&self, hr: &HitRecord, wi: Vec3, wo: Vec3) -> f64 {
+		if self.is_specular() {
+			return 0.0;
+		}
+		self.fuzz_pdf(reflect(-1.0 * wo, hr.normal), unit_vector(wi))
+	}
+
+	fn sample(&self, hr: &HitRecord, wo: Vec3, rng: &mut Rng) -> Option<BsdfSample> {
+		let reflected = reflect(-1.0 * wo, hr.normal);
+		let dir = unit_vector(reflected + self.fuzz*random_in_unit_sphere(rng));
+		if dot(dir, hr.normal) > 0.0 {
+			Some(BsdfSample { dir, weight: self.albedo, pdf: self.fuzz_pdf(reflected, dir) })
 		} else {
 			None
 		}
 	}
+
+	fn is_specular(&self) -> bool {
+		self.fuzz <= 0.0
+	}
+
+	fn albedo(&self, _coord: Vec2, _p: &Point3) -> Color {
+		self.albedo
+	}
 }
 
+#[test]
+fn fuzz_pdf_test() {
+	// The density integrates to one over the cone of directions through the
+	// ball, with the origin outside and inside the ball.
+	use rand::Rng as _;
+	let mut rng = crate::rng::scene_rng(0);
+	for fuzz in [0.3, 1.0, 1.5] {
+		let metal = Metal { albedo: Vec3(1.0, 1.0, 1.0), fuzz };
+		let cos_max = if fuzz < 1.0 { (1.0 - fuzz * fuzz).sqrt() } else { -1.0 };
+		let n = 400000;
+		let total = (0..n).map(|_| {
+			let z = rng.gen_range(cos_max..1.0);
+			let phi = 2.0 * PI * rng.gen::<f64>();
+			let r = (1.0 - z * z).sqrt();
+			metal.fuzz_pdf(Vec3(0.0, 0.0, 1.0), Vec3(r * phi.cos(), r * phi.sin(), z))
+		}).sum::<f64>() * 2.0 * PI * (1.0 - cos_max) / n as f64;
+		assert!((total - 1.0).abs() < 0.02, "{} {}", fuzz, total);
+	}
+}
@@ -0,0 +1,401 @@
+// Rough surfaces made of tiny mirror facets (microfacets) whose normals follow
+// the GGX (Trowbridge-Reitz) distribution, with height-correlated masking and
+// shadowing. Directions are sampled from the facets visible from the incoming
+// direction (Heitz, "Sampling the GGX Distribution of Visible Normals", 2018).
+//
+// Both materials evaluate their scattering function and its PDF for any
+// direction, so that light sampling helps them like Lambertian surfaces.
+use std::f64::consts::PI;
+
+use rand::Rng as _;
+
+use crate::vec3::*;
+use crate::hit::*;
+use crate::material::*;
+use crate::rng::Rng;
+
+// Roughness below this behaves like a mirror, but keeps the numbers finite.
+const MIN_ALPHA: f64 = 1e-3;
+
+// An orthonormal basis with `w` the surface normal. Directions in local
+// coordinates have the normal as z.
+struct Frame {
+	u: Vec3,
+	v: Vec3,
+	w: Vec3,
+}
+
+impl Frame {
+	fn new(normal: &Vec3) -> Frame {
+		let w = unit_vector(*normal);
+		let a = if w.0.abs() > 0.9 { Vec3(0.0, 1.0, 0.0) } else { Vec3(1.0, 0.0, 0.0) };
+		let v = unit_vector(cross(w, a));
+		let u = cross(w, v);
+		Frame { u, v, w }
+	}
+
+	fn to_local(&self, d: Vec3) -> Vec3 {
+		Vec3(dot(d, self.u), dot(d, self.v), dot(d, self.w))
+	}
+
+	fn to_world(&self, d: Vec3) -> Vec3 {
+		d.0 * self.u + d.1 * self.v + d.2 * self.w
+	}
+}
+
+// The GGX distribution of facet normals in local coordinates.
+struct Ggx {
+	alpha: f64,
+}
+
+impl Ggx {
+	// `roughness` in [0, 1] is squared to get a perceptually even scale.
+	fn new(roughness: f64) -> Ggx {
+		Ggx { alpha: (roughness * roughness).max(MIN_ALPHA) }
+	}
+
+	// Density of facets with normal `h` per projected area.
+	fn d(&self, h: Vec3) -> f64 {
+		if h.2 <= 0.0 {
+			return 0.0;
+		}
+		let a2 = self.alpha * self.alpha;
+		let x = h.2 * h.2 * (a2 - 1.0) + 1.0;
+		a2 / (PI * x * x)
+	}
+
+	fn lambda(&self, v: Vec3) -> f64 {
+		let cos2 = v.2 * v.2;
+		if cos2 == 0.0 {
+			return f64::INFINITY;
+		}
+		let tan2 = (1.0 - cos2).max(0.0) / cos2;
+		0.5 * (-1.0 + (1.0 + self.alpha * self.alpha * tan2).sqrt())
+	}
+
+	// Fraction of the facets visible from direction `v`.
+	fn g1(&self, v: Vec3) -> f64 {
+		1.0 / (1.0 + self.lambda(v))
+	}
+
+	// Fraction of the facets visible from both directions.
+	fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+		1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+	}
+
+	// A facet normal visible from `wo`, which is above the surface.
+	fn sample_visible(&self, wo: Vec3, rng: &mut Rng) -> Vec3 {
+		let a = self.alpha;
+		// Stretch the view so that the facets become a hemisphere, sample the
+		// projected hemisphere and unstretch.
+		let vh = unit_vector(Vec3(a * wo.0, a * wo.1, wo.2));
+		let len2 = vh.0 * vh.0 + vh.1 * vh.1;
+		let t1 = if len2 > 0.0 { Vec3(-vh.1, vh.0, 0.0) / len2.sqrt() } else { Vec3(1.0, 0.0, 0.0) };
+		let t2 = cross(vh, t1);
+		let r = rng.gen::<f64>().sqrt();
+		let phi = 2.0 * PI * rng.gen::<f64>();
+		let p1 = r * phi.cos();
+		let s = 0.5 * (1.0 + vh.2);
+		let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+		let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+		unit_vector(Vec3(a * nh.0, a * nh.1, nh.2.max(1e-9)))
+	}
+
+	// Density of `sample_visible` returning `h`.
+	fn pdf_visible(&self, wo: Vec3, h: Vec3) -> f64 {
+		self.g1(wo) * dot(wo, h).max(0.0) * self.d(h) / wo.2
+	}
+}
+
+// Reflectance of a conductor with complex index of refraction `eta` + i`k`
+// for unpolarized light, `cos` from the normal.
+fn fresnel_conductor(cos: f64, eta: f64, k: f64) -> f64 {
+	let c2 = cos * cos;
+	let s2 = 1.0 - c2;
+	let t0 = eta * eta - k * k - s2;
+	let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+	let t1 = a2b2 + c2;
+	let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+	let t2 = 2.0 * cos * a;
+	let rs = (t1 - t2) / (t1 + t2);
+	let t3 = c2 * a2b2 + s2 * s2;
+	let t4 = t2 * s2;
+	let rp = rs * (t3 - t4) / (t3 + t4);
+	0.5 * (rp + rs)
+}
+
+// Reflectance of the boundary to a medium with `eta` times the index of
+// refraction, 1 for total internal reflection.
+fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+	let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+	if sin2_t >= 1.0 {
+		return 1.0;
+	}
+	let cos_t = (1.0 - sin2_t).sqrt();
+	let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+	let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+	0.5 * (rs * rs + rp * rp)
+}
+
+// A scattering function in local coordinates, `wo` towards the viewer and
+// above the surface, `wi` where the light comes from.
+trait Lobe {
+	// A direction `wi` or None if the sample is lost.
+	fn sample(&self, wo: Vec3, rng: &mut Rng) -> Option<Vec3>;
+	// The scattering function and the density of `sample` returning `wi`.
+	fn eval(&self, wo: Vec3, wi: Vec3) -> (Color, f64);
+}
+
+// `Material::eval` and `Material::pdf` of `lobe` at `hr`.
+fn eval_lobe(lobe: &dyn Lobe, hr: &HitRecord, wi: Vec3, wo: Vec3) -> (Color, f64) {
+	let frame = Frame::new(&hr.normal);
+	let (wo, wi) = (frame.to_local(wo), frame.to_local(unit_vector(wi)));
+	if wo.2 <= 0.0 {
+		return (Vec3(0.0, 0.0, 0.0), 0.0);
+	}
+	let (f, pdf) = lobe.eval(wo, wi);
+	(f * wi.2.abs(), pdf)
+}
+
+fn sample_lobe(lobe: &dyn Lobe, hr: &HitRecord, wo: Vec3, rng: &mut Rng) -> Option<BsdfSample> {
+	let frame = Frame::new(&hr.normal);
+	let wo = frame.to_local(wo);
+	if wo.2 <= 0.0 {
+		return None;
+	}
+	let wi = lobe.sample(wo, rng)?;
+	let (f, pdf) = lobe.eval(wo, wi);
+	if pdf <= 0.0 || !pdf.is_finite() {
+		return None;
+	}
+	Some(BsdfSample { dir: frame.to_world(wi), weight: f * (wi.2.abs() / pdf), pdf })
+}
+
+// A rough metal, colored by its complex index of refraction `eta` + i`k`
+// per color channel.
+pub struct RoughConductor {
+	pub eta: Color,
+	pub k: Color,
+	// 0 for a mirror, 1 for very rough.
+	pub roughness: f64,
+}
+
+impl RoughConductor {
+	pub const PRESETS: [&'static str; 4] = ["gold", "copper", "aluminium", "silver"];
+
+	// Measured indices of refraction at the red, green and blue wavelengths.
+	pub fn preset(name: &str, roughness: f64) -> Option<RoughConductor> {
+		let (eta, k) = match name {
+			"gold" => (Vec3(0.143, 0.374, 1.442), Vec3(3.983, 2.386, 1.603)),
+			"copper" => (Vec3(0.200, 0.924, 1.102), Vec3(3.912, 2.452, 2.142)),
+			"aluminium" => (Vec3(1.657, 0.880, 0.521), Vec3(9.224, 6.270, 4.837)),
+			"silver" => (Vec3(0.155, 0.117, 0.138), Vec3(4.828, 3.122, 2.147)),
+			_ => return None,
+		};
+		Some(RoughConductor { eta, k, roughness })
+	}
+
+	fn fresnel(&self, cos: f64) -> Color {
+		Vec3(
+			fresnel_conductor(cos, self.eta.0, self.k.0),
+			fresnel_conductor(cos, self.eta.1, self.k.1),
+			fresnel_conductor(cos, self.eta.2, self.k.2),
+		)
+	}
+}
+
+impl Lobe for RoughConductor {
+	fn sample(&self, wo: Vec3, rng: &mut Rng) -> Option<Vec3> {
+		let h = Ggx::new(self.roughness).sample_visible(wo, rng);
+		let wi = 2.0 * dot(wo, h) * h - wo;
+		if wi.2 > 0.0 { Some(wi) } else { None }
+	}
+
+	fn eval(&self, wo: Vec3, wi: Vec3) -> (Color, f64) {
+		if wi.2 <= 0.0 {
+			return (Vec3(0.0, 0.0, 0.0), 0.0);
+		}
+		let ggx = Ggx::new(self.roughness);
+		let h = unit_vector(wo + wi);
+		let d = ggx.d(h);
+		let f = d * ggx.g2(wo, wi) / (4.0 * wo.2 * wi.2) * self.fresnel(dot(wo, h).max(0.0));
+		// Reflecting about `h` changes the density by 1 / (4 |wo.h|).
+		let pdf = ggx.pdf_visible(wo, h) / (4.0 * dot(wo, h).abs());
+		(f, pdf)
+	}
+}
+
+impl Material for RoughConductor {
+	fn eval(&self, hr: &HitRecord, wi: Vec3, wo: Vec3) -> Color {
+		eval_lobe(self, hr, wi, wo).0
+	}
+
+	fn pdf(&self, hr: &HitRecord, wi: Vec3, wo: Vec3) -> f64 {
+		eval_lobe(self, hr, wi, wo).1
+	}
+
+	fn sample(&self, hr: &HitRecord, wo: Vec3, rng: &mut Rng) -> Option<BsdfSample> {
+		sample_lobe(self, hr, wo, rng)
+	}
+
+	fn albedo(&self, _coord: Vec2, _p: &Point3) -> Color {
+		self.fresnel(1.0)
+	}
+}
+
+// Frosted glass: a rough boundary between air and a medium with index of
+// refraction `ir`, reflecting and refracting light.
+pub struct RoughDielectric {
+	pub ir: f64,
+	// 0 for clear glass, 1 for very rough.
+	pub roughness: f64,
+}
+
+impl RoughDielectric {
+	// The boundary seen from the side of `hr`.
+	fn boundary(&self, hr: &HitRecord) -> Boundary {
+		Boundary { ggx: Ggx::new(self.roughness), eta: if hr.front_face { self.ir } else { 1.0 / self.ir } }
+	}
+}
+
+// RoughDielectric seen from one side, `eta` the relative index of refraction
+// of the other side.
+struct Boundary {
+	ggx: Ggx,
+	eta: f64,
+}
+
+impl Lobe for Boundary {
+	fn sample(&self, wo: Vec3, rng: &mut Rng) -> Option<Vec3> {
+		let h = self.ggx.sample_visible(wo, rng);
+		let cos_i = dot(wo, h);
+		if rng.gen::<f64>() < fresnel_dielectric(cos_i, self.eta) {
+			let wi = 2.0 * cos_i * h - wo;
+			return if wi.2 > 0.0 { Some(wi) } else { None };
+		}
+		let cos_t = (1.0 - (1.0 - cos_i * cos_i) / (self.eta * self.eta)).sqrt();
+		let wi = (cos_i / self.eta - cos_t) * h - wo / self.eta;
+		if wi.2 < 0.0 { Some(wi) } else { None }
+	}
+
+	fn eval(&self, wo: Vec3, wi: Vec3) -> (Color, f64) {
+		let none = (Vec3(0.0, 0.0, 0.0), 0.0);
+		if wi.2 == 0.0 {
+			return none;
+		}
+		let ggx = &self.ggx;
+		let white = Vec3(1.0, 1.0, 1.0);
+		if wi.2 > 0.0 {
+			let h = unit_vector(wo + wi);
+			let fr = fresnel_dielectric(dot(wo, h).max(0.0), self.eta);
+			let f = ggx.d(h) * ggx.g2(wo, wi) * fr / (4.0 * wo.2 * wi.2);
+			let pdf = fr * ggx.pdf_visible(wo, h) / (4.0 * dot(wo, h).abs());
+			return (f * white, pdf);
+		}
+		// The facet normal that refracts `wo` into `wi`, facing `wo`.
+		let mut h = unit_vector(wo + self.eta * wi);
+		if h.2 < 0.0 {
+			h = -1.0 * h;
+		}
+		let (cos_o, cos_i) = (dot(wo, h), dot(wi, h));
+		if cos_o <= 0.0 || cos_i >= 0.0 {
+			return none;
+		}
+		let ft = 1.0 - fresnel_dielectric(cos_o, self.eta);
+		let denom = cos_o + self.eta * cos_i;
+		// Change of the density from the facet normal to the refracted direction.
+		let jacobian = self.eta * self.eta * cos_i.abs() / (denom * denom);
+		let f = ggx.d(h) * ggx.g2(wo, wi) * ft * cos_o * jacobian / (wo.2 * wi.2.abs());
+		let pdf = ft * ggx.pdf_visible(wo, h) * jacobian;
+		(f * white, pdf)
+	}
+}
+
+impl Material for RoughDielectric {
+	fn eval(&self, hr: &HitRecord, wi: Vec3, wo: Vec3) -> Color {
+		eval_lobe(&self.boundary(hr), hr, wi, wo).0
+	}
+
+	fn pdf(&self, hr: &HitRecord, wi: Vec3, wo: Vec3) -> f64 {
+		eval_lobe(&self.boundary(hr), hr, wi, wo).1
+	}
+
+	fn sample(&self, hr: &HitRecord, wo: Vec3, rng: &mut Rng) -> Option<BsdfSample> {
+		sample_lobe(&self.boundary(hr), hr, wo, rng)
+	}
+}
+
+#[cfg(test)]
+fn uniform_sphere(rng: &mut Rng) -> Vec3 {
+	let z = 1.0 - 2.0 * rng.gen::<f64>();
+	let phi = 2.0 * PI * rng.gen::<f64>();
+	let r = (1.0 - z * z).sqrt();
+	Vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[test]
+fn fresnel_test() {
+	assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+	assert_eq!(fresnel_dielectric(0.1, 1.0 / 1.5), 1.0);
+	// At normal incidence ((n-1)^2 + k^2) / ((n+1)^2 + k^2).
+	let (n, k) = (0.143, 3.983);
+	let f0 = ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+	assert!((fresnel_conductor(1.0, n, k) - f0).abs() < 1e-9);
+	assert!(fresnel_conductor(0.01, n, k) > 0.9);
+	let gold = RoughConductor::preset("gold", 0.5).unwrap().fresnel(1.0);
+	assert!(gold.0 > gold.1 && gold.1 > gold.2);
+	assert!(RoughConductor::preset("brass", 0.5).is_none());
+}
+
+#[test]
+fn ggx_test() {
+	let mut rng = crate::rng::scene_rng(0);
+	let n = 200000;
+	for roughness in [0.3, 0.8] {
+		let ggx = Ggx::new(roughness);
+		// The projected facet area is that of the surface.
+		let area = (0..n).map(|_| {
+			let h = uniform_sphere(&mut rng);
+			ggx.d(h) * h.2.max(0.0)
+		}).sum::<f64>() * 4.0 * PI / n as f64;
+		assert!((area - 1.0).abs() < 0.05, "{}", area);
+		let wo = unit_vector(Vec3(0.6, 0.0, 0.8));
+		for _ in 0..100 {
+			let h = ggx.sample_visible(wo, &mut rng);
+			assert!(dot(wo, h) >= 0.0 && h.2 > 0.0);
+		}
+	}
+}
+
+// Sampling and evaluation agree: the average weight of sampled directions is
+// the integral of the scattering function over the sphere.
+#[cfg(test)]
+fn check_lobe(lobe: &dyn Lobe, wo: Vec3) -> f64 {
+	let mut rng = crate::rng::scene_rng(1);
+	let n = 400000;
+	let sampled = (0..n).filter_map(|_| {
+		let wi = lobe.sample(wo, &mut rng)?;
+		let (f, pdf) = lobe.eval(wo, wi);
+		Some(luminance(f) * wi.2.abs() / pdf)
+	}).sum::<f64>() / n as f64;
+	let integral = (0..n).map(|_| {
+		let wi = uniform_sphere(&mut rng);
+		luminance(lobe.eval(wo, wi).0) * wi.2.abs()
+	}).sum::<f64>() * 4.0 * PI / n as f64;
+	assert!((sampled - integral).abs() < 0.03, "{} {}", sampled, integral);
+	// No more light leaves than arrives.
+	assert!(sampled <= 1.0, "{}", sampled);
+	sampled
+}
+
+#[test]
+fn bsdf_test() {
+	let wo = unit_vector(Vec3(0.3, 0.1, 0.9));
+	let gold = RoughConductor::preset("gold", 0.5).unwrap();
+	assert!(check_lobe(&gold, wo) > 0.5);
+	for eta in [1.5, 1.0 / 1.5] {
+		// Glass loses little light, only by scattering between facets.
+		let glass = Boundary { ggx: Ggx::new(0.4), eta };
+		assert!(check_lobe(&glass, wo) > 0.85);
+	}
+}
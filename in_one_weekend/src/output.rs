@@ -0,0 +1,223 @@
+// Writing rendered frames to image files.
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::{ImageBuffer, ImageResult, Rgb, Rgba, RgbaImage, Rgb32FImage};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+
+use crate::vec3::*;
+use crate::tonemap::ToneMap;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+	// Looping animation with a 256 color palette.
+	Gif,
+	// 8 and 16 bits per channel, tone mapped to sRGB.
+	Png,
+	Png16,
+	// Binary (P6) and plain text (P3) portable pixmaps, 8 bits per channel, tone mapped to sRGB.
+	Ppm,
+	PpmAscii,
+	// Linear floating-point color, not clamped.
+	Hdr,
+	Exr,
+}
+
+impl Format {
+	pub const NAMES: [&'static str; 7] = ["gif", "png", "png16", "ppm", "ppm-ascii", "hdr", "exr"];
+
+	pub fn parse(s: &str) -> Option<Format> {
+		match s.to_ascii_lowercase().as_str() {
+			"gif" => Some(Format::Gif),
+			"png" => Some(Format::Png),
+			"png16" => Some(Format::Png16),
+			"ppm" => Some(Format::Ppm),
+			"ppm-ascii" => Some(Format::PpmAscii),
+			"hdr" => Some(Format::Hdr),
+			"exr" => Some(Format::Exr),
+			_ => None,
+		}
+	}
+
+	// Guesses the format from the file extension. 16-bit PNG and plain text PPM
+	// share their extension with the default variant and have to be asked for.
+	pub fn from_path(path: &str) -> Option<Format> {
+		let ext = Path::new(path).extension()?.to_str()?;
+		match Format::parse(ext)? {
+			Format::Png16 | Format::PpmAscii => None,
+			f => Some(f),
+		}
+	}
+}
+
+// A rendered frame in linear color. Rows are stored bottom to top.
+#[derive(Clone)]
+pub struct Film {
+	pub width: usize,
+	pub height: usize,
+	pub pixels: Vec<Color>,
+}
+
+impl Film {
+	// Calls `f` with the image coordinates of every pixel, rows top to bottom.
+	fn for_each_pixel<F: FnMut(u32, u32, Color)>(&self, mut f: F) {
+		for j in 0..self.height {
+			for i in 0..self.width {
+				f(i as u32, (self.height - j - 1) as u32, self.pixels[j*self.width + i]);
+			}
+		}
+	}
+
+	pub fn to_rgba8(&self, t: &ToneMap) -> RgbaImage {
+		let mut img = RgbaImage::new(self.width as u32, self.height as u32);
+		self.for_each_pixel(|x, y, c| {
+			let [r, g, b] = t.quantize(c, x, y, 255.0);
+			img.put_pixel(x, y, Rgba([r as u8, g as u8, b as u8, 255]));
+		});
+		img
+	}
+
+	pub fn to_rgb16(&self, t: &ToneMap) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+		let mut img = ImageBuffer::new(self.width as u32, self.height as u32);
+		self.for_each_pixel(|x, y, c| {
+			let [r, g, b] = t.quantize(c, x, y, 65535.0);
+			img.put_pixel(x, y, Rgb([r as u16, g as u16, b as u16]));
+		});
+		img
+	}
+
+	pub fn to_rgb32f(&self) -> Rgb32FImage {
+		let mut img = Rgb32FImage::new(self.width as u32, self.height as u32);
+		self.for_each_pixel(|x, y, c| {
+			img.put_pixel(x, y, Rgb([c.0 as f32, c.1 as f32, c.2 as f32]));
+		});
+		img
+	}
+}
+
+// Writes a single frame in one of the still image formats. Integer formats
+// are converted with `t`.
+pub fn save_still(path: &str, format: Format, film: &Film, t: &ToneMap) -> ImageResult<()> {
+	match format {
+		Format::Gif => save_gif(path, vec![film.to_rgba8(t)]),
+		Format::Png => film.to_rgba8(t).save_with_format(path, image::ImageFormat::Png),
+		Format::Png16 => film.to_rgb16(t).save_with_format(path, image::ImageFormat::Png),
+		Format::Ppm | Format::PpmAscii => {
+			let encoding = if format == Format::Ppm { SampleEncoding::Binary } else { SampleEncoding::Ascii };
+			let rgb = image::DynamicImage::ImageRgba8(film.to_rgba8(t)).into_rgb8();
+			let out = BufWriter::new(File::create(path)?);
+			PnmEncoder::new(out).with_subtype(PnmSubtype::Pixmap(encoding))
+				.encode(rgb.as_raw().as_slice(), rgb.width(), rgb.height(), image::ColorType::Rgb8)
+		}
+		Format::Hdr => {
+			let img = film.to_rgb32f();
+			let out = BufWriter::new(File::create(path)?);
+			HdrEncoder::new(out).encode(&img.pixels().copied().collect::<Vec<_>>(), film.width, film.height)
+		}
+		Format::Exr => film.to_rgb32f().save_with_format(path, image::ImageFormat::OpenExr),
+	}
+}
+
+// Writes the frames as a looping animation that plays forward, then backward.
+pub fn save_gif(path: &str, fs: Vec<RgbaImage>) -> ImageResult<()> {
+	let file_out = File::create(path)?;
+	let mut encoder = GifEncoder::new(file_out);
+	encoder.set_repeat(Repeat::Infinite)?;
+	for img in &fs {
+		encoder.encode_frame(image::Frame::new(img.clone()))?;
+	};
+	for img in fs.into_iter().rev() {
+		encoder.encode_frame(image::Frame::new(img))?;
+	};
+	Ok(())
+}
+
+// Path of the frame with number `n` when an animation is written as a sequence
+// of still images: frame 7 of 100 of `out.png` is `out.07.png`.
+pub fn frame_path(path: &str, n: usize, num_frames: usize) -> String {
+	let digits = num_frames.saturating_sub(1).to_string().len();
+	let p = Path::new(path);
+	let numbered = match (p.file_stem(), p.extension()) {
+		(Some(stem), Some(ext)) => format!("{}.{:0digits$}.{}", stem.to_string_lossy(), n, ext.to_string_lossy()),
+		_ => format!("{}.{:0digits$}", path, n),
+	};
+	p.with_file_name(numbered).to_string_lossy().into_owned()
+}
+
+// Path of an auxiliary image named `name` written next to the image at
+// `path`: the albedo of `out.png` is `out.albedo.exr`.
+pub fn aov_path(path: &str, name: &str) -> String {
+	Path::new(path).with_extension(format!("{}.exr", name)).to_string_lossy().into_owned()
+}
+
+// Writes all frames: a single file for GIF, one file per frame otherwise.
+pub fn save(path: &str, format: Format, frames: Vec<Film>, t: &ToneMap) -> ImageResult<()> {
+	if format == Format::Gif {
+		return save_gif(path, frames.iter().map(|f| f.to_rgba8(t)).collect());
+	}
+	if frames.len() == 1 {
+		return save_still(path, format, &frames[0], t);
+	}
+	for (n, film) in frames.iter().enumerate() {
+		save_still(&frame_path(path, n, frames.len()), format, film, t)?;
+	}
+	Ok(())
+}
+
+// Visualizes the number of samples taken per pixel, from black for none over
+// red and yellow to white for `max`. `samples` is stored like Film pixels.
+pub fn heat_map(width: usize, height: usize, samples: &[usize], max: usize) -> RgbaImage {
+	let mut img = RgbaImage::new(width as u32, height as u32);
+	for j in 0..height {
+		for i in 0..width {
+			let x = 3.0 * samples[j*width + i] as f64 / max.max(1) as f64;
+			let c = |v: f64| { (255.0 * v.clamp(0.0, 1.0)).round() as u8 };
+			img.put_pixel(i as u32, (height - j - 1) as u32, Rgba([c(x), c(x - 1.0), c(x - 2.0), 255]));
+		}
+	}
+	img
+}
+
+#[test]
+fn frame_path_test() {
+	assert_eq!(frame_path("out.png", 7, 100), "out.07.png");
+	assert_eq!(frame_path("dir/out.exr", 3, 5), "dir/out.3.exr");
+	assert_eq!(frame_path("out", 12, 1000), "out.012");
+}
+
+#[test]
+fn aov_path_test() {
+	assert_eq!(aov_path("out.png", "albedo"), "out.albedo.exr");
+	assert_eq!(aov_path("dir/out", "depth"), "dir/out.depth.exr");
+}
+
+#[test]
+fn heat_map_test() {
+	let img = heat_map(3, 1, &[0, 5, 10], 10);
+	assert_eq!(img.pixels().map(|p| p.0).collect::<Vec<_>>(),
+		[[0, 0, 0, 255], [255, 128, 0, 255], [255, 255, 255, 255]]);
+}
+
+#[test]
+fn save_test() {
+	let film = Film { width: 2, height: 1, pixels: vec![Vec3(4.0, 0.25, 0.0), Vec3(0.0, 0.0, 1.0)] };
+	let dir = std::env::temp_dir();
+	for name in Format::NAMES {
+		let format = Format::parse(name).unwrap();
+		let path = dir.join(format!("in_one_weekend_save_test.{}", name)).to_string_lossy().into_owned();
+		save_still(&path, format, &film, &ToneMap::default()).unwrap();
+		if format == Format::PpmAscii {
+			assert_eq!(std::fs::read_to_string(&path).unwrap().split_whitespace().collect::<Vec<_>>(),
+				["P3", "2", "1", "255", "255", "137", "0", "0", "0", "255"]);
+		}
+		if format == Format::Exr {
+			// Floating-point output keeps values above 1.
+			let img = image::open(&path).unwrap().into_rgb32f();
+			assert_eq!(img.get_pixel(0, 0), &Rgb([4.0, 0.25, 0.0]));
+		}
+		std::fs::remove_file(&path).unwrap();
+	}
+}
@@ -1,28 +1,15 @@
 use crate::vec3::*;
-use crate::hit::*;
 
 use std::f64::consts::PI;
-use rand::random;
+use rand::Rng as _;
+
+use crate::rng::Rng;
 
 pub trait PDF {
 	// Evaluates the PDF for the given direction.
 	fn eval(&self, v: &Vec3) -> f64;
 	// Generates a random Vector according to the pdf.
-	fn gen(&self) -> Vec3;
-}
-
-pub fn gen_eval(origin: &Vec3, cos_pdf: &CosinePDF, w0: f64, pdfs: &[&dyn Hittable]) -> (Vec3, f64) {
-	let mut w = 0.0;
-	let r = random::<f64>();
-	let v = if r <= w0 {
-		cos_pdf.gen()
-	} else {
-		w = (1.0-w0)/(pdfs.len() as f64);
-		let idx = ((r - w0) / w).trunc() as usize;
-		unit_vector(pdfs[idx].gen_random_point(origin))
-	};
-	let gen = w0*cos_pdf.eval(&v) + pdfs.iter().map(|pdf| {pdf.pdf_eval(origin, &v) * w}).sum::<f64>();
-	(v, gen)
+	fn gen(&self, rng: &mut Rng) -> Vec3;
 }
 
 pub struct CosinePDF<'a> {
@@ -30,9 +17,9 @@ pub struct CosinePDF<'a> {
 }
 
 // Generates a vector in the hemisphere defined by normal `normal`.
-fn random_cosine_vector(normal: &Vec3) -> (Vec3, f64) {
-	let r1 = random::<f64>();
-	let r2 = random::<f64>();
+fn random_cosine_vector(normal: &Vec3, rng: &mut Rng) -> (Vec3, f64) {
+	let r1 = rng.gen::<f64>();
+	let r2 = rng.gen::<f64>();
 
 	let phi = 2.0*PI*r1;
 	let z = (1.0-r2).sqrt();
@@ -55,8 +42,8 @@ impl <'a> PDF for CosinePDF<'a> {
 			0.0
 		}
 	}
-	fn gen(&self) -> Vec3 {
-		let rcv = random_cosine_vector(self.normal);
+	fn gen(&self, rng: &mut Rng) -> Vec3 {
+		let rcv = random_cosine_vector(self.normal, rng);
 		rcv.0
 	}
 }
\ No newline at end of file
@@ -1,8 +1,8 @@
 use rand::prelude::*;
-use rand::thread_rng;
 
 use crate::vec3::*;
 use crate::texture::*;
+use crate::rng::Rng;
 
 const POINT_COUNT: usize = 256;
 
@@ -12,8 +12,8 @@ pub struct NoiseTexture {
 }
 
 impl NoiseTexture {
-	pub fn new(scale: f64) -> NoiseTexture {
-		NoiseTexture { noise: Perlin::new(), scale }
+	pub fn new(scale: f64, rng: &mut Rng) -> NoiseTexture {
+		NoiseTexture { noise: Perlin::new(rng), scale }
 	}
 }
 
@@ -31,12 +31,10 @@ struct Perlin {
 }
 
 impl Perlin {
-	fn new() -> Perlin {
-		let mut rng = thread_rng();
-
+	fn new(rng: &mut Rng) -> Perlin {
 		let mut ranvec = [Vec3(0.0, 0.0, 0.0); POINT_COUNT];
-		for i in 0..POINT_COUNT {
-			ranvec[i] = unit_vector(random_vec3_bounds(-1.0, 1.0));
+		for v in ranvec.iter_mut() {
+			*v = unit_vector(random_vec3_bounds(rng, -1.0, 1.0));
 		}
 
 		let mut perm_x: [usize; POINT_COUNT] = [0; POINT_COUNT];
@@ -47,13 +45,14 @@ impl Perlin {
 			perm_y[i] = i;
 			perm_z[i] = i;
 		}
-		perm_x.shuffle(&mut rng);
-		perm_y.shuffle(&mut rng);
-		perm_z.shuffle(&mut rng);
+		perm_x.shuffle(rng);
+		perm_y.shuffle(rng);
+		perm_z.shuffle(rng);
 
 		Perlin { ranvec, perm_x, perm_y, perm_z }
 	}
 
+	#[allow(clippy::needless_range_loop)]
 	fn noise(&self, p: &Point3) -> f64 {
 		let u = p.0 - p.0.floor();
 		let v = p.1 - p.1.floor();
@@ -67,11 +66,11 @@ impl Perlin {
 		for di in 0..2 {
 			for dj in 0..2 {
 				for dk in 0..2 {
-					c[di][dj][dk] = self.ranvec[(
+					c[di][dj][dk] = self.ranvec[
 						self.perm_x[((i + (di as i32)) & 255) as usize] ^
 						self.perm_y[((j + (dj as i32)) & 255) as usize] ^
 						self.perm_z[((k + (dk as i32)) & 255) as usize]
-					) as usize];
+					];
 				}
 			}
 		}
@@ -93,6 +92,7 @@ impl Perlin {
 }
 
 
+#[allow(clippy::needless_range_loop)]
 fn perlin_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
 	let uu = u*u*(3.0-2.0*u);
 	let vv = v*v*(3.0-2.0*v);
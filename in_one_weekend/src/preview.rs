@@ -0,0 +1,352 @@
+// Interactive preview of a scene in the web browser.
+//
+// `serve` renders the first frame progressively, one sample per pixel at a
+// time, and serves a page that shows the image rendered so far. The view is
+// changed with the keyboard on the page or by requesting
+// `/view?look_from=x,y,z&look_at=x,y,z&vfov_deg=d`, after which the render
+// starts over. The page shows the current view as a line for a scene file.
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::vec3::*;
+use crate::hit::*;
+use crate::lights::Lights;
+use crate::scene::*;
+use crate::render::Progressive;
+use crate::scene_file::{parse_f64, parse_vec3};
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<title>Preview</title>
+<style>
+body { background: #222; color: #ddd; font-family: monospace; }
+img { display: block; }
+</style>
+</head>
+<body>
+<img id="image">
+<pre id="status"></pre>
+<p>W/S: forward/back, A/D: left/right, R/F: up/down, arrows: turn, +/-: zoom</p>
+<script>
+let samples = null;
+async function update(query) {
+	const status = await (await fetch('/view' + (query || ''))).text();
+	document.getElementById('status').textContent = status;
+	const n = (status.match(/samples=(\d+)/) || [])[1];
+	if (n !== samples) {
+		samples = n;
+		document.getElementById('image').src = '/image.png?' + Date.now();
+	}
+}
+const moves = {
+	w: 'forward', s: 'back', a: 'left', d: 'right', r: 'up', f: 'down',
+	ArrowLeft: 'turn_left', ArrowRight: 'turn_right', ArrowUp: 'turn_up', ArrowDown: 'turn_down',
+	'+': 'zoom_in', '=': 'zoom_in', '-': 'zoom_out',
+};
+document.addEventListener('keydown', e => {
+	if (moves[e.key]) {
+		e.preventDefault();
+		update('?move=' + moves[e.key]);
+	}
+});
+setInterval(update, 300);
+update();
+</script>
+</body>
+</html>
+"#;
+
+// Fraction of the distance between camera and target moved per step.
+const MOVE_STEP: f64 = 0.05;
+const TURN_STEP_DEG: f64 = 5.0;
+const ZOOM_STEP: f64 = 1.1;
+
+struct State {
+	view: View,
+	// Incremented whenever the view changes.
+	generation: u64,
+	// The image rendered so far as PNG, empty before the first sample.
+	png: Vec<u8>,
+	samples: usize,
+}
+
+struct Shared {
+	state: Mutex<State>,
+	// Signaled when the view changes.
+	changed: Condvar,
+}
+
+// Rotates `v` around the unit vector `axis` by `deg` degrees.
+fn rotate(v: Vec3, axis: Vec3, deg: f64) -> Vec3 {
+	let (s, c) = deg.to_radians().sin_cos();
+	c * v + s * cross(axis, v) + ((1.0 - c) * dot(axis, v)) * axis
+}
+
+// Whether `dir` points straight up or down along `up`, where the camera can
+// not tell which way is up.
+fn along_up(dir: Vec3, up: Vec3) -> bool {
+	cross(unit_vector(dir), unit_vector(up)).length() < 0.01
+}
+
+// Changes the view by one step of `action`: moving both the camera and the
+// point it looks at, turning the camera or zooming.
+fn nudge(v: &View, action: &str) -> Result<View, String> {
+	let dir = v.look_at - v.look_from;
+	let forward = unit_vector(dir);
+	let right = unit_vector(cross(forward, v.v_up));
+	let up = unit_vector(v.v_up);
+	let step = MOVE_STEP * dir.length();
+	let shift = |d: Vec3| { View { look_from: v.look_from + step * d, look_at: v.look_at + step * d, ..v.clone() } };
+	let turn = |axis: Vec3, deg: f64| {
+		let turned = rotate(dir, axis, deg);
+		if along_up(turned, up) {
+			v.clone()
+		} else {
+			View { look_at: v.look_from + turned, ..v.clone() }
+		}
+	};
+	let zoom = |factor: f64| { View { vfov_deg: (v.vfov_deg * factor).clamp(1.0, 179.0), ..v.clone() } };
+	Ok(match action {
+		"forward" => shift(forward),
+		"back" => shift(-1.0 * forward),
+		"right" => shift(right),
+		"left" => shift(-1.0 * right),
+		"up" => shift(up),
+		"down" => shift(-1.0 * up),
+		"turn_left" => turn(up, TURN_STEP_DEG),
+		"turn_right" => turn(up, -TURN_STEP_DEG),
+		"turn_up" => turn(right, TURN_STEP_DEG),
+		"turn_down" => turn(right, -TURN_STEP_DEG),
+		"zoom_in" => zoom(1.0 / ZOOM_STEP),
+		"zoom_out" => zoom(ZOOM_STEP),
+		_ => return Err(format!("unknown move '{}'", action)),
+	})
+}
+
+// Decodes %XX escapes in URL query values.
+fn url_decode(s: &str) -> String {
+	let bytes = s.as_bytes();
+	let mut out = vec![];
+	let mut i = 0;
+	while i < bytes.len() {
+		let hex = s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+		match (bytes[i], hex) {
+			(b'%', Some(b)) => {
+				out.push(b);
+				i += 3;
+			}
+			(b, _) => {
+				out.push(b);
+				i += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&out).into_owned()
+}
+
+// Applies the `key=value` pairs of a `/view` query to `v`.
+fn change_view(v: &View, query: &str) -> Result<View, String> {
+	let mut v = v.clone();
+	for pair in query.split('&').filter(|p| !p.is_empty()) {
+		let (key, value) = pair.split_once('=').ok_or_else(|| format!("expected key=value, got '{}'", pair))?;
+		let value = url_decode(value);
+		let error = |e: String| { format!("'{}': {}", key, e) };
+		match key {
+			"look_from" => v.look_from = parse_vec3(&value).map_err(error)?,
+			"look_at" => v.look_at = parse_vec3(&value).map_err(error)?,
+			"vfov_deg" => {
+				let x = parse_f64(&value).map_err(error)?;
+				if x <= 0.0 || x >= 180.0 {
+					return Err(format!("'vfov_deg' must be between 0 and 180 degrees, got {}", x));
+				}
+				v.vfov_deg = x;
+			}
+			"move" => v = nudge(&v, &value)?,
+			_ => return Err(format!("unknown key '{}'", key)),
+		}
+	}
+	if (v.look_from - v.look_at).near_zero() {
+		return Err("the camera looks at its own position".to_string());
+	}
+	if along_up(v.look_at - v.look_from, v.v_up) {
+		return Err("the camera looks straight up or down".to_string());
+	}
+	Ok(v)
+}
+
+// Numbers rounded to 3 decimals, without trailing zeros.
+fn number(x: f64) -> String {
+	let s = format!("{:.3}", x);
+	let s = s.trim_end_matches('0').trim_end_matches('.');
+	if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+fn status(st: &State) -> String {
+	let v3 = |v: Vec3| { format!("{},{},{}", number(v.0), number(v.1), number(v.2)) };
+	format!("samples={}\nview look_from={} look_at={} vfov_deg={}\n",
+		st.samples, v3(st.view.look_from), v3(st.view.look_at), number(st.view.vfov_deg))
+}
+
+struct Response {
+	status: &'static str,
+	content_type: &'static str,
+	body: Vec<u8>,
+}
+
+impl Response {
+	fn text(status: &'static str, body: String) -> Response {
+		Response { status, content_type: "text/plain; charset=utf-8", body: body.into_bytes() }
+	}
+}
+
+// Answers a GET request for `target`, the path with the query.
+fn respond(target: &str, shared: &Shared) -> Response {
+	let (path, query) = target.split_once('?').unwrap_or((target, ""));
+	let mut st = shared.state.lock().unwrap();
+	match path {
+		"/" => Response { status: "200 OK", content_type: "text/html; charset=utf-8", body: PAGE.as_bytes().to_vec() },
+		"/image.png" if st.png.is_empty() => Response::text("503 Service Unavailable", "not rendered yet\n".to_string()),
+		"/image.png" => Response { status: "200 OK", content_type: "image/png", body: st.png.clone() },
+		"/view" => {
+			if query.is_empty() {
+				return Response::text("200 OK", status(&st));
+			}
+			match change_view(&st.view, query) {
+				Ok(v) => {
+					st.view = v;
+					st.generation += 1;
+					st.samples = 0;
+					shared.changed.notify_all();
+					Response::text("200 OK", status(&st))
+				}
+				Err(e) => Response::text("400 Bad Request", e + "\n"),
+			}
+		}
+		_ => Response::text("404 Not Found", "not found\n".to_string()),
+	}
+}
+
+fn handle(stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+	stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+	let mut reader = BufReader::new(&stream);
+	let mut request = String::new();
+	reader.read_line(&mut request)?;
+	// The headers are not needed.
+	let mut line = String::new();
+	while reader.read_line(&mut line)? > 2 {
+		line.clear();
+	}
+	let response = match request.split_whitespace().collect::<Vec<_>>()[..] {
+		["GET", target, _] => respond(target, shared),
+		_ => Response::text("400 Bad Request", "only GET is supported\n".to_string()),
+	};
+	let mut out = &stream;
+	write!(out, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+		response.status, response.content_type, response.body.len())?;
+	out.write_all(&response.body)
+}
+
+// Renders the current view until it has all samples of the scene, then waits
+// for the view to change.
+fn render_loop(world: &dyn Hittable, lights: &Lights, s: &Scene, shared: &Shared) {
+	loop {
+		let (view, generation) = {
+			let st = shared.state.lock().unwrap();
+			(st.view.clone(), st.generation)
+		};
+		let progressive = Progressive::new(world, &view, s);
+		while progressive.samples() < s.samples_per_pixel {
+			progressive.add_samples(world, lights, s, 1);
+			let mut png = vec![];
+			progressive.film(s).to_rgba8(&s.tone_map).write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+				.expect("PNG encoding failed");
+			let mut st = shared.state.lock().unwrap();
+			if st.generation != generation {
+				break;
+			}
+			st.png = png;
+			st.samples = progressive.samples();
+		}
+		let mut st = shared.state.lock().unwrap();
+		while st.generation == generation {
+			st = shared.changed.wait(st).unwrap();
+		}
+	}
+}
+
+// Serves the preview at `address`, e.g. "127.0.0.1:8000", until the process
+// is stopped. Only returns if the address can not be used.
+pub fn serve(address: &str, world: &dyn Hittable, lights: &Lights, s: &Scene, v: &View) -> std::io::Result<()> {
+	let listener = TcpListener::bind(address)?;
+	let shared = Shared {
+		state: Mutex::new(State { view: v.clone(), generation: 0, png: vec![], samples: 0 }),
+		changed: Condvar::new(),
+	};
+	std::thread::scope(|scope| {
+		scope.spawn(|| render_loop(world, lights, s, &shared));
+		let shared = &shared;
+		for stream in listener.incoming() {
+			// Every connection has its own thread, so that a client that is
+			// slow to send its request does not hold up the others.
+			scope.spawn(move || {
+				// A failed request does not stop the preview.
+				if let Err(e) = stream.and_then(|stream| handle(stream, shared)) {
+					eprintln!("preview: {}", e);
+				}
+			});
+		}
+	});
+	Ok(())
+}
+
+#[test]
+fn nudge_test() {
+	let v = View { look_from: Vec3(0.0, 0.0, 0.0), look_at: Vec3(0.0, 0.0, -10.0), ..View::default() };
+	let forward = nudge(&v, "forward").unwrap();
+	assert_near(forward.look_from, Vec3(0.0, 0.0, -0.5));
+	assert_near(forward.look_at, Vec3(0.0, 0.0, -10.5));
+	assert_near(nudge(&v, "right").unwrap().look_from, Vec3(0.5, 0.0, 0.0));
+	assert_near(nudge(&v, "up").unwrap().look_from, Vec3(0.0, 0.5, 0.0));
+
+	// Turning keeps the camera in place and the distance to the target.
+	let turned = nudge(&v, "turn_left").unwrap();
+	assert_eq!(turned.look_from, v.look_from);
+	assert!((turned.look_at.length() - 10.0).abs() < 1e-9);
+	assert!(turned.look_at.0 < 0.0);
+	let mut up = v.clone();
+	for _ in 0..30 {
+		up = nudge(&up, "turn_up").unwrap();
+	}
+	assert!(cross(unit_vector(up.look_at - up.look_from), up.v_up).length() > 0.01);
+
+	assert!(nudge(&v, "zoom_in").unwrap().vfov_deg < v.vfov_deg);
+	assert_eq!(nudge(&v, "jump").err(), Some("unknown move 'jump'".to_string()));
+}
+
+#[test]
+fn respond_test() {
+	let shared = Shared {
+		state: Mutex::new(State { view: View::default(), generation: 0, png: vec![], samples: 0 }),
+		changed: Condvar::new(),
+	};
+	assert_eq!(respond("/image.png", &shared).status, "503 Service Unavailable");
+	assert_eq!(respond("/", &shared).content_type, "text/html; charset=utf-8");
+	assert_eq!(respond("/favicon.ico", &shared).status, "404 Not Found");
+
+	let r = respond("/view?look_from=1%2C2%2C3&vfov_deg=20", &shared);
+	assert_eq!(String::from_utf8(r.body).unwrap(), "samples=0\nview look_from=1,2,3 look_at=0,0,0 vfov_deg=20\n");
+	assert_eq!(shared.state.lock().unwrap().generation, 1);
+
+	let r = respond("/view?look_at=1,2,3", &shared);
+	assert_eq!((r.status, String::from_utf8(r.body).unwrap().as_str()),
+		("400 Bad Request", "the camera looks at its own position\n"));
+	assert_eq!(respond("/view?vfov_deg=x", &shared).status, "400 Bad Request");
+	// Looking along v_up leaves the camera without a right direction.
+	let r = respond("/view?look_at=1,3,3", &shared);
+	assert_eq!((r.status, String::from_utf8(r.body).unwrap().as_str()),
+		("400 Bad Request", "the camera looks straight up or down\n"));
+	assert_eq!(shared.state.lock().unwrap().generation, 1);
+}
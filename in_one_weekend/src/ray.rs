@@ -1,11 +1,15 @@
-use std::f64::INFINITY;
-
 use crate::vec3::*;
 use crate::hit::*;
+use crate::environment::Environment;
+use crate::lights::*;
+use crate::delta_light::DeltaLight;
+use crate::rng::Rng;
 
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
+    // When the ray is sent, for objects that move.
+    pub time: f64,
 }
 impl Ray {
     pub fn at(&self, t: f64) -> Point3 {
@@ -13,22 +17,234 @@ impl Ray {
     }
 }
 
-pub fn ray_color(r: &Ray, background: &Color, world: &dyn Hittable, lights: &Vec<&dyn Hittable>,
-    depth: i32) -> Color {
+// The light arriving along `r`, found by following the path it came from and
+// by sampling the lights at every surface (next event estimation). A light
+// found both ways is weighted by the power heuristic (multiple importance
+// sampling), so each way counts where it is the better one.
+pub fn ray_color(r: &Ray, background: &dyn Environment, world: &dyn Hittable, lights: &Lights,
+    depth: i32, rng: &mut Rng) -> Color {
+    trace(r, background, world, lights, depth, None, rng)
+}
+
+// `bsdf_pdf` is the density of the material sampling `r`. It is None for
+// camera rays and after specular surfaces, whose light is not sampled.
+fn trace(r: &Ray, background: &dyn Environment, world: &dyn Hittable, lights: &Lights,
+    depth: i32, bsdf_pdf: Option<f64>, rng: &mut Rng) -> Color {
     if depth <= 0 {
         return Vec3(0.0, 0.0, 0.0);
     }
 
-    if let Some(hr) = world.hit(r, 0.001, INFINITY) {
-        let emitted = hr.material.emitted(hr.coord, &hr.p);
+    let hr = match world.hit(r, 0.001, f64::INFINITY) {
+        Some(hr) => hr,
+        None => return bsdf_weight(bsdf_pdf, lights, r, None) * background.color(&r.dir),
+    };
+    let mut color = hr.material.emitted(hr.coord, &hr.p);
+    if hr.material.is_light() {
+        color = bsdf_weight(bsdf_pdf, lights, r, Some(hr.t)) * color;
+    }
+
+    let wo = unit_vector(-1.0 * r.dir);
+    let specular = hr.material.is_specular();
+    // The last bounce finds no light by sampling the material, so the lights
+    // are not sampled there either.
+    if !specular && depth > 1 && !lights.is_empty() {
+        color = color + sample_light(&hr, wo, r.time, background, world, lights, rng);
+    }
+    if let Some(s) = hr.material.sample(&hr, wo, rng) {
+        let scattered_ray = Ray { orig: hr.p, dir: s.dir, time: r.time };
+        let pdf = if specular { None } else { Some(s.pdf) };
+        color = color + s.weight * trace(&scattered_ray, background, world, lights, depth-1, pdf, rng);
+    }
+    color
+}
+
+// Light from a light chosen by `lights` reaching `hr` and scattered towards `wo`.
+fn sample_light(hr: &HitRecord, wo: Vec3, time: f64, background: &dyn Environment, world: &dyn Hittable,
+    lights: &Lights, rng: &mut Rng) -> Color {
+    let black = Vec3(0.0, 0.0, 0.0);
+    let (i, prob) = lights.choose(&hr.p, rng);
+    let light = match lights.get(i) {
+        Light::Object(light) => light,
+        Light::Delta(light) => return sample_delta_light(hr, wo, time, world, light, prob),
+    };
+    let dir = light.gen_random_point(&hr.p, rng);
+    if dir.near_zero() {
+        return black;
+    }
+    let r = Ray { orig: hr.p, dir: unit_vector(dir), time };
+    let f = hr.material.eval(hr, r.dir, wo);
+    if f == black {
+        return black;
+    }
+    let (emitted, t) = match world.hit(&r, 0.001, f64::INFINITY) {
+        Some(h) if h.material.is_light() => (h.material.emitted(h.coord, &h.p), Some(h.t)),
+        Some(_) => return black,
+        None => (background.color(&r.dir), None),
+    };
+    // Another light in front is counted when it is chosen itself. A moving
+    // light may not be in the sampled direction at the time of the ray.
+    let light_t = light.hit(&r, 0.001, f64::INFINITY).map(|h| h.t);
+    if !same_distance(light_t, t) || (light_t.is_none() && light.bounding_box().is_some()) {
+        return black;
+    }
+    let light_pdf = light_pdf(lights, &r, t);
+    if light_pdf <= 0.0 {
+        return black;
+    }
+    let weight = power_heuristic(light_pdf, hr.material.pdf(hr, r.dir, wo));
+    f * emitted * (weight / light_pdf)
+}
+
+// Light from `light`, chosen with probability `prob`, reaching `hr` and
+// scattered towards `wo`. It is only found here, so it is not weighted.
+fn sample_delta_light(hr: &HitRecord, wo: Vec3, time: f64, world: &dyn Hittable, light: &dyn DeltaLight,
+    prob: f64) -> Color {
+    let black = Vec3(0.0, 0.0, 0.0);
+    let (dir, dist, arriving) = light.illuminate(&hr.p);
+    if arriving == black {
+        return black;
+    }
+    let f = hr.material.eval(hr, dir, wo);
+    if f == black || world.hit(&Ray { orig: hr.p, dir, time }, 0.001, dist - 0.001).is_some() {
+        return black;
+    }
+    f * arriving / prob
+}
+
+// Weight of light found by sampling the material with density `bsdf_pdf`,
+// reaching the light at distance `t` or the environment if None.
+fn bsdf_weight(bsdf_pdf: Option<f64>, lights: &Lights, r: &Ray, t: Option<f64>) -> f64 {
+    match bsdf_pdf {
+        Some(pdf) => power_heuristic(pdf, light_pdf(lights, r, t)),
+        None => 1.0,
+    }
+}
+
+// Density of `sample_light` sampling the direction of `r` and reaching the
+// light at distance `t`, or the environment if None.
+fn light_pdf(lights: &Lights, r: &Ray, t: Option<f64>) -> f64 {
+    let dir = unit_vector(r.dir);
+    lights.at(t.map(|t| r.at(t))).into_iter()
+        .filter(|(_, light)| same_distance(light.hit(r, 0.001, f64::INFINITY).map(|h| h.t), t))
+        .map(|(i, light)| lights.probability(&r.orig, i) * light.pdf_eval(&r.orig, &dir))
+        .sum()
+}
+
+fn same_distance(a: Option<f64>, b: Option<f64>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => (a - b).abs() <= 1e-6 * b.max(1.0),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
 
-        if let Some((scatter_dir, color_contribution)) = hr.material.scatter(r, &hr, lights) {
-            let scattered_ray = Ray {orig: hr.p, dir: scatter_dir};
-            emitted + color_contribution * ray_color(&scattered_ray, background, world, lights, depth-1)
-        } else {
-            emitted
-        }
-    } else {
-        background.clone()
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    if pdf.is_infinite() {
+        return 1.0;
     }
-}
\ No newline at end of file
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+}
+
+#[test]
+fn light_sampling_test() {
+    use crate::material::Material;
+    use crate::rectangle::XZRect;
+    use crate::lambertian::Lambertian;
+    use crate::metal::{DiffuseLight, Metal};
+    use crate::microfacet::RoughConductor;
+    use crate::texture::SolidColor;
+    use crate::lights::LightSampling;
+
+    let floors: Vec<Box<dyn Material>> = vec![
+        Box::new(Lambertian { albedo: Box::new(SolidColor { color: Vec3(0.8, 0.8, 0.8) }) }),
+        Box::new(Metal { albedo: Vec3(0.8, 0.8, 0.8), fuzz: 0.6 }),
+        Box::new(RoughConductor::preset("gold", 0.5).unwrap()),
+    ];
+    for floor in floors {
+        let light = XZRect {
+            material: Box::new(DiffuseLight { emit: Box::new(SolidColor { color: Vec3(4.0, 4.0, 4.0) }) }),
+            p1: Vec2(-0.5, -0.5), p2: Vec2(0.5, 0.5), k: 2.0,
+        };
+        let world = HittableList { objects: vec![
+            Box::new(XZRect { material: floor, p1: Vec2(-10.0, -10.0), p2: Vec2(10.0, 10.0), k: 0.0 }),
+            Box::new(light),
+        ]};
+        let lights = Lights::new(world.pick_lights(), vec![], LightSampling::Tree);
+        assert_eq!(lights.len(), 1);
+        // The light reflected by the floor is the same, whether the light is
+        // sampled or only found by sampling the floor.
+        let r = Ray { orig: Vec3(0.0, 1.0, -1.5), dir: Vec3(0.0, -1.0, 1.0), time: 0.0 };
+        let mut rng = crate::rng::scene_rng(0);
+        let n = 200000;
+        let mut mean = |lights: &Lights| {
+            (0..n).map(|_| luminance(ray_color(&r, &Vec3(0.0, 0.0, 0.0), &world, lights, 2, &mut rng))).sum::<f64>() / n as f64
+        };
+        let (sampled, unsampled) = (mean(&lights), mean(&Lights::new(vec![], vec![], LightSampling::Tree)));
+        assert!(sampled > 0.05 && (sampled / unsampled - 1.0).abs() < 0.03, "{} {}", sampled, unsampled);
+    }
+}
+
+#[test]
+fn delta_light_test() {
+    use crate::rectangle::XZRect;
+    use crate::lambertian::gray;
+    use crate::delta_light::*;
+
+    let world = XZRect {
+        material: Box::new(gray()),
+        p1: Vec2(-10.0, -10.0), p2: Vec2(10.0, 10.0), k: 0.0,
+    };
+    let point = PointLight { position: Vec3(0.0, 2.0, 0.0), intensity: Vec3(8.0, 8.0, 8.0) };
+    let spot = SpotLight::new(Vec3(0.0, 2.0, 0.0), Vec3(0.0, 1.0, 0.0), Vec3(8.0, 8.0, 8.0), 30.0, 5.0);
+    let sun = DirectionalLight::new(Vec3(0.0, -1.0, 0.0), Vec3(1.0, 1.0, 1.0));
+    let r = Ray { orig: Vec3(0.0, 1.0, 0.0), dir: Vec3(0.0, -1.0, 0.0), time: 0.0 };
+    let mut rng = crate::rng::scene_rng(0);
+    let mut color = |light: &dyn DeltaLight| {
+        let lights = Lights::new(vec![], vec![light], LightSampling::Tree);
+        ray_color(&r, &Vec3(0.0, 0.0, 0.0), &world, &lights, 2, &mut rng)
+    };
+    // The floor reflects albedo / pi of the light arriving at it, the point
+    // light sending 8 / 2^2 to the point below it.
+    let pi = std::f64::consts::PI;
+    assert!((color(&point) - Vec3(1.0, 1.0, 1.0) / pi).near_zero());
+    assert!((color(&sun) - Vec3(0.5, 0.5, 0.5) / pi).near_zero());
+    // The spotlight points away.
+    assert_eq!(color(&spot), Vec3(0.0, 0.0, 0.0));
+    // Lights behind the floor do not light it.
+    let below = PointLight { position: Vec3(0.0, -2.0, 0.0), intensity: Vec3(8.0, 8.0, 8.0) };
+    assert_eq!(color(&below), Vec3(0.0, 0.0, 0.0));
+}
+
+#[test]
+fn moving_light_test() {
+    use crate::rectangle::XZRect;
+    use crate::sphere::MovingSphere;
+    use crate::lambertian::gray;
+    use crate::metal::DiffuseLight;
+    use crate::texture::SolidColor;
+    use crate::environment::EnvironmentMap;
+
+    // The light is sampled where it starts, also after it moved.
+    let world = HittableList { objects: vec![
+        Box::new(XZRect { material: Box::new(gray()), p1: Vec2(-10.0, -10.0), p2: Vec2(10.0, 10.0), k: 0.0 }),
+        Box::new(MovingSphere {
+            center0: Vec3(0.0, 2.0, 0.0), center1: Vec3(1.0, 2.0, 0.0), time0: 0.0, time1: 1.0, radius: 0.5,
+            material: Box::new(DiffuseLight { emit: Box::new(SolidColor { color: Vec3(4.0, 4.0, 4.0) }) }),
+        }),
+    ]};
+    let sky = EnvironmentMap::new(1, 1, vec![Vec3(0.2, 0.2, 0.2)]);
+    let mut objects = world.pick_lights();
+    assert_eq!(objects.len(), 1);
+    objects.extend(sky.light());
+    let lights = Lights::new(objects, vec![], LightSampling::Tree);
+    let mut rng = crate::rng::scene_rng(0);
+    for time in [0.5, 1.0] {
+        let r = Ray { orig: Vec3(0.0, 1.0, -1.5), dir: Vec3(0.0, -1.0, 1.0), time };
+        let n = 100000;
+        let mut mean = |lights: &Lights| {
+            (0..n).map(|_| luminance(ray_color(&r, &sky, &world, lights, 2, &mut rng))).sum::<f64>() / n as f64
+        };
+        let (sampled, unsampled) = (mean(&lights), mean(&Lights::new(vec![], vec![], LightSampling::Tree)));
+        assert!((sampled / unsampled - 1.0).abs() < 0.03, "{} {}", sampled, unsampled);
+    }
+}
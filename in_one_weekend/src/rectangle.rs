@@ -1,13 +1,25 @@
-use std::f64::INFINITY;
-
 use crate::aabb::*;
 use crate::material::*;
 use crate::ray::*;
 use crate::vec3::*;
 use crate::hit::*;
+use crate::rng::Rng;
 
 static EPS: f64 = 1e-4;
 
+// Density per solid angle of sampling the point of `rect`, with area `area`,
+// in direction `dir` from `origin`, with points uniformly distributed over the area.
+fn area_pdf(rect: &dyn Hittable, area: f64, origin: &Vec3, dir: &Vec3) -> f64 {
+	let hr = if let Some(x) = rect.hit(&Ray { orig: *origin, dir: *dir, time: 0.0 }, 0.0001, f64::INFINITY) {
+		x
+	} else {
+		return 0.0;
+	};
+	let d_squared = hr.t * hr.t * dir.length_squared();
+	let cos = dot(hr.normal, *dir).abs() / dir.length();
+	d_squared / (cos * area)
+}
+
 pub struct XYRect {
 	pub material: Box<dyn Material>,
 	// Two corners.
@@ -21,7 +33,7 @@ impl Hittable for XYRect {
 	fn bounding_box(&self) -> Option<AABB> {
 		Some(AABB::new(Vec3(self.p1.0, self.p1.1, self.k-EPS), Vec3(self.p2.0, self.p2.1, self.k+EPS)))
 	}
-	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
 		let t = (self.k - r.orig.2)/r.dir.2;
 		if t<t_min || t>t_max {
 			return None;
@@ -34,8 +46,9 @@ impl Hittable for XYRect {
 			p: v,
 			normal: Vec3(0.0, 0.0, 1.0),
 			t,
-			material: &self.material,
+			material: self.material.as_ref(),
 			front_face: false,
+			object_id: 0,
 			coord: Vec2(
 				(v.0-self.p1.0)/(self.p2.0-self.p1.0),
 				(v.1-self.p1.1)/(self.p2.1-self.p1.1),
@@ -45,20 +58,25 @@ impl Hittable for XYRect {
 		Some(hr)
 	}
 
-	fn gen_random_point(&self, origin: &Vec3) -> Vec3 {
-		Vec3(random_f64(self.p1.0, self.p2.0), random_f64(self.p1.1, self.p2.1), self.k) - *origin
+	fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		Vec3(random_f64(rng, self.p1.0, self.p2.0), random_f64(rng, self.p1.1, self.p2.1), self.k) - *origin
 	}
 
 	fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
-		let hr = if let Some(x) = self.hit(&Ray { orig: *origin, dir: *dir }, 0.0001, INFINITY) {
-			x
+		area_pdf(self, (self.p2.0 - self.p1.0) * (self.p2.1 - self.p1.1), origin, dir)
+	}
+
+	fn pick_lights(&self) -> Vec<&dyn Hittable> {
+		if self.material.is_light() {
+			vec![self]
 		} else {
-			return 0.0;
-		};
+			vec![]
+		}
+	}
+
+	fn power(&self) -> f64 {
 		let area = (self.p2.0 - self.p1.0) * (self.p2.1 - self.p1.1);
-		let d_squared = hr.t * hr.t * dir.length_squared();
-		let cos = dot(hr.normal, *dir).abs() / dir.length();
-		d_squared / (cos * area)
+		diffuse_power(self.material.as_ref(), area, &Vec3((self.p1.0 + self.p2.0) / 2.0, (self.p1.1 + self.p2.1) / 2.0, self.k))
 	}
 }
 
@@ -72,17 +90,10 @@ pub struct XZRect {
 }
 
 impl Hittable for XZRect {
-	fn pick_lights(&self) -> Vec<&dyn Hittable> {
-		if self.material.is_light() {
-			vec![self]
-		} else {
-			vec![]
-		}
-	}
 	fn bounding_box(&self) -> Option<AABB> {
 		Some(AABB::new(Vec3(self.p1.0, self.k-EPS, self.p1.1), Vec3(self.p2.0, self.k+EPS, self.p2.1)))
 	}
-	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
 		let t = (self.k - r.orig.1)/r.dir.1;
 		if t<t_min || t>t_max {
 			return None;
@@ -95,8 +106,9 @@ impl Hittable for XZRect {
 			p: v,
 			normal: Vec3(0.0, 1.0, 0.0),
 			t,
-			material: &self.material,
+			material: self.material.as_ref(),
 			front_face: false,
+			object_id: 0,
 			coord: Vec2(
 				(v.0-self.p1.0)/(self.p2.0-self.p1.0),
 				(v.2-self.p1.1)/(self.p2.1-self.p1.1),
@@ -106,20 +118,25 @@ impl Hittable for XZRect {
 		Some(hr)
 	}
 
-	fn gen_random_point(&self, origin: &Vec3) -> Vec3 {
-		Vec3(random_f64(self.p1.0, self.p2.0), self.k, random_f64(self.p1.1, self.p2.1))-*origin
+	fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		Vec3(random_f64(rng, self.p1.0, self.p2.0), self.k, random_f64(rng, self.p1.1, self.p2.1))-*origin
 	}
 
 	fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
-		let hr = if let Some(x) = self.hit(&Ray { orig: *origin, dir: *dir }, 0.0001, INFINITY) {
-			x
+		area_pdf(self, (self.p2.0 - self.p1.0) * (self.p2.1 - self.p1.1), origin, dir)
+	}
+
+	fn pick_lights(&self) -> Vec<&dyn Hittable> {
+		if self.material.is_light() {
+			vec![self]
 		} else {
-			return 0.0;
-		};
+			vec![]
+		}
+	}
+
+	fn power(&self) -> f64 {
 		let area = (self.p2.0 - self.p1.0) * (self.p2.1 - self.p1.1);
-		let d_squared = hr.t * hr.t * dir.length_squared();
-		let cos = dot(hr.normal, *dir).abs() / dir.length();
-		d_squared / (cos * area)
+		diffuse_power(self.material.as_ref(), area, &Vec3((self.p1.0 + self.p2.0) / 2.0, self.k, (self.p1.1 + self.p2.1) / 2.0))
 	}
 }
 
@@ -136,7 +153,7 @@ impl Hittable for YZRect {
 	fn bounding_box(&self) -> Option<AABB> {
 		Some(AABB::new(Vec3(self.k-EPS, self.p1.0, self.p1.1), Vec3(self.k+EPS, self.p2.0, self.p2.1)))
 	}
-	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
 		let t = (self.k - r.orig.0)/r.dir.0;
 		if t<t_min || t>t_max {
 			return None;
@@ -149,8 +166,9 @@ impl Hittable for YZRect {
 			p: v,
 			normal: Vec3(1.0, 0.0, 0.0),
 			t,
-			material: &self.material,
+			material: self.material.as_ref(),
 			front_face: false,
+			object_id: 0,
 			coord: Vec2(
 				(v.1-self.p1.0)/(self.p2.0-self.p1.0),
 				(v.2-self.p1.1)/(self.p2.1-self.p1.1),
@@ -160,9 +178,51 @@ impl Hittable for YZRect {
 		Some(hr)
 	}
 
-	/*
-	fn gen_random_point(&self) -> Vec3 {
-		Vec3(self.k, random_f64(self.p1.0, self.p2.0), random_f64(self.p1.1, self.p2.1))
+	fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		Vec3(self.k, random_f64(rng, self.p1.0, self.p2.0), random_f64(rng, self.p1.1, self.p2.1)) - *origin
+	}
+
+	fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
+		area_pdf(self, (self.p2.0 - self.p1.0) * (self.p2.1 - self.p1.1), origin, dir)
+	}
+
+	fn pick_lights(&self) -> Vec<&dyn Hittable> {
+		if self.material.is_light() {
+			vec![self]
+		} else {
+			vec![]
+		}
+	}
+
+	fn power(&self) -> f64 {
+		let area = (self.p2.0 - self.p1.0) * (self.p2.1 - self.p1.1);
+		diffuse_power(self.material.as_ref(), area, &Vec3(self.k, (self.p1.0 + self.p2.0) / 2.0, (self.p1.1 + self.p2.1) / 2.0))
+	}
+}
+#[test]
+fn light_test() {
+	use crate::metal::*;
+	use crate::texture::*;
+
+	let light = || Box::new(DiffuseLight { emit: Box::new(SolidColor { color: Vec3(1.0, 1.0, 1.0) }) });
+	let (p1, p2) = (Vec2(-1.0, -2.0), Vec2(1.0, 2.0));
+	let rects: [Box<dyn Hittable>; 3] = [
+		Box::new(XYRect { material: light(), p1, p2, k: 2.0 }),
+		Box::new(XZRect { material: light(), p1, p2, k: 2.0 }),
+		Box::new(YZRect { material: light(), p1, p2, k: 2.0 }),
+	];
+	let origin = Vec3(0.5, 0.0, 0.0);
+	let mut rng = crate::rng::scene_rng(0);
+	for rect in rects {
+		assert_eq!(rect.pick_lights().len(), 1);
+		// Sampled points lie on the rectangle and the density integrates to one.
+		for _ in 0..100 {
+			let v = rect.gen_random_point(&origin, &mut rng);
+			assert!(rect.pdf_eval(&origin, &v) > 0.0);
+		}
+		let n = 100000;
+		let total = (0..n).map(|_| rect.pdf_eval(&origin, &random_unit_vector(&mut rng))).sum::<f64>()
+			* 4.0 * std::f64::consts::PI / n as f64;
+		assert!((total - 1.0).abs() < 0.03, "{}", total);
 	}
-	*/
-}
\ No newline at end of file
+}
@@ -0,0 +1,362 @@
+// Rendering frames of a scene.
+//
+// The image is split into square tiles, which are rendered in parallel with
+// all samples of a pass and added to a shared frame buffer. With `save_temps`
+// set, a pass has that many samples and the image rendered so far is written
+// after each pass; otherwise all samples are rendered in one pass.
+//
+// The sums of the samples can be saved to a checkpoint file after each pass
+// and loaded again to continue the render, or to add samples to a finished one.
+use std::sync::Mutex;
+
+use indicatif::ProgressBar;
+use rand::Rng as _;
+use rayon::prelude::*;
+
+use crate::vec3::*;
+use crate::camera::*;
+use crate::hit::*;
+use crate::lights::Lights;
+use crate::ray::*;
+use crate::rng;
+use crate::scene::*;
+use crate::output::*;
+use crate::aov::*;
+
+const TILE_SIZE: usize = 16;
+// Camera rays per pixel used to find the AOVs.
+const FEATURE_SAMPLES: usize = 4;
+
+// Pixels `x` in `x0..x1`, `y` in `y0..y1`, rows counted from the bottom like in Film.
+struct Tile {
+    x0: usize,
+    x1: usize,
+    y0: usize,
+    y1: usize,
+}
+
+impl Tile {
+    // Indices of the tile's pixels in an image of width `width`, row by row.
+    fn indices(&self, width: usize) -> impl Iterator<Item = usize> + '_ {
+        (self.y0..self.y1).flat_map(move |y| (self.x0..self.x1).map(move |x| y*width + x))
+    }
+}
+
+fn tiles(width: usize, height: usize) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for y0 in (0..height).step_by(TILE_SIZE) {
+        for x0 in (0..width).step_by(TILE_SIZE) {
+            tiles.push(Tile { x0, x1: (x0 + TILE_SIZE).min(width), y0, y1: (y0 + TILE_SIZE).min(height) });
+        }
+    }
+    tiles
+}
+
+// Running sums of the samples of a pixel.
+#[derive(Clone, Copy)]
+struct PixelStats {
+    sum: Color,
+    luminance_sum: f64,
+    luminance_sq_sum: f64,
+    n: usize,
+}
+
+impl PixelStats {
+    fn new() -> PixelStats {
+        PixelStats { sum: Vec3(0.0, 0.0, 0.0), luminance_sum: 0.0, luminance_sq_sum: 0.0, n: 0 }
+    }
+
+    fn add(&mut self, c: Color) {
+        let l = luminance(c);
+        self.sum = self.sum + c;
+        self.luminance_sum += l;
+        self.luminance_sq_sum += l * l;
+        self.n += 1;
+    }
+
+    fn mean(&self) -> Color {
+        (1.0/self.n as f64) * self.sum
+    }
+
+    // Estimated standard error of the displayed brightness of the pixel.
+    fn error(&self) -> f64 {
+        let n = self.n as f64;
+        let mean = self.luminance_sum / n;
+        let variance = ((self.luminance_sq_sum - self.luminance_sum * mean) / (n - 1.0)).max(0.0);
+        // Pixels are displayed roughly as sqrt(x), which changes errors by
+        // 1 / (2 sqrt(x)). Dark pixels are limited so that they can converge.
+        (variance / n).sqrt() / (2.0 * mean.sqrt().max(0.1))
+    }
+
+    fn converged(&self, s: &Scene) -> bool {
+        s.adaptive_threshold > 0.0 && self.n >= s.min_samples.max(2) && self.error() < s.adaptive_threshold
+    }
+}
+
+// Adds samples to every pixel of the tile until it has `samples` of them.
+// `stats` holds the tile's pixels row by row. In adaptive mode pixels that
+// have converged are skipped.
+#[allow(clippy::too_many_arguments)]
+fn render_tile(world: &dyn Hittable, lights: &Lights, s: &Scene, cam: &Camera, frame: usize,
+    tile: &Tile, samples: usize, stats: &mut [PixelStats]) {
+    let (image_width, image_height) = (s.image_width, s.image_height());
+    for (pixel, stats) in tile.indices(image_width).zip(stats.iter_mut()) {
+        let (i, j) = (pixel % image_width, pixel / image_width);
+        // Samples are added in order, so the sum does not depend on the tiling, the
+        // threads or whether the render was continued from a checkpoint.
+        while stats.n < samples && !stats.converged(s) {
+            let sample = stats.n;
+            let mut rng = rng::pixel_rng(s.seed, frame, sample, pixel);
+            let u = (i as f64 + rng.gen::<f64>()) / (image_width as f64 - 1.0);
+            let v = (j as f64 + rng.gen::<f64>()) / (image_height as f64 - 1.0);
+            let r = cam.get_ray(u, v, &mut rng);
+            stats.add(ray_color(&r, s.environment(), world, lights, s.max_depth, &mut rng));
+        }
+    }
+}
+
+// Renders all tiles in parallel until every pixel in `stats` has `samples`
+// samples. Progress is reported to `bar` in samples of single pixels,
+// skipped samples of converged pixels count as done.
+#[allow(clippy::too_many_arguments)]
+fn render_pass(bar: &ProgressBar, world: &dyn Hittable, lights: &Lights, s: &Scene, cam: &Camera,
+    frame: usize, tiles: &[Tile], stats: &Mutex<Vec<PixelStats>>, samples: usize) {
+    let width = s.image_width;
+    tiles.par_iter().for_each(|tile| {
+        // Tiles do not overlap, the lock is only held while copying.
+        let mut tile_stats: Vec<PixelStats> = {
+            let stats = stats.lock().unwrap();
+            tile.indices(width).map(|i| stats[i]).collect()
+        };
+        let todo: usize = tile_stats.iter().map(|p| samples.saturating_sub(p.n)).sum();
+        render_tile(world, lights, s, cam, frame, tile, samples, &mut tile_stats);
+        let mut stats = stats.lock().unwrap();
+        for (i, p) in tile.indices(width).zip(tile_stats) {
+            stats[i] = p;
+        }
+        bar.inc(todo as u64);
+    });
+}
+
+// Finds the AOVs of every pixel from the first surfaces hit by camera rays.
+// The rays are the same as those of the first samples of the frame.
+fn render_features(world: &dyn Hittable, s: &Scene, cam: &Camera, frame: usize) -> Features {
+    let (image_width, image_height) = (s.image_width, s.image_height());
+    let pixels: Vec<_> = (0..image_width*image_height).into_par_iter().map(|pixel| {
+        let (i, j) = (pixel % image_width, pixel / image_width);
+        let mut albedo = Vec3(0.0, 0.0, 0.0);
+        let mut normal = Vec3(0.0, 0.0, 0.0);
+        let mut first = (f64::INFINITY, Vec3(0.0, 0.0, 0.0), 0);
+        for sample in 0..FEATURE_SAMPLES {
+            let mut rng = rng::pixel_rng(s.seed, frame, sample, pixel);
+            let u = (i as f64 + rng.gen::<f64>()) / (image_width as f64 - 1.0);
+            let v = (j as f64 + rng.gen::<f64>()) / (image_height as f64 - 1.0);
+            let r = cam.get_ray(u, v, &mut rng);
+            if let Some(hr) = world.hit(&r, 0.001, f64::INFINITY) {
+                albedo = albedo + hr.material.albedo(hr.coord, &hr.p);
+                normal = normal + hr.normal;
+                if sample == 0 {
+                    first = (hr.t * r.dir.length(), hr.p, hr.object_id);
+                }
+            } else {
+                albedo = albedo + s.environment().color(&r.dir);
+            }
+        }
+        let n = FEATURE_SAMPLES as f64;
+        (albedo / n, normal / n, first)
+    }).collect();
+    Features {
+        albedo: pixels.iter().map(|p| p.0).collect(),
+        normal: pixels.iter().map(|p| p.1).collect(),
+        depth: pixels.iter().map(|p| p.2.0).collect(),
+        position: pixels.iter().map(|p| p.2.1).collect(),
+        id: pixels.iter().map(|p| p.2.2).collect(),
+    }
+}
+
+// A rendered frame and the number of samples taken for each of its pixels.
+// The AOVs are only rendered when they are needed for the denoiser or the output.
+pub struct Frame {
+    pub film: Film,
+    pub samples: Vec<usize>,
+    pub features: Option<Features>,
+}
+
+// Where the sums of the samples of a frame are saved after every pass.
+pub struct Checkpoint {
+    pub path: String,
+    // Continue the render from the file if it exists.
+    pub resume: bool,
+}
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"iowckpt1";
+const CHECKPOINT_HEADER: usize = 8 + 4*8;
+const CHECKPOINT_PIXEL: usize = 6*8;
+
+// The file holds the magic, then width, height, seed and frame number as u64,
+// then for every pixel the five sums as f64 and the number of samples as u64,
+// all little endian. It is replaced only once completely written.
+fn save_checkpoint(path: &str, s: &Scene, frame: usize, stats: &[PixelStats]) -> std::io::Result<()> {
+    let mut data = Vec::with_capacity(CHECKPOINT_HEADER + CHECKPOINT_PIXEL * stats.len());
+    data.extend_from_slice(CHECKPOINT_MAGIC);
+    for x in [s.image_width as u64, s.image_height() as u64, s.seed, frame as u64] {
+        data.extend_from_slice(&x.to_le_bytes());
+    }
+    for p in stats {
+        for x in [p.sum.0, p.sum.1, p.sum.2, p.luminance_sum, p.luminance_sq_sum] {
+            data.extend_from_slice(&x.to_le_bytes());
+        }
+        data.extend_from_slice(&(p.n as u64).to_le_bytes());
+    }
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)
+}
+
+// Fails if the checkpoint is not of frame `frame` of a render with the same
+// size and seed. Other changes to the scene are not detected.
+fn load_checkpoint(path: &str, s: &Scene, frame: usize) -> Result<Vec<PixelStats>, String> {
+    let data = std::fs::read(path).map_err(|e| format!("failed to read '{}': {}", path, e))?;
+    let pixels = s.image_width * s.image_height();
+    if data.len() < CHECKPOINT_HEADER || &data[..8] != CHECKPOINT_MAGIC {
+        return Err(format!("'{}' is not a checkpoint", path));
+    }
+    let word = |i: usize| { u64::from_le_bytes(data[i..i + 8].try_into().unwrap()) };
+    let header = [s.image_width as u64, s.image_height() as u64, s.seed, frame as u64];
+    if (0..4).any(|i| word(8 + 8*i) != header[i]) || data.len() != CHECKPOINT_HEADER + CHECKPOINT_PIXEL * pixels {
+        return Err(format!("'{}' is a checkpoint of a different image size, seed or frame", path));
+    }
+    Ok((0..pixels).map(|p| {
+        let x = |i: usize| { f64::from_bits(word(CHECKPOINT_HEADER + CHECKPOINT_PIXEL*p + 8*i)) };
+        PixelStats {
+            sum: Vec3(x(0), x(1), x(2)),
+            luminance_sum: x(3),
+            luminance_sq_sum: x(4),
+            n: word(CHECKPOINT_HEADER + CHECKPOINT_PIXEL*p + 40) as usize,
+        }
+    }).collect())
+}
+
+// Renders all samples of frame number `frame` of the scene seen from `v`,
+// reporting progress to `bar`. A resumed frame continues with the
+// samples after those in the checkpoint.
+pub fn build_frame(bar: &ProgressBar, world: &dyn Hittable, lights: &Lights, v: &View, s: &Scene,
+    frame: usize, checkpoint: Option<&Checkpoint>) -> Result<Frame, String> {
+    let (width, height) = (s.image_width, s.image_height());
+    let cam = build_camera(v, s.aspect_ratio, world);
+    let denoiser = s.denoise.denoiser();
+    let features = if denoiser.is_some() || !s.aovs.is_empty() {
+        Some(render_features(world, s, &cam, frame))
+    } else {
+        None
+    };
+    let average = |stats: &[PixelStats]| {
+        let film = Film { width, height, pixels: stats.iter().map(PixelStats::mean).collect() };
+        match (&denoiser, &features) {
+            (Some(denoiser), Some(features)) => denoiser.denoise(&film, features),
+            _ => film,
+        }
+    };
+
+    let tiles = tiles(width, height);
+    let stats = match checkpoint {
+        Some(c) if c.resume && std::path::Path::new(&c.path).exists() => load_checkpoint(&c.path, s, frame)?,
+        _ => vec![PixelStats::new(); width*height],
+    };
+    let mut done = stats.iter().map(|p| p.n).max().unwrap_or(0);
+    bar.inc((done.min(s.samples_per_pixel) * width*height) as u64);
+    let stats = Mutex::new(stats);
+    let pass = if s.save_temps > 0 { s.save_temps } else { s.samples_per_pixel };
+    while done < s.samples_per_pixel {
+        // Passes end at multiples of `pass`, also when resuming.
+        let samples = ((done / pass + 1) * pass).min(s.samples_per_pixel);
+        render_pass(bar, world, lights, s, &cam, frame, &tiles, &stats, samples);
+        done = samples;
+        if let Some(c) = checkpoint {
+            save_checkpoint(&c.path, s, frame, &stats.lock().unwrap())
+                .map_err(|e| format!("failed to write '{}': {}", c.path, e))?;
+        }
+        if s.save_temps > 0 && done % s.save_temps == 0 {
+            average(&stats.lock().unwrap()).to_rgba8(&s.tone_map).save(&s.temp_path).expect("temp save fail");
+        }
+    }
+    let stats = stats.into_inner().unwrap();
+    Ok(Frame { film: average(&stats), samples: stats.iter().map(|p| p.n).collect(), features })
+}
+
+// A view of the scene rendered a few samples at a time, used for previews.
+// Only the first frame is rendered and the denoiser is not applied.
+pub struct Progressive {
+    cam: Camera,
+    tiles: Vec<Tile>,
+    stats: Mutex<Vec<PixelStats>>,
+}
+
+impl Progressive {
+    pub fn new(world: &dyn Hittable, v: &View, s: &Scene) -> Progressive {
+        Progressive {
+            cam: build_camera(v, s.aspect_ratio, world),
+            tiles: tiles(s.image_width, s.image_height()),
+            stats: Mutex::new(vec![PixelStats::new(); s.image_width * s.image_height()]),
+        }
+    }
+
+    // Samples taken per pixel so far.
+    pub fn samples(&self) -> usize {
+        self.stats.lock().unwrap().iter().map(|p| p.n).max().unwrap_or(0)
+    }
+
+    // Takes `n` more samples of every pixel.
+    pub fn add_samples(&self, world: &dyn Hittable, lights: &Lights, s: &Scene, n: usize) {
+        let samples = self.samples() + n;
+        render_pass(&ProgressBar::hidden(), world, lights, s, &self.cam, 0, &self.tiles, &self.stats, samples);
+    }
+
+    pub fn film(&self, s: &Scene) -> Film {
+        let pixels = self.stats.lock().unwrap().iter().map(PixelStats::mean).collect();
+        Film { width: s.image_width, height: s.image_height(), pixels }
+    }
+}
+
+#[test]
+fn pixel_stats_test() {
+    let mut s = Scene { adaptive_threshold: 0.01, min_samples: 4, ..Scene::default() };
+    let mut flat = PixelStats::new();
+    let mut noisy = PixelStats::new();
+    for i in 0..4 {
+        flat.add(Vec3(0.5, 0.5, 0.5));
+        noisy.add(Vec3(1.0, 1.0, 1.0) * (i % 2) as f64);
+    }
+    assert_eq!(flat.mean(), Vec3(0.5, 0.5, 0.5));
+    assert!(flat.converged(&s));
+    assert!(!noisy.converged(&s));
+    s.adaptive_threshold = 0.0;
+    assert!(!flat.converged(&s));
+}
+
+#[test]
+fn checkpoint_test() {
+    use crate::bvh_node::BVHNode;
+    use crate::lights::LightSampling;
+
+    let mut rng = rng::scene_rng(0);
+    let setup = crate::scenes::builtin("two_spheres", &mut rng).unwrap();
+    let world = BVHNode::new(setup.objects);
+    let lights = Lights::new(world.pick_lights(), vec![], LightSampling::Tree);
+    let mut s = Scene { aspect_ratio: 1.0, image_width: 8, samples_per_pixel: 4, save_temps: 0, ..setup.scene };
+    let path = std::env::temp_dir().join("in_one_weekend_checkpoint_test").to_string_lossy().into_owned();
+    let _ = std::fs::remove_file(&path);
+    let c = Checkpoint { path: path.clone(), resume: true };
+    let bar = ProgressBar::hidden();
+
+    build_frame(&bar, &world, &lights, &setup.view, &s, 0, Some(&c)).unwrap();
+    // Adding samples later gives the same image as taking them all at once.
+    s.samples_per_pixel = 8;
+    let resumed = build_frame(&bar, &world, &lights, &setup.view, &s, 0, Some(&c)).unwrap();
+    let direct = build_frame(&bar, &world, &lights, &setup.view, &s, 0, None).unwrap();
+    assert_eq!(resumed.film.pixels, direct.film.pixels);
+    assert_eq!(resumed.samples, vec![8; 64]);
+
+    assert!(build_frame(&bar, &world, &lights, &setup.view, &s, 1, Some(&c)).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,65 @@
+// Random number generation.
+//
+// All randomness is derived from the seed of the scene, so that renders are
+// reproducible across runs and thread counts. Every sample of every pixel
+// gets its own generator, independent of the order pixels are rendered in.
+// PCG is specified exactly, unlike rand's SmallRng, so the same seed gives
+// the same image on every platform and with every version of rand.
+pub type Rng = rand_pcg::Pcg64Mcg;
+
+// Streams keep the generators for different purposes apart.
+const SCENE_STREAM: u64 = 1;
+const PIXEL_STREAM: u64 = 2;
+
+// SplitMix64 finalizer, a bijection on u64 with good avalanche.
+fn mix(mut x: u64) -> u64 {
+	x = x.wrapping_add(0x9e3779b97f4a7c15);
+	x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+	x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+	x ^ (x >> 31)
+}
+
+// Hashes a sequence of numbers into one.
+pub fn hash(values: &[u64]) -> u64 {
+	values.iter().fold(0, |h, v| mix(h ^ mix(*v)))
+}
+
+// Maps a hash to a uniformly distributed number in [0, 1).
+pub fn unit_f64(h: u64) -> f64 {
+	(h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Seeds a generator with 128 bits from the hash `h`.
+fn from_hash(h: u64) -> Rng {
+	Rng::new((mix(h) as u128) << 64 | h as u128)
+}
+
+// Generator used while building the scene: random layouts, noise textures.
+pub fn scene_rng(seed: u64) -> Rng {
+	from_hash(hash(&[SCENE_STREAM, seed]))
+}
+
+// Generator for sample `sample` of pixel `pixel` in frame `frame`.
+pub fn pixel_rng(seed: u64, frame: usize, sample: usize, pixel: usize) -> Rng {
+	from_hash(hash(&[PIXEL_STREAM, seed, frame as u64, sample as u64, pixel as u64]))
+}
+
+#[test]
+fn pixel_rng_test() {
+	use rand::Rng as _;
+
+	let a = pixel_rng(1, 0, 3, 7).gen::<u64>();
+	assert_eq!(a, pixel_rng(1, 0, 3, 7).gen::<u64>());
+	assert_ne!(a, pixel_rng(2, 0, 3, 7).gen::<u64>());
+	assert_ne!(a, pixel_rng(1, 0, 7, 3).gen::<u64>());
+	assert_ne!(a, pixel_rng(1, 1, 3, 7).gen::<u64>());
+}
+
+#[test]
+fn stable_test() {
+	use rand::Rng as _;
+
+	// The golden images depend on these exact numbers.
+	assert_eq!(scene_rng(0).gen::<u64>(), 0xcbca7ce3b1af5b1e);
+	assert_eq!(pixel_rng(0, 0, 0, 0).gen::<u64>(), 0x9913039fcecf9852);
+}
@@ -0,0 +1,145 @@
+use crate::vec3::*;
+use crate::denoise::Denoise;
+use crate::aov::Aov;
+use crate::tonemap::ToneMap;
+use crate::environment::Environment;
+use crate::hit::Hittable;
+use crate::camera::Aperture;
+use crate::lights::*;
+use crate::delta_light::DeltaLight;
+
+#[derive(Clone)]
+pub struct View {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub v_up: Vec3,
+    pub vfov_deg: f64,
+    // Diameter of the lens, 0 for a pinhole camera.
+    pub aperture: f64,
+    pub aperture_shape: Aperture,
+    pub dist_to_focus: f64,
+    // Focuses on whatever is seen in the image center instead of at `dist_to_focus`.
+    pub autofocus: bool,
+    // Tilt-shift lens: the plane in focus is turned by `tilt_deg` degrees
+    // around the horizontal axis, receding towards the top of the image for
+    // positive angles. `shift` moves the image in fractions of its width and
+    // height without turning the camera.
+    pub tilt_deg: f64,
+    pub shift: Vec2,
+    // Objects that move are blurred over their motion between these times.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+impl Default for View {
+    fn default() -> View {
+        View {
+            look_from: Vec3(13.0, 2.0, 3.0),
+            look_at: Vec3(0.0, 0.0, 0.0),
+            v_up: Vec3(0.0, 1.0, 0.0),
+            dist_to_focus: 10.0,
+            aperture: 0.0,
+            aperture_shape: Aperture::Circle,
+            autofocus: false,
+            tilt_deg: 0.0,
+            shift: Vec2(0.0, 0.0),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            vfov_deg: 40.0,
+        }
+    }
+}
+
+pub struct Scene {
+    pub aspect_ratio: f64,
+    pub image_width: usize,
+    // Number of samples per pixel, the most samples per pixel in adaptive mode.
+    pub samples_per_pixel: usize,
+    // Adaptive sampling stops sampling a pixel once the estimated standard
+    // error of its displayed brightness (in [0, 1]) is below the threshold,
+    // after at least `min_samples` samples. 0 disables adaptive sampling.
+    pub adaptive_threshold: f64,
+    pub min_samples: usize,
+    pub background: Vec3,
+    // Light from all directions, replaces the background color if set.
+    pub environment: Option<Box<dyn Environment>>,
+    // Point, spot and directional lights, which are not objects of the world.
+    pub delta_lights: Vec<Box<dyn DeltaLight>>,
+    pub max_depth: i32,
+    // How the light to sample at every bounce is chosen.
+    pub light_sampling: LightSampling,
+    // Denoiser applied to the final and the intermediate images.
+    pub denoise: Denoise,
+    // Auxiliary images rendered with every frame.
+    pub aovs: Vec<Aov>,
+    // Conversion of the rendered colors for 8 and 16-bit images.
+    pub tone_map: ToneMap,
+    // Every `save_temps` samples the image rendered so far is written to `temp_path`.
+    pub save_temps: usize,
+    pub temp_path: String,
+    // All random numbers of the render are derived from the seed, see rng.rs.
+    pub seed: u64,
+}
+
+impl Scene {
+    pub fn image_height(&self) -> usize {
+        ((self.image_width as f64) / self.aspect_ratio).round() as usize
+    }
+
+    // What rays that hit nothing see.
+    pub fn environment(&self) -> &dyn Environment {
+        match &self.environment {
+            Some(e) => e.as_ref(),
+            None => &self.background,
+        }
+    }
+
+    // The lights to sample: those among the objects of `world`, the
+    // environment and the delta lights.
+    pub fn lights<'a>(&'a self, world: &'a dyn Hittable) -> Lights<'a> {
+        let mut lights = world.pick_lights();
+        lights.extend(self.environment().light());
+        let delta = self.delta_lights.iter().map(|l| l.as_ref()).collect();
+        Lights::new(lights, delta, self.light_sampling)
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene {
+            aspect_ratio: 16.0 / 9.0,
+            image_width: 400,
+            max_depth: 50,
+            light_sampling: LightSampling::default(),
+            samples_per_pixel: 36,
+            adaptive_threshold: 0.0,
+            min_samples: 16,
+            background: Vec3(0.7, 0.8, 1.0),
+            environment: None,
+            delta_lights: vec![],
+            denoise: Denoise::None,
+            aovs: vec![],
+            tone_map: ToneMap::default(),
+            save_temps: 30,
+            temp_path: "tmp.png".to_string(),
+            seed: 0,
+        }
+    }
+}
+
+// Maps the base view and the time within the animation (in [0, 1)) to the view of a frame.
+pub type ViewFn = Box<dyn Fn(&View, f64) -> View + Sync>;
+
+pub struct Animation {
+    pub num_frames: usize,
+    pub f: ViewFn,
+}
+
+impl Default for Animation {
+    fn default() -> Animation {
+        Animation {
+            num_frames: 1,
+            f: Box::new(|v, _t| { v.clone() }),
+        }
+    }
+}
@@ -0,0 +1,837 @@
+// Text scene description format.
+//
+// A scene file is a sequence of lines, each holding one directive followed by
+// `key=value` arguments. Everything after `#` is a comment. For example:
+//
+//   scene aspect_ratio=1 image_width=600 samples_per_pixel=1000 background=0,0,0
+//   view look_from=278,278,-800 look_at=278,278,0 vfov_deg=40
+//   animation num_frames=100 look_from=556,278,-800
+//   texture checker checker odd=0.2,0.3,0.1 even=0.9,0.9,0.9
+//   material ground lambertian albedo=checker
+//   material light diffuse_light emit=15,15,15
+//   sphere center=0,-1000,0 radius=1000 material=ground
+//   xz_rect p1=213,227 p2=343,332 k=554 material=light
+//   box p0=0,0,0 p1=165,165,165 material=ground rotate_y=-18 translate=130,0,65
+//   triangle v0=0,0,0 v1=1,0,0 v2=0,1,0 material=ground
+//   material smoke isotropic albedo=0.5,0.5,0.5
+//   sphere center=0,1,0 radius=1 material=smoke density=0.2
+//   mesh path=bunny.obj material=ground scale=10 rotate_y=45 translate=0,1,0
+//
+// `scene` and `view` set the fields of `Scene` and `View` with the same names,
+// `scene` also takes the tone mapping settings `exposure`, `tonemap` and `dither`.
+// Its `light_sampling` chooses the light to sample at every bounce: `uniform`,
+// in proportion to the lights' `power` or by a `tree` of lights preferring
+// near and bright ones (the default).
+// `environment` is an equirectangular HDR or EXR image lighting the scene from
+// all directions instead of the background color, made brighter by
+// `environment_scale` and turned by `environment_rotate_y` degrees.
+// `sky` replaces the background with a clear daylight sky, for example
+//   sky sun_elevation=30 sun_azimuth=120 turbidity=3 ground_albedo=0.3 sun=true
+// with the sun `sun_elevation` degrees above the horizon and `sun_azimuth`
+// degrees from -z towards +x. `turbidity` is the haziness of the air from 1.7
+// to 10, the ground below the horizon reflects `ground_albedo` of the light
+// and `sun=false` leaves out the sun itself. All keys are optional.
+// `view` also shapes the lens opening with `aperture_blades` (a polygon, turned
+// by `aperture_rotation` degrees) or `aperture_mask` (an image whose bright parts
+// let light through), focuses on the image center with `autofocus=true` and
+// tilts and shifts the lens with `tilt_deg` and `shift=x,y`.
+// Objects moving during the time interval [0, 1] are blurred over the times
+// between the `view` keys `shutter_open` and `shutter_close`.
+// `animation` sets the number of frames and the view at the end of the
+// animation; fields that are not given stay as in `view`.
+// `conductor` is a rough metal, either a `metal` of gold, copper, aluminium or
+// silver or given by its complex index of refraction `eta` + i`k` per color
+// channel, e.g. `material gold conductor metal=gold roughness=0.3`.
+// `rough_dielectric` is frosted glass with index of refraction `ir`. Their
+// `roughness` goes from 0 for a mirror to 1.
+// `point_light position=x,y,z intensity=r,g,b` shines in all directions,
+// `spot_light` also takes the `direction` it points to, the `angle_deg`
+// between its axis and the edge of its cone (30 by default) and the outer
+// `falloff_deg` degrees of the cone over which it fades (0 by default).
+// `directional_light direction=x,y,z irradiance=r,g,b` is parallel light
+// shining along `direction`, like sunlight. These lights have no size and are
+// never seen, they only light the objects.
+// Textures and materials are named and must be defined before they are used.
+// Wherever a texture is expected a color `r,g,b` can be used instead.
+// A mesh takes its materials from the MTL files referenced by the OBJ file,
+// `material` is used for faces without one and defaults to a gray lambertian.
+// Every object with `density` becomes the boundary of a volume of that
+// density, e.g. smoke, scattering with the object's material (usually
+// `isotropic albedo=<texture>`).
+// Every object can be placed with `scale` (a number or `x,y,z`), `rotate_x`,
+// `rotate_y`, `rotate_z` (in degrees) and `translate`, applied in this order.
+// It moves with `move=x,y,z`, the distance covered by time 1, and turns with
+// `spin=x,y,z`, the degrees turned around the axes through its position by
+// time 1. A sphere with `center1` moves from `center` to there instead.
+// Lights moving with `move` or `spin` are not sampled, they are only found by
+// rays scattered towards them and so render noisier. A light sphere with
+// `center1` is sampled where it is at time 0.
+// Relative paths are resolved against the directory of the scene file.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::Rng as _;
+
+use crate::vec3::*;
+use crate::hit::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::perlin::*;
+use crate::lambertian::*;
+use crate::metal::*;
+use crate::dielectric::*;
+use crate::microfacet::*;
+use crate::isotropic::*;
+use crate::sphere::*;
+use crate::rectangle::*;
+use crate::box_shape::*;
+use crate::constant_medium::*;
+use crate::triangle::*;
+use crate::mesh::*;
+use crate::transform::*;
+use crate::scene::*;
+use crate::camera::*;
+use crate::denoise::Denoise;
+use crate::tonemap::Operator;
+use crate::lights::LightSampling;
+use crate::delta_light::*;
+use crate::environment::EnvironmentMap;
+use crate::sky::*;
+use crate::rng::*;
+
+// Everything needed to render a scene.
+pub struct SceneSetup {
+	pub scene: Scene,
+	pub view: View,
+	pub animation: Animation,
+	pub objects: Vec<Box<dyn Hittable>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SceneError {
+	// 1-based line number of the offending directive.
+	pub line: usize,
+	pub message: String,
+}
+
+impl fmt::Display for SceneError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+// Reads and parses the scene file at `path`. Errors are formatted as `path:line: message`.
+// Random textures are seeded from `rng`.
+pub fn load(path: &str, rng: &mut Rng) -> Result<SceneSetup, String> {
+	let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+	let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+	parse(&text, base_dir, rng).map_err(|e| format!("{}:{}: {}", path, e.line, e.message))
+}
+
+pub fn parse(text: &str, base_dir: &Path, rng: &mut Rng) -> Result<SceneSetup, SceneError> {
+	let mut parser = Parser {
+		base_dir,
+		rng,
+		setup: SceneSetup {
+			scene: Scene::default(),
+			view: View::default(),
+			animation: Animation::default(),
+			objects: vec![],
+		},
+		textures: HashMap::new(),
+		materials: HashMap::new(),
+	};
+	let mut last_line = 0;
+	for (i, line) in text.lines().enumerate() {
+		last_line = i + 1;
+		let line = line.split('#').next().unwrap();
+		let mut words = line.split_whitespace();
+		let directive = match words.next() {
+			Some(d) => d,
+			None => continue,
+		};
+		parser.directive(directive, words.collect())
+			.map_err(|message| SceneError { line: i + 1, message })?;
+	}
+	if parser.setup.objects.is_empty() {
+		return Err(SceneError { line: last_line, message: "scene has no objects".to_string() });
+	}
+	Ok(parser.setup)
+}
+
+#[derive(Clone)]
+enum TextureSpec {
+	Solid(Color),
+	Checker(Box<TextureSpec>, Box<TextureSpec>),
+	// Scale and seed of the noise, objects using the same texture get the same noise.
+	Noise(f64, u64),
+	Image(ImageTexture),
+}
+
+impl TextureSpec {
+	fn build(&self) -> Box<dyn Texture> {
+		match self {
+			TextureSpec::Solid(color) => Box::new(SolidColor { color: *color }),
+			TextureSpec::Checker(odd, even) => Box::new(CheckerTexture { odd: odd.build(), even: even.build() }),
+			TextureSpec::Noise(scale, seed) => Box::new(NoiseTexture::new(*scale, &mut scene_rng(*seed))),
+			TextureSpec::Image(img) => Box::new(img.clone()),
+		}
+	}
+}
+
+// Materials are owned by the objects using them, so a named material is kept
+// as a description and a new instance is built for every object.
+#[derive(Clone)]
+enum MaterialSpec {
+	Lambertian(TextureSpec),
+	Metal(Color, f64),
+	Dielectric(f64),
+	// Index of refraction `eta` + i`k` and roughness.
+	Conductor(Color, Color, f64),
+	// Index of refraction and roughness.
+	RoughDielectric(f64, f64),
+	DiffuseLight(TextureSpec),
+	Isotropic(TextureSpec),
+}
+
+impl MaterialSpec {
+	fn build(&self) -> Box<dyn Material> {
+		match self {
+			MaterialSpec::Lambertian(albedo) => Box::new(Lambertian { albedo: albedo.build() }),
+			MaterialSpec::Metal(albedo, fuzz) => Box::new(Metal { albedo: *albedo, fuzz: *fuzz }),
+			MaterialSpec::Dielectric(ir) => Box::new(Dielectric { ir: *ir }),
+			MaterialSpec::Conductor(eta, k, roughness) => Box::new(RoughConductor { eta: *eta, k: *k, roughness: *roughness }),
+			MaterialSpec::RoughDielectric(ir, roughness) => Box::new(RoughDielectric { ir: *ir, roughness: *roughness }),
+			MaterialSpec::DiffuseLight(emit) => Box::new(DiffuseLight { emit: emit.build() }),
+			MaterialSpec::Isotropic(albedo) => Box::new(Isotropic { albedo: albedo.build() }),
+		}
+	}
+}
+
+struct Parser<'a> {
+	base_dir: &'a Path,
+	rng: &'a mut Rng,
+	setup: SceneSetup,
+	textures: HashMap<String, TextureSpec>,
+	materials: HashMap<String, MaterialSpec>,
+}
+
+impl Parser<'_> {
+	fn directive(&mut self, directive: &str, words: Vec<&str>) -> Result<(), String> {
+		match directive {
+			"scene" => self.scene(Args::new(directive, &words)?),
+			"view" => self.view(Args::new(directive, &words)?),
+			"animation" => self.animation(Args::new(directive, &words)?),
+			"sky" => self.sky(Args::new(directive, &words)?),
+			"point_light" | "spot_light" | "directional_light" => {
+				let light = self.delta_light(directive, Args::new(directive, &words)?)?;
+				self.setup.scene.delta_lights.push(light);
+				Ok(())
+			}
+			"texture" => {
+				let (name, kind, args) = named(directive, &words)?;
+				let texture = self.texture(kind, args)?;
+				define(&mut self.textures, "texture", name, texture)
+			}
+			"material" => {
+				let (name, kind, args) = named(directive, &words)?;
+				let material = self.material(kind, args)?;
+				define(&mut self.materials, "material", name, material)
+			}
+			_ => {
+				let object = self.object(directive, Args::new(directive, &words)?)?;
+				self.setup.objects.push(object);
+				Ok(())
+			}
+		}
+	}
+
+	fn scene(&mut self, mut args: Args) -> Result<(), String> {
+		let s = &mut self.setup.scene;
+		if let Some(x) = args.positive_f64("aspect_ratio")? { s.aspect_ratio = x; }
+		if let Some(x) = args.positive_usize("image_width")? { s.image_width = x; }
+		if let Some(x) = args.positive_usize("samples_per_pixel")? { s.samples_per_pixel = x; }
+		if let Some(x) = args.f64("adaptive_threshold")? {
+			if x < 0.0 {
+				return Err(format!("'adaptive_threshold' must not be negative, got {}", x));
+			}
+			s.adaptive_threshold = x;
+		}
+		if let Some(x) = args.positive_usize("min_samples")? { s.min_samples = x; }
+		if let Some(x) = args.vec3("background")? { s.background = x; }
+		if let Some(x) = args.positive_usize("max_depth")? { s.max_depth = x as i32; }
+		if let Some(x) = args.usize("save_temps")? { s.save_temps = x; }
+		if let Some(x) = args.str("light_sampling")? {
+			s.light_sampling = LightSampling::parse(x).ok_or_else(|| {
+				format!("'light_sampling': expected one of {}, got '{}'", LightSampling::NAMES.join(", "), x)
+			})?;
+		}
+		if let Some(x) = args.str("denoise")? {
+			s.denoise = Denoise::parse(x).ok_or_else(|| {
+				format!("'denoise': expected one of {}, got '{}'", Denoise::NAMES.join(", "), x)
+			})?;
+		}
+		if let Some(x) = args.f64("exposure")? { s.tone_map.exposure = x; }
+		if let Some(x) = args.str("tonemap")? {
+			s.tone_map.operator = Operator::parse(x).ok_or_else(|| {
+				format!("'tonemap': expected one of {}, got '{}'", Operator::NAMES.join(", "), x)
+			})?;
+		}
+		if let Some(x) = args.bool("dither")? { s.tone_map.dither = x; }
+		let scale = args.positive_f64("environment_scale")?;
+		let rotation = args.f64("environment_rotate_y")?;
+		match args.str("environment")? {
+			Some(path) => {
+				let path = self.base_dir.join(path);
+				let map = EnvironmentMap::load(&path.to_string_lossy(), scale.unwrap_or(1.0))?;
+				s.environment = Some(Box::new(map.rotate_y(rotation.unwrap_or(0.0))));
+			}
+			None if scale.is_some() || rotation.is_some() => {
+				return Err("'environment_scale' and 'environment_rotate_y' require 'environment'".to_string());
+			}
+			None => {}
+		}
+		args.finish()
+	}
+
+	fn sky(&mut self, mut args: Args) -> Result<(), String> {
+		let elevation = args.f64("sun_elevation")?.unwrap_or(30.0);
+		if !(0.0..=90.0).contains(&elevation) {
+			return Err(format!("'sun_elevation' must be between 0 and 90, got {}", elevation));
+		}
+		let azimuth = args.f64("sun_azimuth")?.unwrap_or(0.0);
+		let turbidity = args.f64("turbidity")?.unwrap_or(3.0);
+		if !(1.7..=10.0).contains(&turbidity) {
+			return Err(format!("'turbidity' must be between 1.7 and 10, got {}", turbidity));
+		}
+		let ground_albedo = args.f64("ground_albedo")?.unwrap_or(0.3);
+		if !(0.0..=1.0).contains(&ground_albedo) {
+			return Err(format!("'ground_albedo' must be between 0 and 1, got {}", ground_albedo));
+		}
+		let sun = args.bool("sun")?.unwrap_or(true);
+		args.finish()?;
+		let sky = Sky::new(sun_direction(elevation, azimuth), turbidity, ground_albedo, sun);
+		self.setup.scene.environment = Some(Box::new(sky));
+		Ok(())
+	}
+
+	fn delta_light(&self, kind: &str, mut args: Args) -> Result<Box<dyn DeltaLight>, String> {
+		let light: Box<dyn DeltaLight> = match kind {
+			"point_light" => Box::new(PointLight {
+				position: args.require("position", Args::vec3)?,
+				intensity: args.require("intensity", Args::vec3)?,
+			}),
+			"spot_light" => {
+				let position = args.require("position", Args::vec3)?;
+				let direction = args.require("direction", Args::direction)?;
+				let intensity = args.require("intensity", Args::vec3)?;
+				let angle = args.f64("angle_deg")?.unwrap_or(30.0);
+				if !(angle > 0.0 && angle < 180.0) {
+					return Err(format!("'angle_deg' must be between 0 and 180, got {}", angle));
+				}
+				let falloff = args.f64("falloff_deg")?.unwrap_or(0.0);
+				if !(0.0..=angle).contains(&falloff) {
+					return Err(format!("'falloff_deg' must be between 0 and 'angle_deg', got {}", falloff));
+				}
+				Box::new(SpotLight::new(position, direction, intensity, angle, falloff))
+			}
+			_ => Box::new(DirectionalLight::new(
+				args.require("direction", Args::direction)?,
+				args.require("irradiance", Args::vec3)?,
+			)),
+		};
+		args.finish()?;
+		Ok(light)
+	}
+
+	fn view(&mut self, mut args: Args) -> Result<(), String> {
+		let v = &mut self.setup.view;
+		if let Some(x) = args.vec3("look_from")? { v.look_from = x; }
+		if let Some(x) = args.vec3("look_at")? { v.look_at = x; }
+		if let Some(x) = args.vec3("v_up")? { v.v_up = x; }
+		if let Some(x) = args.positive_f64("vfov_deg")? { v.vfov_deg = x; }
+		if let Some(x) = args.f64("aperture")? { v.aperture = x; }
+		if let Some(x) = args.positive_f64("dist_to_focus")? { v.dist_to_focus = x; }
+		let rotation = args.f64("aperture_rotation")?;
+		match (args.usize("aperture_blades")?, args.str("aperture_mask")?) {
+			(Some(_), Some(_)) => return Err("'aperture_blades' and 'aperture_mask' can not be combined".to_string()),
+			(Some(blades), None) if blades < 3 => {
+				return Err(format!("'aperture_blades' must be at least 3, got {}", blades));
+			}
+			(Some(blades), None) => v.aperture_shape = Aperture::Polygon { blades, rotation_deg: rotation.unwrap_or(0.0) },
+			(None, Some(path)) => {
+				let mask = ApertureMask::load(&self.base_dir.join(path).to_string_lossy())?;
+				v.aperture_shape = Aperture::Mask(Arc::new(mask));
+			}
+			(None, None) if rotation.is_some() => return Err("'aperture_rotation' requires 'aperture_blades'".to_string()),
+			(None, None) => {}
+		}
+		if let Some(x) = args.bool("autofocus")? { v.autofocus = x; }
+		if let Some(x) = args.f64("tilt_deg")? {
+			if x.abs() >= 90.0 {
+				return Err(format!("'tilt_deg' must be between -90 and 90, got {}", x));
+			}
+			v.tilt_deg = x;
+		}
+		if let Some(x) = args.vec2("shift")? { v.shift = x; }
+		if let Some(x) = args.f64("shutter_open")? { v.shutter_open = x; }
+		if let Some(x) = args.f64("shutter_close")? { v.shutter_close = x; }
+		if v.shutter_close < v.shutter_open {
+			return Err(format!("'shutter_close' must not be before 'shutter_open', got {} and {}",
+				v.shutter_close, v.shutter_open));
+		}
+		args.finish()
+	}
+
+	fn animation(&mut self, mut args: Args) -> Result<(), String> {
+		let num_frames = args.positive_usize("num_frames")?.unwrap_or(1);
+		let look_from = args.vec3("look_from")?;
+		let look_at = args.vec3("look_at")?;
+		let vfov_deg = args.positive_f64("vfov_deg")?;
+		let dist_to_focus = args.positive_f64("dist_to_focus")?;
+		let shutter_open = args.f64("shutter_open")?;
+		let shutter_close = args.f64("shutter_close")?;
+		args.finish()?;
+
+		let lerp = |a: f64, b: f64, t: f64| { a + (b - a) * t };
+		let lerp_vec = |a: Vec3, b: Vec3, t: f64| { a + t * (b - a) };
+		self.setup.animation = Animation {
+			num_frames,
+			f: Box::new(move |v, t| {
+				let mut z = v.clone();
+				if let Some(x) = look_from { z.look_from = lerp_vec(v.look_from, x, t); }
+				if let Some(x) = look_at { z.look_at = lerp_vec(v.look_at, x, t); }
+				if let Some(x) = vfov_deg { z.vfov_deg = lerp(v.vfov_deg, x, t); }
+				if let Some(x) = dist_to_focus { z.dist_to_focus = lerp(v.dist_to_focus, x, t); }
+				if let Some(x) = shutter_open { z.shutter_open = lerp(v.shutter_open, x, t); }
+				if let Some(x) = shutter_close { z.shutter_close = lerp(v.shutter_close, x, t); }
+				z
+			}),
+		};
+		Ok(())
+	}
+
+	fn texture(&mut self, kind: &str, mut args: Args) -> Result<TextureSpec, String> {
+		let texture = match kind {
+			"solid" => TextureSpec::Solid(args.require("color", Args::vec3)?),
+			"checker" => {
+				let odd = self.texture_arg(&mut args, "odd")?;
+				let even = self.texture_arg(&mut args, "even")?;
+				TextureSpec::Checker(Box::new(odd), Box::new(even))
+			}
+			"noise" => TextureSpec::Noise(args.require("scale", Args::positive_f64)?, self.rng.gen()),
+			"image" => {
+				let path = self.base_dir.join(args.require("path", Args::str)?);
+				let img = ImageTexture::new(&path.to_string_lossy())
+					.map_err(|e| format!("failed to load image '{}': {}", path.display(), e))?;
+				TextureSpec::Image(img)
+			}
+			_ => return Err(format!("unknown texture type '{}'", kind)),
+		};
+		args.finish()?;
+		Ok(texture)
+	}
+
+	// A texture argument is either a color or the name of a defined texture.
+	fn texture_arg(&self, args: &mut Args, key: &str) -> Result<TextureSpec, String> {
+		let value = args.require(key, Args::str)?;
+		if let Ok(color) = parse_vec3(value) {
+			return Ok(TextureSpec::Solid(color));
+		}
+		self.textures.get(value).cloned()
+			.ok_or_else(|| format!("'{}' is neither a color nor a defined texture", value))
+	}
+
+	fn material(&self, kind: &str, mut args: Args) -> Result<MaterialSpec, String> {
+		let material = match kind {
+			"lambertian" => MaterialSpec::Lambertian(self.texture_arg(&mut args, "albedo")?),
+			"metal" => MaterialSpec::Metal(
+				args.require("albedo", Args::vec3)?,
+				args.f64("fuzz")?.unwrap_or(0.0),
+			),
+			"dielectric" => MaterialSpec::Dielectric(args.require("ir", Args::positive_f64)?),
+			"conductor" => {
+				let (eta, k) = match args.str("metal")? {
+					Some(metal) => {
+						let preset = RoughConductor::preset(metal, 0.0).ok_or_else(|| {
+							format!("'metal' must be one of {}, got '{}'", RoughConductor::PRESETS.join(", "), metal)
+						})?;
+						(preset.eta, preset.k)
+					}
+					None => (args.require("eta", Args::vec3)?, args.require("k", Args::vec3)?),
+				};
+				MaterialSpec::Conductor(eta, k, args.require("roughness", Args::roughness)?)
+			}
+			"rough_dielectric" => MaterialSpec::RoughDielectric(
+				args.require("ir", Args::positive_f64)?,
+				args.require("roughness", Args::roughness)?,
+			),
+			"diffuse_light" => MaterialSpec::DiffuseLight(self.texture_arg(&mut args, "emit")?),
+			"isotropic" => MaterialSpec::Isotropic(self.texture_arg(&mut args, "albedo")?),
+			_ => return Err(format!("unknown material type '{}'", kind)),
+		};
+		args.finish()?;
+		Ok(material)
+	}
+
+	fn material_arg(&self, args: &mut Args) -> Result<Box<dyn Material>, String> {
+		let name = args.require("material", Args::str)?;
+		self.named_material(name)
+	}
+
+	fn named_material(&self, name: &str) -> Result<Box<dyn Material>, String> {
+		self.materials.get(name).map(|m| m.build())
+			.ok_or_else(|| format!("undefined material '{}'", name))
+	}
+
+	fn object(&self, kind: &str, mut args: Args) -> Result<Box<dyn Hittable>, String> {
+		let medium = match args.positive_f64("density")? {
+			Some(density) => {
+				let name = args.peek("material").ok_or("'density' requires 'material'")?;
+				Some((density, self.named_material(name)?))
+			}
+			None => None,
+		};
+		let object: Box<dyn Hittable> = match kind {
+			"sphere" => {
+				let center = args.require("center", Args::vec3)?;
+				let radius = args.require("radius", Args::positive_f64)?;
+				match args.vec3("center1")? {
+					Some(center1) => Box::new(MovingSphere {
+						center0: center, center1, time0: 0.0, time1: 1.0, radius, material: self.material_arg(&mut args)?,
+					}),
+					None => Box::new(Sphere { center, radius, material: self.material_arg(&mut args)? }),
+				}
+			}
+			"xy_rect" => Box::new(XYRect {
+				p1: args.require("p1", Args::vec2)?,
+				p2: args.require("p2", Args::vec2)?,
+				k: args.require("k", Args::f64)?,
+				material: self.material_arg(&mut args)?,
+			}),
+			"xz_rect" => Box::new(XZRect {
+				p1: args.require("p1", Args::vec2)?,
+				p2: args.require("p2", Args::vec2)?,
+				k: args.require("k", Args::f64)?,
+				material: self.material_arg(&mut args)?,
+			}),
+			"yz_rect" => Box::new(YZRect {
+				p1: args.require("p1", Args::vec2)?,
+				p2: args.require("p2", Args::vec2)?,
+				k: args.require("k", Args::f64)?,
+				material: self.material_arg(&mut args)?,
+			}),
+			"box" => Box::new(BoxShape::new(
+				args.require("p0", Args::vec3)?,
+				args.require("p1", Args::vec3)?,
+				self.material_arg(&mut args)?,
+			)),
+			"triangle" => Box::new(Triangle {
+				v: [args.require("v0", Args::vec3)?, args.require("v1", Args::vec3)?, args.require("v2", Args::vec3)?],
+				normals: match (args.vec3("n0")?, args.vec3("n1")?, args.vec3("n2")?) {
+					(Some(n0), Some(n1), Some(n2)) => Some([n0, n1, n2]),
+					(None, None, None) => None,
+					_ => return Err("'triangle' requires all of 'n0', 'n1', 'n2' or none".to_string()),
+				},
+				material: self.material_arg(&mut args)?,
+			}),
+			"mesh" => {
+				let path = self.base_dir.join(args.require("path", Args::str)?);
+				let material = match args.str("material")? {
+					Some(name) => self.named_material(name)?,
+					None => Box::new(Lambertian { albedo: Box::new(SolidColor { color: Vec3(0.73, 0.73, 0.73) }) }),
+				};
+				let to_world = if is_moving(&args) { None } else { placement(&mut args)? };
+				Box::new(TriangleMesh::load(&path.to_string_lossy(), material, &to_world.unwrap_or(Affine::identity()))?)
+			}
+			_ => return Err(format!("unknown directive '{}'", kind)),
+		};
+		let object = transform(object, &mut args)?;
+		args.finish()?;
+		if let Some((density, phase_function)) = medium {
+			return Ok(Box::new(ConstantMedium::new(object, density, phase_function)));
+		}
+		Ok(object)
+	}
+}
+
+// Wraps the object into a Motion if it moves, or else into a Transform if any
+// of the transformation keys are given.
+fn transform(object: Box<dyn Hittable>, args: &mut Args) -> Result<Box<dyn Hittable>, String> {
+	if is_moving(args) {
+		let start = scale_and_rotation(args)?.iter().fold(Affine::identity(), |a, b| a.then(b));
+		let zero = Vec3(0.0, 0.0, 0.0);
+		let (translate, velocity, spin) = (args.vec3("translate")?, args.vec3("move")?, args.vec3("spin")?);
+		let m = Motion::new(object, start, translate.unwrap_or(zero), velocity.unwrap_or(zero), spin.unwrap_or(zero))
+			.ok_or("the transformation is not invertible")?;
+		return Ok(Box::new(m));
+	}
+	match placement(args)? {
+		Some(to_world) => {
+			let t = Transform::new(object, to_world).ok_or("the transformation is not invertible")?;
+			Ok(Box::new(t))
+		}
+		None => Ok(object),
+	}
+}
+
+fn is_moving(args: &Args) -> bool {
+	args.peek("move").is_some() || args.peek("spin").is_some()
+}
+
+// The transformation of an object that does not move, None if no
+// transformation keys are given.
+fn placement(args: &mut Args) -> Result<Option<Affine>, String> {
+	let mut steps = scale_and_rotation(args)?;
+	if let Some(t) = args.vec3("translate")? {
+		steps.push(Affine::translation(t));
+	}
+	Ok(steps.into_iter().reduce(|a, b| a.then(&b)))
+}
+
+fn scale_and_rotation(args: &mut Args) -> Result<Vec<Affine>, String> {
+	let mut steps = vec![];
+	if let Some(s) = args.str("scale")? {
+		let s = parse_f64(s).map(|x| Vec3(x, x, x)).or_else(|_| parse_vec3(s))
+			.map_err(|_| format!("'scale': expected a number or three comma-separated numbers, got '{}'", s))?;
+		steps.push(Affine::scaling(s));
+	}
+	for (axis, key) in ["rotate_x", "rotate_y", "rotate_z"].iter().enumerate() {
+		if let Some(deg) = args.f64(key)? {
+			steps.push(Affine::rotation(axis, deg));
+		}
+	}
+	Ok(steps)
+}
+
+// Splits `<name> <kind> key=value...` used by the `texture` and `material` directives.
+fn named<'a>(directive: &str, words: &[&'a str]) -> Result<(&'a str, &'a str, Args<'a>), String> {
+	if words.len() < 2 || words[0].contains('=') || words[1].contains('=') {
+		return Err(format!("expected '{} <name> <type> key=value...'", directive));
+	}
+	Ok((words[0], words[1], Args::new(words[1], &words[2..])?))
+}
+
+fn define<T>(defs: &mut HashMap<String, T>, what: &str, name: &str, value: T) -> Result<(), String> {
+	if defs.insert(name.to_string(), value).is_some() {
+		return Err(format!("{} '{}' is already defined", what, name));
+	}
+	Ok(())
+}
+
+// The `key=value` arguments of a directive. Every key has to be consumed,
+// leftovers are reported as unknown so that typos do not go unnoticed.
+struct Args<'a> {
+	directive: String,
+	values: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Args<'a> {
+	fn new(directive: &str, words: &[&'a str]) -> Result<Args<'a>, String> {
+		let mut values: Vec<(&'a str, &'a str)> = vec![];
+		for w in words {
+			let (k, v) = w.split_once('=')
+				.ok_or_else(|| format!("expected key=value, got '{}'", w))?;
+			if values.iter().any(|(k2, _)| *k2 == k) {
+				return Err(format!("duplicate key '{}'", k));
+			}
+			values.push((k, v));
+		}
+		Ok(Args { directive: directive.to_string(), values })
+	}
+
+	fn take(&mut self, key: &str) -> Option<&'a str> {
+		let pos = self.values.iter().position(|(k, _)| *k == key)?;
+		Some(self.values.remove(pos).1)
+	}
+
+	fn peek(&self, key: &str) -> Option<&'a str> {
+		self.values.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+	}
+
+	fn require<T>(&mut self, key: &str, get: fn(&mut Self, &str) -> Result<Option<T>, String>) -> Result<T, String> {
+		get(self, key)?.ok_or_else(|| format!("'{}' requires '{}'", self.directive, key))
+	}
+
+	fn finish(self) -> Result<(), String> {
+		match self.values.first() {
+			Some((k, _)) => Err(format!("unknown key '{}' for '{}'", k, self.directive)),
+			None => Ok(()),
+		}
+	}
+
+	fn str(&mut self, key: &str) -> Result<Option<&'a str>, String> {
+		Ok(self.take(key))
+	}
+
+	fn f64(&mut self, key: &str) -> Result<Option<f64>, String> {
+		self.take(key).map(|v| parse_f64(v).map_err(|e| format!("'{}': {}", key, e))).transpose()
+	}
+
+	fn positive_f64(&mut self, key: &str) -> Result<Option<f64>, String> {
+		match self.f64(key)? {
+			Some(x) if x <= 0.0 => Err(format!("'{}' must be positive, got {}", key, x)),
+			x => Ok(x),
+		}
+	}
+
+	fn roughness(&mut self, key: &str) -> Result<Option<f64>, String> {
+		match self.f64(key)? {
+			Some(x) if !(0.0..=1.0).contains(&x) => Err(format!("'{}' must be between 0 and 1, got {}", key, x)),
+			x => Ok(x),
+		}
+	}
+
+	fn direction(&mut self, key: &str) -> Result<Option<Vec3>, String> {
+		match self.vec3(key)? {
+			Some(x) if x.near_zero() => Err(format!("'{}' must not be zero", key)),
+			x => Ok(x),
+		}
+	}
+
+	fn usize(&mut self, key: &str) -> Result<Option<usize>, String> {
+		self.take(key).map(|v| {
+			v.parse::<usize>().map_err(|_| format!("'{}': expected a non-negative integer, got '{}'", key, v))
+		}).transpose()
+	}
+
+	fn positive_usize(&mut self, key: &str) -> Result<Option<usize>, String> {
+		match self.usize(key)? {
+			Some(0) => Err(format!("'{}' must be positive, got 0", key)),
+			x => Ok(x),
+		}
+	}
+
+	fn bool(&mut self, key: &str) -> Result<Option<bool>, String> {
+		self.take(key).map(|v| {
+			match v {
+				"true" => Ok(true),
+				"false" => Ok(false),
+				_ => Err(format!("'{}': expected true or false, got '{}'", key, v)),
+			}
+		}).transpose()
+	}
+
+	fn vec2(&mut self, key: &str) -> Result<Option<Vec2>, String> {
+		self.take(key).map(|v| {
+			match parse_list(v)?[..] {
+				[x, y] => Ok(Vec2(x, y)),
+				_ => Err(format!("expected two comma-separated numbers, got '{}'", v)),
+			}.map_err(|e| format!("'{}': {}", key, e))
+		}).transpose()
+	}
+
+	fn vec3(&mut self, key: &str) -> Result<Option<Vec3>, String> {
+		self.take(key).map(|v| parse_vec3(v).map_err(|e| format!("'{}': {}", key, e))).transpose()
+	}
+}
+
+pub fn parse_f64(s: &str) -> Result<f64, String> {
+	match s.parse::<f64>() {
+		Ok(x) if x.is_finite() => Ok(x),
+		_ => Err(format!("expected a number, got '{}'", s)),
+	}
+}
+
+fn parse_list(s: &str) -> Result<Vec<f64>, String> {
+	s.split(',').map(parse_f64).collect()
+}
+
+pub fn parse_vec3(s: &str) -> Result<Vec3, String> {
+	match parse_list(s)?[..] {
+		[x, y, z] => Ok(Vec3(x, y, z)),
+		_ => Err(format!("expected three comma-separated numbers, got '{}'", s)),
+	}
+}
+
+#[cfg(test)]
+fn parse_err(text: &str) -> SceneError {
+	match parse(text, Path::new(""), &mut scene_rng(0)) {
+		Ok(_) => panic!("parsing succeeded"),
+		Err(e) => e,
+	}
+}
+
+#[test]
+fn parse_test() {
+	let setup = parse("
+		# A comment.
+		scene image_width=100 background=0,0,0 tonemap=aces exposure=-1 light_sampling=power  # Trailing comment.
+		view look_from=1,2,3 vfov_deg=20 aperture_blades=6 autofocus=true shift=0,0.1
+		animation num_frames=10 look_from=3,2,1
+		sky sun_elevation=45 turbidity=4
+		texture checker checker odd=0.2,0.3,0.1 even=0.9,0.9,0.9
+		material ground lambertian albedo=checker
+		material light diffuse_light emit=4,4,4
+		material gold conductor metal=gold roughness=0.3
+		material frosted rough_dielectric ir=1.5 roughness=0.2
+		sphere center=0,-1000,0 radius=1000 material=ground
+		sphere center=0,1,0 center1=0,2,0 radius=1 material=ground
+		box p0=0,0,0 p1=1,1,1 material=ground translate=3,0,0 move=1,0,0 spin=0,90,0
+		xz_rect p1=-1,-1 p2=1,1 k=4 material=light
+		point_light position=0,5,0 intensity=10,10,10
+		spot_light position=0,5,0 direction=0,-1,0 intensity=10,10,10 angle_deg=20 falloff_deg=5
+		directional_light direction=1,-1,0 irradiance=2,2,2
+	", Path::new(""), &mut scene_rng(0)).unwrap_or_else(|e| panic!("{}", e));
+
+	assert_eq!(setup.scene.image_width, 100);
+	assert_eq!(setup.scene.background, Vec3(0.0, 0.0, 0.0));
+	assert_eq!((setup.scene.tone_map.operator, setup.scene.tone_map.exposure), (Operator::Aces, -1.0));
+	assert_eq!(setup.scene.light_sampling, LightSampling::Power);
+	assert_eq!(setup.view.vfov_deg, 20.0);
+	assert!(matches!(setup.view.aperture_shape, Aperture::Polygon { blades: 6, .. }));
+	assert!(setup.view.autofocus && setup.view.shift == Vec2(0.0, 0.1));
+	assert_eq!(setup.objects.len(), 4);
+	assert_eq!(setup.animation.num_frames, 10);
+	assert_eq!((setup.animation.f)(&setup.view, 0.5).look_from, Vec3(2.0, 2.0, 2.0));
+	assert_eq!(setup.objects[3].pick_lights().len(), 1);
+	assert_eq!(setup.objects[1].bounding_box().unwrap().max().1, 3.0);
+	assert!(setup.scene.environment().light().is_some());
+	assert_eq!(setup.scene.delta_lights.len(), 3);
+	assert!(setup.scene.delta_lights[2].position().is_none());
+}
+
+#[test]
+fn parse_error_test() {
+	assert_eq!(parse_err("\nsphere center=0,0,0 radius=1 material=red\n"),
+		SceneError { line: 2, message: "undefined material 'red'".to_string() });
+	assert_eq!(parse_err("material red lambertian albedo=1,0\n").message,
+		"'1,0' is neither a color nor a defined texture");
+	assert_eq!(parse_err("scene image_width=0").message, "'image_width' must be positive, got 0");
+	assert_eq!(parse_err("scene image_widht=10").message, "unknown key 'image_widht' for 'scene'");
+	assert_eq!(parse_err("scene dither=yes").message, "'dither': expected true or false, got 'yes'");
+	assert_eq!(parse_err("material m metal fuzz=0.1").message, "'metal' requires 'albedo'");
+	assert_eq!(parse_err("sky sun_elevation=-5").message, "'sun_elevation' must be between 0 and 90, got -5");
+	assert_eq!(parse_err("view aperture_blades=2").message, "'aperture_blades' must be at least 3, got 2");
+	assert_eq!(parse_err("view aperture_rotation=10").message, "'aperture_rotation' requires 'aperture_blades'");
+	assert_eq!(parse_err("view shutter_open=1 shutter_close=0").message,
+		"'shutter_close' must not be before 'shutter_open', got 0 and 1");
+	assert_eq!(parse_err("material m conductor metal=brass roughness=0.1").message,
+		"'metal' must be one of gold, copper, aluminium, silver, got 'brass'");
+	assert_eq!(parse_err("material m rough_dielectric ir=1.5 roughness=2").message,
+		"'roughness' must be between 0 and 1, got 2");
+	assert_eq!(parse_err("sky turbidity=20").message, "'turbidity' must be between 1.7 and 10, got 20");
+	assert_eq!(parse_err("spot_light position=0,0,0 direction=0,0,0 intensity=1,1,1").message,
+		"'direction' must not be zero");
+	assert_eq!(parse_err("spot_light position=0,0,0 direction=0,-1,0 intensity=1,1,1 falloff_deg=40").message,
+		"'falloff_deg' must be between 0 and 'angle_deg', got 40");
+	assert_eq!(parse_err("point_light position=0,0,0").message, "'point_light' requires 'intensity'");
+	assert_eq!(parse_err("cube size=1").message, "unknown directive 'cube'");
+	assert_eq!(parse_err("mesh path=x.obj density=1").message, "'density' requires 'material'");
+	assert_eq!(parse_err("scene image_width=10\n\n").message, "scene has no objects");
+}
+
+#[test]
+fn example_scenes_test() {
+	for entry in std::fs::read_dir("scenes").unwrap() {
+		let path = entry.unwrap().path();
+		if path.extension().is_none_or(|e| e != "scene") {
+			continue;
+		}
+		if let Err(e) = load(&path.to_string_lossy(), &mut scene_rng(0)) {
+			panic!("{}", e);
+		}
+	}
+}
@@ -0,0 +1,331 @@
+use rand::Rng as _;
+
+use crate::vec3::*;
+use crate::hit::*;
+use crate::metal::*;
+use crate::sphere::*;
+use crate::texture::*;
+use crate::perlin::*;
+use crate::rectangle::*;
+use crate::lambertian::*;
+use crate::dielectric::*;
+use crate::box_shape::*;
+use crate::transform::*;
+use crate::isotropic::*;
+use crate::constant_medium::*;
+use crate::scene::*;
+use crate::sky::*;
+use crate::scene_file::SceneSetup;
+use crate::rng::Rng;
+
+fn test_sphere() -> Vec<Box<dyn Hittable>> {
+    let mut objects: Vec<Box<dyn Hittable>> = vec![];
+
+    let gray = Lambertian{albedo: Box::new(SolidColor{color: Vec3(0.18, 0.18, 0.18)})};
+    objects.push(Sphere::box_new(Vec3(200.0, 200.0, 200.0), 100.0, gray));
+
+    let light = Box::new(DiffuseLight{emit: Box::new(SolidColor{color: 15.0*Vec3(1.0, 1.0, 1.0)})});
+    objects.push(Box::new(XZRect{p1: Vec2(210.0, 227.0), p2: Vec2(343.0, 332.0), k: 554.0, material: light}));
+    objects
+}
+
+// Walls of the Cornell box, open towards the camera.
+fn cornell_walls() -> Vec<Box<dyn Hittable>> {
+    let mut objects: Vec<Box<dyn Hittable>> = vec![];
+
+    let green = Box::new(Lambertian{albedo: Box::new(SolidColor{color: Vec3(0.12, 0.45, 0.15)})});
+    objects.push(Box::new(YZRect{p1: Vec2(0.0, 0.0), p2: Vec2(555.0, 555.0), k: 555.0, material: green}));
+    
+    let red = Box::new(Lambertian{albedo: Box::new(SolidColor{color: Vec3(0.65, 0.05, 0.05)})});
+    objects.push(Box::new(YZRect{p1: Vec2(0.0, 0.0), p2: Vec2(555.0, 555.0), k: 0.0, material: red}));
+
+    let white = Box::new(Lambertian{albedo: Box::new(SolidColor{color: Vec3(0.73, 0.73, 0.73)})});
+    objects.push(Box::new(XZRect{p1: Vec2(0.0, 0.0), p2: Vec2(555.0, 555.0), k: 0.0, material: white}));
+
+    let white = Box::new(Lambertian{albedo: Box::new(SolidColor{color: Vec3(0.73, 0.73, 0.73)})});
+    objects.push(Box::new(XZRect{p1: Vec2(0.0, 0.0), p2: Vec2(555.0, 555.0), k: 555.0, material: white}));
+
+    let white = Box::new(Lambertian{albedo: Box::new(SolidColor{color: Vec3(0.73, 0.73, 0.73)})});
+    objects.push(Box::new(XYRect{p1: Vec2(0.0, 0.0), p2: Vec2(555.0, 555.0), k: 555.0, material: white}));
+
+    objects
+}
+
+fn cornell_box() -> Vec<Box<dyn Hittable>> {
+    let mut objects = cornell_walls();
+
+    let light = Box::new(DiffuseLight{emit: Box::new(SolidColor{color: 15.0*Vec3(1.0, 1.0, 1.0)})});
+    objects.push(Box::new(XZRect{p1: Vec2(213.0, 227.0), p2: Vec2(343.0, 332.0), k: 554.0, material: light}));
+    let light = Box::new(DiffuseLight{emit: Box::new(SolidColor{color: 15.0*Vec3(1.0, 1.0, 1.0)})});
+    objects.push(Box::new(XZRect{p1: Vec2(113.0, 127.0), p2: Vec2(243.0, 192.0), k: 554.0, material: light}));
+
+    objects.push(Sphere::box_new(Vec3(200.0, 350.0, 200.0), 100.0, Metal{albedo: Vec3(1.0, 1.0, 1.0), fuzz: 0.0}));
+    objects.push(Sphere::box_new(Vec3(400.0, 350.0, 200.0), 80.0, Dielectric{ir: 1.5}));
+
+    objects
+}
+
+// The classic Cornell box with a tall and a short rotated box.
+fn cornell_boxes() -> Vec<Box<dyn Hittable>> {
+    let mut objects = cornell_walls();
+
+    let light = Box::new(DiffuseLight{emit: Box::new(SolidColor{color: 15.0*Vec3(1.0, 1.0, 1.0)})});
+    objects.push(Box::new(XZRect{p1: Vec2(213.0, 227.0), p2: Vec2(343.0, 332.0), k: 554.0, material: light}));
+
+    let white = || Box::new(Lambertian{albedo: Box::new(SolidColor{color: Vec3(0.73, 0.73, 0.73)})});
+    let tall = BoxShape::new(Vec3(0.0, 0.0, 0.0), Vec3(165.0, 330.0, 165.0), white());
+    let place = Affine::rotation(1, 15.0).then(&Affine::translation(Vec3(265.0, 0.0, 295.0)));
+    objects.push(Box::new(Transform::new(Box::new(tall), place).unwrap()));
+    let short = BoxShape::new(Vec3(0.0, 0.0, 0.0), Vec3(165.0, 165.0, 165.0), white());
+    let place = Affine::rotation(1, -18.0).then(&Affine::translation(Vec3(130.0, 0.0, 65.0)));
+    objects.push(Box::new(Transform::new(Box::new(short), place).unwrap()));
+
+    objects
+}
+
+// The Cornell box with the two boxes filled with black and white smoke.
+fn cornell_smoke() -> Vec<Box<dyn Hittable>> {
+    let mut objects = cornell_walls();
+
+    let light = Box::new(DiffuseLight{emit: Box::new(SolidColor{color: 7.0*Vec3(1.0, 1.0, 1.0)})});
+    objects.push(Box::new(XZRect{p1: Vec2(113.0, 127.0), p2: Vec2(443.0, 432.0), k: 554.0, material: light}));
+
+    let smoke = |color: Color| Box::new(Isotropic{albedo: Box::new(SolidColor{color})});
+    let tall = BoxShape::new(Vec3(0.0, 0.0, 0.0), Vec3(165.0, 330.0, 165.0), smoke(Vec3(0.0, 0.0, 0.0)));
+    let place = Affine::rotation(1, 15.0).then(&Affine::translation(Vec3(265.0, 0.0, 295.0)));
+    let tall = Transform::new(Box::new(tall), place).unwrap();
+    objects.push(Box::new(ConstantMedium::new(Box::new(tall), 0.01, smoke(Vec3(0.0, 0.0, 0.0)))));
+    let short = BoxShape::new(Vec3(0.0, 0.0, 0.0), Vec3(165.0, 165.0, 165.0), smoke(Vec3(1.0, 1.0, 1.0)));
+    let place = Affine::rotation(1, -18.0).then(&Affine::translation(Vec3(130.0, 0.0, 65.0)));
+    let short = Transform::new(Box::new(short), place).unwrap();
+    objects.push(Box::new(ConstantMedium::new(Box::new(short), 0.01, smoke(Vec3(1.0, 1.0, 1.0)))));
+
+    objects
+}
+
+fn simple_light(rng: &mut Rng) -> Vec<Box<dyn Hittable>> {
+    let mut objects: Vec<Box<dyn Hittable>> = vec![];
+
+    let noise = Box::new(NoiseTexture::new(8.0, rng));
+    objects.push(Sphere::box_new(Vec3(0.0, -1000.0, 0.0), 1000.0, Lambertian{albedo: noise}));
+    //let noise = Box::new(NoiseTexture::new(4.0));
+    //objects.push(Sphere::box_new(Vec3(0.0, 2.0, 0.0), 2.0, Lambertian{albedo: noise}));
+    let green = Lambertian{albedo: Box::new(SolidColor{color: Vec3(0.12, 0.85, 0.15)})};
+    objects.push(Sphere::box_new(Vec3(0.0, 2.0, 0.0), 2.0, green));
+
+    let difflight = Box::new(DiffuseLight{emit: Box::new(SolidColor{color: 0.2*Vec3(1.0, 1.0, 1.0)})});
+    objects.push(Box::new(XYRect{p1: Vec2(-1.0, 1.0), p2: Vec2(1.0, 3.0), k: -2.0, material: difflight}));
+
+    let difflight = Box::new(DiffuseLight{emit: Box::new(SolidColor{color: 1.0*Vec3(1.0, 1.0, 1.0)})});
+    objects.push(Box::new(XYRect{p1: Vec2(-1.0, 1.0), p2: Vec2(1.0, 3.0), k: 2.0, material: difflight}));
+
+    let difflight = Box::new(DiffuseLight{emit: Box::new(SolidColor{color: 1.0*Vec3(1.0, 1.0, 1.0)})});
+    objects.push(Box::new(XZRect{p1: Vec2(-1.0, -1.0), p2: Vec2(1.0, 1.0), k: 4.0, material: difflight}));
+
+    let difflight = Box::new(DiffuseLight{emit: Box::new(SolidColor{color: 4.0*Vec3(1.0, 1.0, 1.0)})});
+    objects.push(Box::new(XZRect{p1: Vec2(-1.0, -1.0), p2: Vec2(1.0, 1.0), k: 0.0, material: difflight}));
+
+    objects
+}
+
+
+fn earth() -> Vec<Box<dyn Hittable>> {
+    let mut objects: Vec<Box<dyn Hittable>> = vec![];
+
+    let earth = ImageTexture::new("earthmap.jpg").expect("failed to load an image");
+    objects.push(Sphere::box_new(Vec3(0.0, 0.0, 0.0), 2.0, Lambertian{albedo: Box::new(earth)}));
+
+    objects
+}
+
+fn two_perlin_spheres(rng: &mut Rng) -> Vec<Box<dyn Hittable>> {
+    let mut objects: Vec<Box<dyn Hittable>> = vec![];
+
+    let noise = Box::new(NoiseTexture::new(8.0, rng));
+    objects.push(Sphere::box_new(Vec3(0.0, -1000.0, 0.0), 1000.0, Lambertian{albedo: noise}));
+    let noise = Box::new(NoiseTexture::new(4.0, rng));
+    objects.push(Sphere::box_new(Vec3(0.0, 2.0, 0.0), 2.0, Lambertian{albedo: noise}));
+
+    objects
+}
+
+fn two_spheres() -> Vec<Box<dyn Hittable>> {
+    let mut objects: Vec<Box<dyn Hittable>> = vec![];
+
+    let checker = Box::new(CheckerTexture{
+        odd: Box::new(SolidColor{color: Vec3(0.2,0.3,0.1)}),
+        even: Box::new(SolidColor{color: Vec3(0.9,0.9,0.9)}),
+    });
+    objects.push(Sphere::box_new(Vec3(0.0, -10.0, 0.0), 10.0, Lambertian{albedo: checker}));
+
+    let checker = Box::new(CheckerTexture{
+        odd: Box::new(SolidColor{color: Vec3(0.2,0.3,0.1)}),
+        even: Box::new(SolidColor{color: Vec3(0.9,0.9,0.9)}),
+    });
+    objects.push(Sphere::box_new(Vec3(0.0, 10.0, 0.0), 10.0, Lambertian{albedo: checker}));
+
+    objects
+}
+
+// With `moving`, the small diffuse spheres bounce up during the time interval [0, 1].
+fn random_scene(rng: &mut Rng, moving: bool) -> Vec<Box<dyn Hittable>> {
+    let mut objects: Vec<Box<dyn Hittable>> = vec![];
+    let checker = Box::new(CheckerTexture{
+        odd: Box::new(SolidColor{color: Vec3(0.2,0.3,0.1)}),
+        even: Box::new(SolidColor{color: Vec3(0.9,0.9,0.9)}),
+    });
+    objects.push(Sphere::box_new(Vec3(0.0, -1000.0, 0.0), 1000.0, Lambertian{albedo: checker}));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = rng.gen::<f64>();
+            let center = Vec3(a as f64 + 0.9*rng.gen::<f64>(), 0.2, b as f64 + 0.9*rng.gen::<f64>());
+            if (center-Vec3(4.0, 0.2, 0.0)).length() > 0.9 {
+                match choose_mat {
+                    x if x<0.8 => {
+                        let color = random_vec3(rng)*random_vec3(rng);
+                        let material = Lambertian{albedo: Box::new(SolidColor{color})};
+                        if moving {
+                            let center1 = center + Vec3(0.0, 0.5*rng.gen::<f64>(), 0.0);
+                            objects.push(Box::new(MovingSphere{
+                                center0: center, center1, time0: 0.0, time1: 1.0, radius: 0.2, material: Box::new(material),
+                            }));
+                        } else {
+                            objects.push(Sphere::box_new(center, 0.2, material));
+                        }
+                    }
+                    x if x < 0.95 => {
+                        let albedo = random_vec3_bounds(rng, 0.5, 1.0);
+                        let fuzz = rng.gen::<f64>()*0.5;
+                        let material = Metal{albedo, fuzz};
+                        objects.push(Sphere::box_new(center, 0.2, material));
+                    }
+                    x if x>=0.95 => {
+                        let material = Dielectric{ir: 1.5};
+                        objects.push(Sphere::box_new(center, 0.2, material));
+
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    objects.push(Sphere::box_new(Vec3(0.0, 1.0, 0.0), 1.0, Dielectric{ir: 1.5}));
+
+    objects.push(Sphere::box_new(Vec3(-4.0, -1.0, 0.0), 1.0, Lambertian{albedo: Box::new(SolidColor{color: Vec3(0.4, 0.2, 0.1)})}));
+    objects.push(Sphere::box_new(Vec3(4.0, 1.0, 0.0), 1.0, Metal{albedo: Vec3(0.7, 0.6, 0.5), fuzz: 0.0}));
+
+    // Using BVH reduces the time to render (1200 width, 50 samples/pixel) from 602s to 155s.
+    objects
+}
+
+// Names of the scenes that can be built with `builtin`.
+pub const NAMES: [&str; 12] = [
+    "random", "random_sky", "bouncing_spheres", "two_spheres", "two_perlin_spheres", "earth", "simple_light",
+    "cornell_box", "cornell_boxes", "cornell_smoke", "cornell_pan", "test_sphere",
+];
+
+// Builds the built-in scene with the given name. Random layouts and textures
+// are generated with `rng`.
+pub fn builtin(name: &str, rng: &mut Rng) -> Option<SceneSetup> {
+    let mut s = Scene::default();
+    let mut v = View::default();
+    let mut a = Animation::default();
+
+    // World
+    let objects = match name {
+        "random" => {
+            v.aperture = 0.1;
+            v.vfov_deg = 20.0;
+            random_scene(rng, false)
+        }
+        "random_sky" => {
+            v.aperture = 0.1;
+            v.vfov_deg = 20.0;
+            s.environment = Some(Box::new(Sky::new(sun_direction(25.0, 150.0), 3.0, 0.3, true)));
+            random_scene(rng, false)
+        }
+        "bouncing_spheres" => {
+            v.aperture = 0.1;
+            v.vfov_deg = 20.0;
+            v.shutter_close = 1.0;
+            random_scene(rng, true)
+        }
+        "two_spheres" => {
+            v.vfov_deg = 20.0;
+            two_spheres()
+        }
+        "two_perlin_spheres" => {
+            v.vfov_deg = 20.0;
+            two_perlin_spheres(rng)
+        }
+        "earth" => {
+            v.vfov_deg = 20.0;
+            earth()
+        }
+        "simple_light" => {
+            v.look_from = Vec3(26.0, 3.0, 0.0);
+            v.look_at = Vec3(0.0, 2.0, 0.0);
+            v.v_up = Vec3(0.0, 1.0, 0.0);
+            v.vfov_deg = 10.0;
+            s.background = Vec3(0.0, 0.0, 0.0);
+            s.samples_per_pixel = 800;
+            simple_light(rng)
+        }
+        "cornell_box" => {
+            s.aspect_ratio = 1.0;
+            s.image_width = 600;
+            s.samples_per_pixel = 1000;
+            v.look_from = Vec3(278.0, 278.0, -800.0);
+            v.look_at = Vec3(278.0, 278.0, 0.0);
+            s.background = Vec3(0.0, 0.0, 0.0);
+
+            cornell_box()
+        }
+        "cornell_boxes" => {
+            s.aspect_ratio = 1.0;
+            s.image_width = 600;
+            s.samples_per_pixel = 1000;
+            v.look_from = Vec3(278.0, 278.0, -800.0);
+            v.look_at = Vec3(278.0, 278.0, 0.0);
+            s.background = Vec3(0.0, 0.0, 0.0);
+
+            cornell_boxes()
+        }
+        "cornell_smoke" => {
+            s.aspect_ratio = 1.0;
+            s.image_width = 600;
+            s.samples_per_pixel = 200;
+            v.look_from = Vec3(278.0, 278.0, -800.0);
+            v.look_at = Vec3(278.0, 278.0, 0.0);
+            s.background = Vec3(0.0, 0.0, 0.0);
+
+            cornell_smoke()
+        }
+        "cornell_pan" => {
+            s.aspect_ratio = 1.0;
+            s.image_width = 300;
+            s.samples_per_pixel = 800;
+            v.look_from = Vec3(278.0, 278.0, -800.0);
+            v.look_at = Vec3(278.0, 278.0, 0.0);
+            s.background = Vec3(0.0, 0.0, 0.0);
+
+            a.num_frames = 100;
+            a.f = Box::new(|v, t| { let mut z = v.clone(); z.look_from = Vec3(2.0*278.0*t, 278.0, -800.0); z});
+            cornell_box()
+        }
+        "test_sphere" => {
+            s.aspect_ratio = 1.0;
+            s.image_width = 600;
+            s.samples_per_pixel = 1000;
+            v.look_from = Vec3(278.0, 278.0, -800.0);
+            v.look_at = Vec3(278.0, 278.0, 0.0);
+            s.background = Vec3(1.0, 1.0, 1.0);
+
+            test_sphere()
+        }
+        _ => return None,
+    };
+    Some(SceneSetup { scene: s, view: v, animation: a, objects })
+}
+
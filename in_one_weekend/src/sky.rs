@@ -0,0 +1,250 @@
+// A clear sky computed with the analytic model of Preetham, Shirley and Smits,
+// "A Practical Analytic Model for Daylight" (1999), with the sun as a disc
+// that can be sampled as a light.
+use std::f64::consts::PI;
+
+use crate::aabb::*;
+use crate::hit::*;
+use crate::ray::*;
+use crate::vec3::*;
+use crate::environment::Environment;
+use crate::rng::Rng;
+
+// Radiance of 1 kcd/m^2, the unit of the model, in scene units. Chosen so that
+// white surfaces in the sun come out at about 1 with the default exposure.
+const SCALE: f64 = 0.05;
+// Luminance of the sun outside the atmosphere in kcd/m^2.
+const SUN_LUMINANCE: f64 = 1.9e6;
+// Angular radius of the sun seen from the earth.
+const SUN_RADIUS_DEG: f64 = 0.267;
+// Wavelengths in micrometers standing in for the red, green and blue channels.
+const WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
+
+// Direction towards the sun, `elevation` degrees above the horizon and
+// `azimuth` degrees from -z towards +x.
+pub fn sun_direction(elevation: f64, azimuth: f64) -> Vec3 {
+	let (sin_e, cos_e) = elevation.to_radians().sin_cos();
+	let (sin_a, cos_a) = azimuth.to_radians().sin_cos();
+	Vec3(cos_e * sin_a, sin_e, -cos_e * cos_a)
+}
+
+// The distribution of one quantity (luminance or chromaticity) over the sky,
+// with the angle `theta` from the zenith and `gamma` from the sun.
+struct Perez([f64; 5]);
+
+impl Perez {
+	fn eval(&self, cos_theta: f64, gamma: f64) -> f64 {
+		let [a, b, c, d, e] = self.0;
+		(1.0 + a * (b / cos_theta.max(1e-3)).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+	}
+}
+
+// Sky luminance and chromaticity for a sun `theta_s` radians from the zenith,
+// as the zenith value and its distribution.
+struct Channel {
+	zenith: f64,
+	perez: Perez,
+	// The distribution at the zenith, which the values are relative to.
+	norm: f64,
+}
+
+impl Channel {
+	fn new(zenith: f64, perez: Perez, theta_s: f64) -> Channel {
+		let norm = perez.eval(1.0, theta_s);
+		Channel { zenith, perez, norm }
+	}
+
+	fn eval(&self, cos_theta: f64, gamma: f64) -> f64 {
+		self.zenith * self.perez.eval(cos_theta, gamma) / self.norm
+	}
+}
+
+// The sun as a light: a disc of constant radiance around `dir`, infinitely far away.
+pub struct Sun {
+	dir: Vec3,
+	// Cosine of the angular radius.
+	cos_max: f64,
+	radiance: Color,
+}
+
+impl Sun {
+	fn color(&self, dir: &Vec3) -> Color {
+		if dot(unit_vector(*dir), self.dir) >= self.cos_max { self.radiance } else { Vec3(0.0, 0.0, 0.0) }
+	}
+}
+
+impl Hittable for Sun {
+	// The sun is only seen by rays that hit nothing else.
+	fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord<'_>> {
+		None
+	}
+
+	fn bounding_box(&self) -> Option<AABB> {
+		None
+	}
+
+	// Returns a direction, uniformly distributed over the cone of the disc.
+	fn gen_random_point(&self, _origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		random_in_cone(self.dir, self.cos_max, rng)
+	}
+
+	fn pdf_eval(&self, _origin: &Vec3, dir: &Vec3) -> f64 {
+		if dot(unit_vector(*dir), self.dir) >= self.cos_max { 1.0 / (2.0 * PI * (1.0 - self.cos_max)) } else { 0.0 }
+	}
+}
+
+// The sky above the horizon and a uniform ground below it, reflecting the
+// light of the sky and the sun with `ground_albedo`.
+pub struct Sky {
+	sun_dir: Vec3,
+	luminance: Channel,
+	x: Channel,
+	y: Channel,
+	ground: Color,
+	sun: Option<Sun>,
+}
+
+impl Sky {
+	// `turbidity` is the haziness of the air, from about 2 (very clear) to 10
+	// (hazy); the model is fitted for the sun above the horizon. The sun disc
+	// is left out of the sky unless `sun_disc` is set.
+	pub fn new(sun_dir: Vec3, turbidity: f64, ground_albedo: f64, sun_disc: bool) -> Sky {
+		let t = turbidity;
+		let sun_dir = unit_vector(sun_dir);
+		let theta_s = sun_dir.1.clamp(-1.0, 1.0).acos();
+		let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+		let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+		let poly = |c: [f64; 4]| { ((c[0] * theta_s + c[1]) * theta_s + c[2]) * theta_s + c[3] };
+		let zenith_x = t * t * poly([0.00166, -0.00375, 0.00209, 0.0])
+			+ t * poly([-0.02903, 0.06377, -0.03202, 0.00394]) + poly([0.11693, -0.21196, 0.06052, 0.25886]);
+		let zenith_y = t * t * poly([0.00275, -0.00610, 0.00317, 0.0])
+			+ t * poly([-0.04214, 0.08970, -0.04153, 0.00516]) + poly([0.15346, -0.26756, 0.06670, 0.26688]);
+		let luminance = Perez([0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703]);
+		let x = Perez([-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452]);
+		let y = Perez([-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529]);
+		let mut sky = Sky {
+			sun_dir,
+			luminance: Channel::new(zenith_luminance, luminance, theta_s),
+			x: Channel::new(zenith_x, x, theta_s),
+			y: Channel::new(zenith_y, y, theta_s),
+			ground: Vec3(0.0, 0.0, 0.0),
+			sun: None,
+		};
+
+		let sun = Sun {
+			dir: sun_dir,
+			cos_max: SUN_RADIUS_DEG.to_radians().cos(),
+			radiance: SUN_LUMINANCE * SCALE * sun_transmittance(theta_s, t),
+		};
+		// Light falling on the ground, integrated over a grid of the upper
+		// hemisphere, uniform in cos(theta) and so in solid angle.
+		let (n, m) = (64, 128);
+		let mut irradiance = Vec3(0.0, 0.0, 0.0);
+		for i in 0..n {
+			let cos_theta = (i as f64 + 0.5) / n as f64;
+			let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+			for j in 0..m {
+				let phi = 2.0 * PI * (j as f64 + 0.5) / m as f64;
+				let dir = Vec3(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+				irradiance = irradiance + cos_theta * sky.sky_color(&dir);
+			}
+		}
+		irradiance = irradiance * (2.0 * PI / (n * m) as f64);
+		if sun_disc {
+			irradiance = irradiance + sun_dir.1.max(0.0) * 2.0 * PI * (1.0 - sun.cos_max) * sun.radiance;
+			sky.sun = Some(sun);
+		}
+		sky.ground = ground_albedo / PI * irradiance;
+		sky
+	}
+
+	// The sky without the sun, `dir` is normalized and above the horizon.
+	fn sky_color(&self, dir: &Vec3) -> Color {
+		let gamma = dot(*dir, self.sun_dir).clamp(-1.0, 1.0).acos();
+		let luminance = SCALE * self.luminance.eval(dir.1, gamma);
+		let (x, y) = (self.x.eval(dir.1, gamma), self.y.eval(dir.1, gamma));
+		// xyY to XYZ to linear sRGB.
+		let (cx, cz) = (x / y * luminance, (1.0 - x - y) / y * luminance);
+		Vec3(
+			(3.2406 * cx - 1.5372 * luminance - 0.4986 * cz).max(0.0),
+			(-0.9689 * cx + 1.8758 * luminance + 0.0415 * cz).max(0.0),
+			(0.0557 * cx - 0.2040 * luminance + 1.0570 * cz).max(0.0),
+		)
+	}
+}
+
+// Fraction of the sunlight that passes the atmosphere, scattered away by
+// molecules (Rayleigh) and haze (aerosols), for the sun `theta_s` radians
+// from the zenith. From the appendix of the Preetham paper, without ozone
+// and water vapor absorption.
+fn sun_transmittance(theta_s: f64, turbidity: f64) -> Color {
+	// Thickness of the air the sunlight passes relative to the zenith, after Kasten and Young.
+	let zenith_deg = theta_s.to_degrees().min(93.885);
+	let air_mass = 1.0 / (theta_s.cos().max(0.0) + 0.15 * (93.885 - zenith_deg + 1e-6).powf(-1.253));
+	let beta = 0.04608 * turbidity - 0.04586;
+	let t = |lambda: f64| {
+		let rayleigh = -0.008735 * lambda.powf(-4.08);
+		let aerosol = -beta * lambda.powf(-1.3);
+		((rayleigh + aerosol) * air_mass).exp()
+	};
+	Vec3(t(WAVELENGTHS[0]), t(WAVELENGTHS[1]), t(WAVELENGTHS[2]))
+}
+
+impl Environment for Sky {
+	fn color(&self, dir: &Vec3) -> Color {
+		let dir = unit_vector(*dir);
+		if dir.1 < 0.0 {
+			return self.ground;
+		}
+		let sun = match &self.sun {
+			Some(sun) => sun.color(&dir),
+			None => Vec3(0.0, 0.0, 0.0),
+		};
+		self.sky_color(&dir) + sun
+	}
+
+	fn light(&self) -> Option<&dyn Hittable> {
+		self.sun.as_ref().map(|sun| sun as &dyn Hittable)
+	}
+}
+
+#[test]
+fn sky_test() {
+	let sky = Sky::new(sun_direction(30.0, 90.0), 3.0, 0.3, true);
+	assert!((sky.sun_dir - unit_vector(Vec3(3f64.sqrt(), 1.0, 0.0))).near_zero());
+	// Blue at the zenith, brightest towards the sun and at the horizon.
+	let zenith = sky.color(&Vec3(0.0, 1.0, 0.0));
+	assert!(zenith.2 > zenith.0, "{:?}", zenith);
+	let near_sun = luminance(sky.color(&sun_direction(30.0, 80.0)));
+	let away = luminance(sky.color(&sun_direction(30.0, -90.0)));
+	assert!(near_sun > 2.0 * away && away > 0.0, "{} {}", near_sun, away);
+	assert!(luminance(sky.color(&sun_direction(2.0, -90.0))) > luminance(zenith));
+	// Reddish sun, much brighter than the sky.
+	let sun = sky.color(&sky.sun_dir);
+	assert!(sun.0 > sun.2 && luminance(sun) > 1e4 * near_sun, "{:?}", sun);
+	assert!(luminance(sky.ground) > 0.0 && sky.color(&Vec3(0.0, -1.0, 0.0)) == sky.ground);
+
+	// Without the disc the sun is neither seen nor a light.
+	let no_sun = Sky::new(sun_direction(30.0, 90.0), 3.0, 0.3, false);
+	assert!(luminance(no_sun.color(&no_sun.sun_dir)) < 10.0);
+	assert!(no_sun.light().is_none());
+	assert!(luminance(no_sun.ground) < luminance(sky.ground));
+}
+
+#[test]
+fn sun_sampling_test() {
+	let sky = Sky::new(sun_direction(45.0, 0.0), 3.0, 0.3, true);
+	let sun = sky.light().unwrap();
+	let origin = Vec3(0.0, 0.0, 0.0);
+	let mut rng = crate::rng::scene_rng(0);
+	for _ in 0..1000 {
+		let d = sun.gen_random_point(&origin, &mut rng);
+		assert!(sun.pdf_eval(&origin, &d) > 0.0);
+		assert!(luminance(sky.color(&d)) > 1e4);
+	}
+	// The PDF integrates to 1 over the disc.
+	let cos_max = SUN_RADIUS_DEG.to_radians().cos();
+	assert!((sun.pdf_eval(&origin, &sky.sun_dir) * 2.0 * PI * (1.0 - cos_max) - 1.0).abs() < 1e-9);
+	assert_eq!(sun.pdf_eval(&origin, &Vec3(0.0, 1.0, 0.0)), 0.0);
+}
+
@@ -5,6 +5,7 @@ use crate::material::*;
 use crate::hit::*;
 use crate::ray::*;
 use crate::aabb::*;
+use crate::rng::Rng;
 
 pub struct Sphere {
     pub center: Point3,
@@ -13,36 +14,141 @@ pub struct Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.orig - self.center;
-        let a = r.dir.length_squared();
-        let half_b = dot(oc, r.dir);
-        let c = dot(oc, oc) - self.radius*self.radius;
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center, self.radius, self.material.as_ref(), r, t_min, t_max)
+    }
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(AABB::new(self.center + (-self.radius), self.center+self.radius))
+    }
+    fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+        random_toward_sphere(self.center, self.radius, origin, rng)
+    }
+    fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
+        pdf_toward_sphere(self.center, self.radius, self.material.as_ref(), origin, dir)
+    }
+    fn pick_lights(&self) -> Vec<&dyn Hittable> {
+        if self.material.is_light() {
+            vec![self]
+        } else {
+            vec![]
+        }
+    }
+    fn power(&self) -> f64 {
+        diffuse_power(self.material.as_ref(), 4.0*PI * self.radius*self.radius, &self.center)
+    }
+}
+
+// Cosine of the angular radius of the sphere seen from `origin` and the
+// solid angle it covers, None inside the sphere.
+fn cone(center: Point3, radius: f64, origin: &Point3) -> Option<(f64, f64)> {
+    let x = radius*radius / (center - *origin).length_squared();
+    if x >= 1.0 {
+        return None;
+    }
+    let cos_max = (1.0 - x).sqrt();
+    // 2 pi (1 - cos_max), without cancellation for small spheres.
+    Some((cos_max, 2.0*PI * x / (1.0 + cos_max)))
+}
+
+// Returns a direction, uniformly distributed over the cone of directions
+// in which the sphere is seen.
+fn random_toward_sphere(center: Point3, radius: f64, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+    match cone(center, radius, origin) {
+        Some((cos_max, _)) => random_in_cone(unit_vector(center - *origin), cos_max, rng),
+        None => random_unit_vector(rng),
+    }
+}
+
+fn pdf_toward_sphere(center: Point3, radius: f64, material: &dyn Material, origin: &Vec3, dir: &Vec3) -> f64 {
+    let r = Ray { orig: *origin, dir: *dir, time: 0.0 };
+    if hit_sphere(center, radius, material, &r, 0.0001, f64::INFINITY).is_none() {
+        return 0.0;
+    }
+    match cone(center, radius, origin) {
+        Some((_, solid_angle)) => 1.0 / solid_angle,
+        None => 1.0 / (4.0*PI),
+    }
+}
+
+fn hit_sphere<'a>(center: Point3, radius: f64, material: &'a dyn Material, r: &Ray, t_min: f64, t_max: f64)
+    -> Option<HitRecord<'a>> {
+    let oc = r.orig - center;
+    let a = r.dir.length_squared();
+    let half_b = dot(oc, r.dir);
+    let c = dot(oc, oc) - radius*radius;
 
-        let discriminant = half_b*half_b - a*c;
+    let discriminant = half_b*half_b - a*c;
 
-        if discriminant<0.0 {
+    if discriminant<0.0 {
+        return None;
+    }
+    let sqrtd = discriminant.sqrt();
+    let mut root = (-half_b - sqrtd) / a;
+    if root < t_min || root > t_max {
+        root = (-half_b + sqrtd) / a;
+        if root < t_min || root > t_max {
             return None;
         }
-        let sqrtd = discriminant.sqrt();
-        let mut root = (-half_b - sqrtd) / a;
-        if root < t_min || root > t_max {
-            root = (-half_b + sqrtd) / a;
-            if root < t_min || root > t_max {
-                return None;
-            }
+    }
+    let p = r.at(root);
+    let outward_normal = (p - center) / radius;
+    let mut hr = HitRecord {
+        p, normal: outward_normal, t: root, front_face: false, material,
+        coord: get_shpere_coord(outward_normal),
+        object_id: 0,
+    };
+    hr.set_face_normal(r, outward_normal);
+    Some(hr)
+}
+
+// A sphere moving in a straight line from `center0` at `time0` to `center1`
+// at `time1`. It stays at the ends before and after, so that its bounding box
+// holds at all times. As a light it is sampled where it is at `time0`, so
+// that the light sampled and the density of sampling it agree at all times.
+// Once it moved away it is found only by rays scattered towards it.
+pub struct MovingSphere {
+    pub center0: Point3,
+    pub center1: Point3,
+    pub time0: f64,
+    pub time1: f64,
+    pub radius: f64,
+    pub material: Box<dyn Material>,
+}
+
+impl MovingSphere {
+    fn center(&self, time: f64) -> Point3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
         }
-		let p = r.at(root);
-        let outward_normal = (p - self.center) / self.radius;
-        let mut hr = HitRecord {
- 			p, normal: (r.at(root) - self.center) / self.radius, t: root, front_face: false, material: &self.material,
-			coord: get_shpere_coord(outward_normal),
-		};
-        hr.set_face_normal(r, outward_normal);
-        return Some(hr);
+        let t = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + t*(self.center1 - self.center0)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center(r.time), self.radius, self.material.as_ref(), r, t_min, t_max)
     }
     fn bounding_box(&self) -> Option<AABB> {
-        Some(AABB::new(self.center + (-self.radius), self.center+self.radius))
+        let b0 = AABB::new(self.center0 + (-self.radius), self.center0+self.radius);
+        let b1 = AABB::new(self.center1 + (-self.radius), self.center1+self.radius);
+        Some(b0.surrounding_box(&b1))
+    }
+    fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+        random_toward_sphere(self.center0, self.radius, origin, rng)
+    }
+    fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
+        pdf_toward_sphere(self.center0, self.radius, self.material.as_ref(), origin, dir)
+    }
+    fn pick_lights(&self) -> Vec<&dyn Hittable> {
+        if self.material.is_light() {
+            vec![self]
+        } else {
+            vec![]
+        }
+    }
+    fn power(&self) -> f64 {
+        diffuse_power(self.material.as_ref(), 4.0*PI * self.radius*self.radius, &self.center0)
     }
 }
 
@@ -58,4 +164,46 @@ impl Sphere {
 	pub fn box_new<T: Material+'static>(center: Point3, radius: f64, material: T) -> Box<Sphere> {
     	Box::new(Sphere{center, radius, material: Box::new(material)})
 	}
-}
\ No newline at end of file
+}
+
+#[test]
+fn moving_sphere_test() {
+    use crate::lambertian::gray;
+    let s = MovingSphere {
+        center0: Vec3(0.0, 0.0, 0.0), center1: Vec3(0.0, 2.0, 0.0), time0: 0.0, time1: 1.0, radius: 1.0,
+        material: Box::new(gray()),
+    };
+    assert_eq!(s.bounding_box(), Some(AABB::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 3.0, 1.0))));
+    let ray = |time: f64| { Ray { orig: Vec3(0.0, 1.5, -5.0), dir: Vec3(0.0, 0.0, 1.0), time } };
+    assert!(s.hit(&ray(0.0), 0.0, f64::INFINITY).is_none());
+    let hr = s.hit(&ray(0.5), 0.0, f64::INFINITY).unwrap();
+    assert!((hr.p - Vec3(0.0, 1.5, -0.75f64.sqrt())).near_zero());
+    // After `time1` the sphere stays where it stopped.
+    assert!(s.hit(&ray(3.0), 0.0, f64::INFINITY).is_some());
+}
+
+#[test]
+fn light_test() {
+    use crate::metal::*;
+    use crate::texture::*;
+
+    let light = Sphere {
+        center: Vec3(0.0, 0.0, -3.0), radius: 1.0,
+        material: Box::new(DiffuseLight { emit: Box::new(SolidColor { color: Vec3(1.0, 1.0, 1.0) }) }),
+    };
+    assert_eq!(light.pick_lights().len(), 1);
+    let mut rng = crate::rng::scene_rng(0);
+    // From outside and inside, the sampled directions hit the sphere and
+    // the density integrates to one.
+    for origin in [Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.5, -3.0)] {
+        for _ in 0..100 {
+            let dir = light.gen_random_point(&origin, &mut rng);
+            assert!(light.pdf_eval(&origin, &dir) > 0.0);
+        }
+        let n = 100000;
+        let total = (0..n).map(|_| light.pdf_eval(&origin, &random_unit_vector(&mut rng))).sum::<f64>()
+            * 4.0*PI / n as f64;
+        assert!((total - 1.0).abs() < 0.03, "{}", total);
+    }
+    assert_eq!(light.pdf_eval(&Vec3(0.0, 0.0, 0.0), &Vec3(0.0, 0.0, 1.0)), 0.0);
+}
@@ -3,7 +3,7 @@ use image::io::Reader as ImageReader;
 
 use crate::vec3::*;
 
-pub trait Texture: Sync {
+pub trait Texture: Sync + Send {
 	// Returns a color at surface coordinates `coord`. (TODO: what is `p` then?)
 	fn value(&self, coord: Vec2, p: &Point3) -> Color;
 }
@@ -34,6 +34,7 @@ impl Texture for CheckerTexture {
 	}
 }
 
+#[derive(Clone)]
 pub struct ImageTexture {
 	img: RgbImage,
 }
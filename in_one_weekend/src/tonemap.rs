@@ -0,0 +1,111 @@
+// Conversion of linear rendered colors to displayable sRGB.
+//
+// Colors are scaled by the exposure, mapped into [0, 1] by a tone mapping
+// operator and encoded with the sRGB transfer function. Floating-point
+// outputs are written linear and do not use any of this.
+use crate::vec3::*;
+use crate::rng;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Operator {
+	// Cuts off everything above 1, bright lights become flat white.
+	#[default]
+	Clamp,
+	// x / (1 + x), compresses highlights smoothly.
+	Reinhard,
+	// Krzysztof Narkowicz's fit of the ACES filmic curve, with more contrast
+	// than Reinhard.
+	Aces,
+}
+
+impl Operator {
+	pub const NAMES: [&'static str; 3] = ["clamp", "reinhard", "aces"];
+
+	pub fn parse(s: &str) -> Option<Operator> {
+		match s {
+			"clamp" => Some(Operator::Clamp),
+			"reinhard" => Some(Operator::Reinhard),
+			"aces" => Some(Operator::Aces),
+			_ => None,
+		}
+	}
+
+	fn apply(self, x: f64) -> f64 {
+		let x = x.max(0.0);
+		match self {
+			Operator::Clamp => x.min(1.0),
+			Operator::Reinhard => x / (1.0 + x),
+			Operator::Aces => (x * (2.51*x + 0.03) / (x * (2.43*x + 0.59) + 0.14)).min(1.0),
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ToneMap {
+	// In stops: colors are multiplied by 2^exposure.
+	pub exposure: f64,
+	pub operator: Operator,
+	// Adds noise of up to one quantization step before rounding, which hides
+	// banding in smooth gradients.
+	pub dither: bool,
+}
+
+// The sRGB transfer function, from linear to encoded values in [0, 1].
+fn srgb(x: f64) -> f64 {
+	if x <= 0.0031308 {
+		12.92 * x
+	} else {
+		1.055 * x.powf(1.0 / 2.4) - 0.055
+	}
+}
+
+impl ToneMap {
+	// The displayed color in [0, 1], sRGB encoded.
+	pub fn map(&self, c: Color) -> Color {
+		let scale = self.exposure.exp2();
+		let f = |x: f64| { srgb(self.operator.apply(scale * x)) };
+		Vec3(f(c.0), f(c.1), f(c.2))
+	}
+
+	// Displayed color of the pixel at image coordinates `x`, `y` as integers
+	// in [0, max].
+	pub fn quantize(&self, c: Color, x: u32, y: u32, max: f64) -> [f64; 3] {
+		let c = self.map(c);
+		let q = |v: f64, channel: u64| {
+			// Rounds down or up, randomly with the distance to the neighbors.
+			let offset = if self.dither { rng::unit_f64(rng::hash(&[x as u64, y as u64, channel])) } else { 0.5 };
+			(max * v + offset).floor().clamp(0.0, max)
+		};
+		[q(c.0, 0), q(c.1, 1), q(c.2, 2)]
+	}
+}
+
+#[test]
+fn srgb_test() {
+	assert_eq!(srgb(0.0), 0.0);
+	assert!((srgb(1.0) - 1.0).abs() < 1e-12);
+	assert!((srgb(0.18) - 0.4614).abs() < 1e-4);
+	// The two pieces meet.
+	assert!((12.92 * 0.0031308 - (1.055 * 0.0031308f64.powf(1.0 / 2.4) - 0.055)).abs() < 1e-6);
+}
+
+#[test]
+fn tone_map_test() {
+	let light = Vec3(15.0, 15.0, 0.25);
+	assert_eq!(ToneMap::default().quantize(light, 0, 0, 255.0), [255.0, 255.0, 137.0]);
+	// Operators other than clamp keep bright colors apart.
+	for operator in [Operator::Reinhard, Operator::Aces] {
+		let t = ToneMap { operator, ..ToneMap::default() };
+		assert!(t.map(Vec3(4.0, 4.0, 4.0)).0 < t.map(light).0);
+		assert!(t.map(light).0 < 1.0);
+	}
+	let t = ToneMap { exposure: 1.0, ..ToneMap::default() };
+	assert_eq!(t.map(Vec3(0.25, 0.25, 0.25)), ToneMap::default().map(Vec3(0.5, 0.5, 0.5)));
+
+	// Dithering keeps the average.
+	let t = ToneMap { dither: true, ..ToneMap::default() };
+	let c = Vec3(0.3, 0.3, 0.3);
+	let n = 10000;
+	let mean = (0..n).map(|x| t.quantize(c, x, 0, 255.0)[0]).sum::<f64>() / n as f64;
+	assert!((mean - 255.0 * t.map(c).0).abs() < 0.05, "{}", mean);
+}
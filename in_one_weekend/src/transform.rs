@@ -0,0 +1,390 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hit::*;
+use crate::ray::*;
+use crate::vec3::*;
+use crate::rng::Rng;
+
+// Affine transformation `x -> m*x + t`, the upper 3x4 part of a 4x4 matrix.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Affine {
+	m: [[f64; 3]; 3],
+	t: Vec3,
+}
+
+impl Affine {
+	pub fn identity() -> Affine {
+		Affine::linear([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+	}
+
+	fn linear(m: [[f64; 3]; 3]) -> Affine {
+		Affine { m, t: Vec3(0.0, 0.0, 0.0) }
+	}
+
+	pub fn translation(t: Vec3) -> Affine {
+		Affine { t, ..Affine::identity() }
+	}
+
+	pub fn scaling(s: Vec3) -> Affine {
+		Affine::linear([[s.0, 0.0, 0.0], [0.0, s.1, 0.0], [0.0, 0.0, s.2]])
+	}
+
+	// Counter-clockwise rotation by `deg` degrees around the x, y or z axis
+	// (`axis` 0, 1 or 2) when looking towards the origin.
+	pub fn rotation(axis: usize, deg: f64) -> Affine {
+		let (s, c) = (deg * PI / 180.0).sin_cos();
+		Affine::linear(match axis {
+			0 => [[1.0, 0.0, 0.0], [0.0, c, -s], [0.0, s, c]],
+			1 => [[c, 0.0, s], [0.0, 1.0, 0.0], [-s, 0.0, c]],
+			_ => [[c, -s, 0.0], [s, c, 0.0], [0.0, 0.0, 1.0]],
+		})
+	}
+
+	// The transformation applying `self` first and `next` second.
+	pub fn then(&self, next: &Affine) -> Affine {
+		let mut m = [[0.0; 3]; 3];
+		for (i, row) in m.iter_mut().enumerate() {
+			for (j, x) in row.iter_mut().enumerate() {
+				*x = (0..3).map(|k| next.m[i][k] * self.m[k][j]).sum();
+			}
+		}
+		Affine { m, t: next.vector(self.t) + next.t }
+	}
+
+	pub fn determinant(&self) -> f64 {
+		let m = &self.m;
+		m[0][0] * (m[1][1]*m[2][2] - m[1][2]*m[2][1])
+			- m[0][1] * (m[1][0]*m[2][2] - m[1][2]*m[2][0])
+			+ m[0][2] * (m[1][0]*m[2][1] - m[1][1]*m[2][0])
+	}
+
+	// Returns None for degenerate transformations, e.g. scaling by 0.
+	pub fn inverse(&self) -> Option<Affine> {
+		let det = self.determinant();
+		if det.abs() < 1e-12 {
+			return None;
+		}
+		let m = &self.m;
+		let cofactor = |i: usize, j: usize| {
+			let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+			let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+			m[r0][c0]*m[r1][c1] - m[r0][c1]*m[r1][c0]
+		};
+		let mut inv = [[0.0; 3]; 3];
+		for (i, row) in inv.iter_mut().enumerate() {
+			for (j, x) in row.iter_mut().enumerate() {
+				*x = cofactor(j, i) / det;
+			}
+		}
+		let inv = Affine::linear(inv);
+		Some(Affine { t: -1.0 * inv.vector(self.t), ..inv })
+	}
+
+	pub fn point(&self, p: Point3) -> Point3 {
+		self.vector(p) + self.t
+	}
+
+	// Transforms a direction, ignoring the translation.
+	pub fn vector(&self, v: Vec3) -> Vec3 {
+		let m = &self.m;
+		Vec3(
+			m[0][0]*v.0 + m[0][1]*v.1 + m[0][2]*v.2,
+			m[1][0]*v.0 + m[1][1]*v.1 + m[1][2]*v.2,
+			m[2][0]*v.0 + m[2][1]*v.1 + m[2][2]*v.2,
+		)
+	}
+
+	// Multiplies by the transposed linear part. Normals are transformed to
+	// world space by the transpose of the world-to-object transformation.
+	pub fn transpose_vector(&self, v: Vec3) -> Vec3 {
+		let m = &self.m;
+		Vec3(
+			m[0][0]*v.0 + m[1][0]*v.1 + m[2][0]*v.2,
+			m[0][1]*v.0 + m[1][1]*v.1 + m[2][1]*v.2,
+			m[0][2]*v.0 + m[1][2]*v.1 + m[2][2]*v.2,
+		)
+	}
+}
+
+// An instance of an object placed in the world by an affine transformation.
+pub struct Transform {
+	object: Arc<dyn Hittable>,
+	to_world: Affine,
+	to_object: Affine,
+	// The lights inside the object placed like it, unless the object is a
+	// single light that the Transform samples itself.
+	lights: Vec<TransformedLight>,
+}
+
+impl Transform {
+	// Returns None if the transformation is not invertible.
+	pub fn new(object: Box<dyn Hittable>, to_world: Affine) -> Option<Transform> {
+		let to_object = to_world.inverse()?;
+		let object: Arc<dyn Hittable> = Arc::from(object);
+		let lights = if is_single_light(object.as_ref()) {
+			vec![]
+		} else {
+			object.pick_lights().into_iter().map(|light| {
+				// SAFETY: the light is part of `object`, which is never changed
+				// and which the Arc kept next to the reference keeps in place.
+				let light = unsafe { std::mem::transmute::<&dyn Hittable, &'static dyn Hittable>(light) };
+				TransformedLight { _group: object.clone(), light, to_world, to_object }
+			}).collect()
+		};
+		Some(Transform { object, to_world, to_object, lights })
+	}
+}
+
+fn is_single_light(object: &dyn Hittable) -> bool {
+	let lights = object.pick_lights();
+	lights.len() == 1 && std::ptr::addr_eq(lights[0], object)
+}
+
+// Hits `object` placed in the world by `to_world`, with `to_object` its inverse.
+fn hit_transformed<'a>(object: &'a dyn Hittable, to_world: &Affine, to_object: &Affine, r: &Ray, t_min: f64,
+	t_max: f64) -> Option<HitRecord<'a>> {
+	// The direction is not normalized, so `t` is the same in both spaces.
+	let object_ray = Ray { orig: to_object.point(r.orig), dir: to_object.vector(r.dir), time: r.time };
+	let mut hr = object.hit(&object_ray, t_min, t_max)?;
+	hr.p = to_world.point(hr.p);
+	hr.normal = unit_vector(to_object.transpose_vector(hr.normal));
+	Some(hr)
+}
+
+fn bounding_box_transformed(object: &dyn Hittable, to_world: &Affine) -> Option<AABB> {
+	let b = object.bounding_box()?;
+	corners(&b).map(|c| to_world.point(c)).map(|c| AABB::new(c, c)).reduce(|a, b| a.surrounding_box(&b))
+}
+
+fn random_point_transformed(object: &dyn Hittable, to_world: &Affine, to_object: &Affine, origin: &Vec3,
+	rng: &mut Rng) -> Vec3 {
+	to_world.vector(object.gen_random_point(&to_object.point(*origin), rng))
+}
+
+fn pdf_transformed(object: &dyn Hittable, to_object: &Affine, origin: &Vec3, dir: &Vec3) -> f64 {
+	let d = unit_vector(*dir);
+	let object_dir = to_object.vector(d);
+	// Directions are mapped by `d -> A*d/|A*d|`, which changes solid angles by |det A| / |A*d|^3.
+	let jacobian = to_object.determinant().abs() / object_dir.length().powi(3);
+	object.pdf_eval(&to_object.point(*origin), &object_dir) * jacobian
+}
+
+// Areas change by about |det A|^(2/3), exactly so for uniform scaling.
+fn power_transformed(object: &dyn Hittable, to_world: &Affine) -> f64 {
+	object.power() * to_world.determinant().abs().powf(2.0 / 3.0)
+}
+
+// The corners of box `b`.
+fn corners(b: &AABB) -> impl Iterator<Item = Point3> {
+	let (lo, hi) = (b.min(), b.max());
+	(0..8).map(move |i| Vec3(
+		if i & 1 == 0 { lo.0 } else { hi.0 },
+		if i & 2 == 0 { lo.1 } else { hi.1 },
+		if i & 4 == 0 { lo.2 } else { hi.2 },
+	))
+}
+
+impl Hittable for Transform {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+		hit_transformed(self.object.as_ref(), &self.to_world, &self.to_object, r, t_min, t_max)
+	}
+
+	fn bounding_box(&self) -> Option<AABB> {
+		bounding_box_transformed(self.object.as_ref(), &self.to_world)
+	}
+
+	fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		random_point_transformed(self.object.as_ref(), &self.to_world, &self.to_object, origin, rng)
+	}
+
+	fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
+		pdf_transformed(self.object.as_ref(), &self.to_object, origin, dir)
+	}
+
+	// A single light is sampled as a whole, the lights of a group one by one.
+	fn pick_lights(&self) -> Vec<&dyn Hittable> {
+		if is_single_light(self.object.as_ref()) {
+			vec![self]
+		} else {
+			self.lights.iter().map(|l| l as &dyn Hittable).collect()
+		}
+	}
+
+	fn power(&self) -> f64 {
+		power_transformed(self.object.as_ref(), &self.to_world)
+	}
+}
+
+// A light inside a group, placed in the world like the group.
+struct TransformedLight {
+	// Owns the light, so that `light` stays valid.
+	_group: Arc<dyn Hittable>,
+	light: &'static dyn Hittable,
+	to_world: Affine,
+	to_object: Affine,
+}
+
+impl Hittable for TransformedLight {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+		hit_transformed(self.light, &self.to_world, &self.to_object, r, t_min, t_max)
+	}
+
+	fn bounding_box(&self) -> Option<AABB> {
+		bounding_box_transformed(self.light, &self.to_world)
+	}
+
+	fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		random_point_transformed(self.light, &self.to_world, &self.to_object, origin, rng)
+	}
+
+	fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
+		pdf_transformed(self.light, &self.to_object, origin, dir)
+	}
+
+	fn power(&self) -> f64 {
+		power_transformed(self.light, &self.to_world)
+	}
+}
+
+// An object that moves during the time interval [0, 1]. At time 0 it is placed
+// by `start`, whose translation must be 0, and moved to `position`. By time t it
+// has turned by t times `spin` degrees around the x, y and z axes through its
+// position and moved by t times `velocity`. Before 0 and after 1 it stays put.
+// Moving objects are not sampled as lights.
+pub struct Motion {
+	object: Box<dyn Hittable>,
+	start: Affine,
+	position: Point3,
+	velocity: Vec3,
+	spin: Vec3,
+}
+
+impl Motion {
+	// Returns None if `start` is not invertible.
+	pub fn new(object: Box<dyn Hittable>, start: Affine, position: Point3, velocity: Vec3, spin: Vec3)
+		-> Option<Motion> {
+		start.inverse()?;
+		Some(Motion { object, start: Affine { t: Vec3(0.0, 0.0, 0.0), ..start }, position, velocity, spin })
+	}
+
+	fn to_world(&self, time: f64) -> Affine {
+		let t = time.clamp(0.0, 1.0);
+		self.start
+			.then(&Affine::rotation(0, t * self.spin.0))
+			.then(&Affine::rotation(1, t * self.spin.1))
+			.then(&Affine::rotation(2, t * self.spin.2))
+			.then(&Affine::translation(self.position + t * self.velocity))
+	}
+}
+
+impl Hittable for Motion {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+		let to_world = self.to_world(r.time);
+		let to_object = to_world.inverse()?;
+		hit_transformed(self.object.as_ref(), &to_world, &to_object, r, t_min, t_max)
+	}
+
+	fn bounding_box(&self) -> Option<AABB> {
+		let b = self.object.bounding_box()?;
+		let (from, to) = (self.position, self.position + self.velocity);
+		if self.spin == Vec3(0.0, 0.0, 0.0) {
+			// The corners move in straight lines, so the boxes at both ends cover them.
+			let ends = corners(&b).flat_map(|c| { let c = self.start.point(c); [from + c, to + c] });
+			return ends.map(|c| AABB::new(c, c)).reduce(|a, b| a.surrounding_box(&b));
+		}
+		// Turning keeps the object within the sphere around its position reaching the farthest corner.
+		let r = corners(&b).map(|c| self.start.point(c).length()).fold(0.0, f64::max);
+		let box_at = |p: Point3| { AABB::new(p + (-r), p + r) };
+		Some(box_at(from).surrounding_box(&box_at(to)))
+	}
+}
+
+#[test]
+fn affine_test() {
+	let a = Affine::scaling(Vec3(2.0, 1.0, 1.0))
+		.then(&Affine::rotation(1, 90.0))
+		.then(&Affine::translation(Vec3(0.0, 0.0, 5.0)));
+	// x is scaled, then rotated to -z and moved.
+	assert_near(a.point(Vec3(1.0, 0.0, 0.0)), Vec3(0.0, 0.0, 3.0));
+	assert_near(a.inverse().unwrap().point(Vec3(0.0, 0.0, 3.0)), Vec3(1.0, 0.0, 0.0));
+	assert_near(a.vector(Vec3(0.0, 1.0, 0.0)), Vec3(0.0, 1.0, 0.0));
+	assert!(Affine::scaling(Vec3(1.0, 0.0, 1.0)).inverse().is_none());
+}
+
+#[test]
+fn transform_test() {
+	use crate::sphere::*;
+	use crate::lambertian::gray;
+
+	let sphere = Sphere::box_new(Vec3(0.0, 0.0, 0.0), 1.0, gray());
+	let ellipsoid = Transform::new(sphere, Affine::scaling(Vec3(2.0, 1.0, 1.0))
+		.then(&Affine::translation(Vec3(10.0, 0.0, 0.0)))).unwrap();
+
+	assert_eq!(ellipsoid.bounding_box(), Some(AABB::new(Vec3(8.0, -1.0, -1.0), Vec3(12.0, 1.0, 1.0))));
+	let hr = ellipsoid.hit(&Ray { orig: Vec3(0.0, 0.0, 0.0), dir: Vec3(1.0, 0.0, 0.0), time: 0.0 }, 0.0, f64::INFINITY).unwrap();
+	assert_near(hr.p, Vec3(8.0, 0.0, 0.0));
+	assert_near(hr.normal, Vec3(-1.0, 0.0, 0.0));
+	assert_eq!(hr.t, 8.0);
+}
+
+#[test]
+fn light_pdf_test() {
+	use crate::rectangle::*;
+	use crate::metal::*;
+	use crate::texture::*;
+
+	let light = || Box::new(DiffuseLight { emit: Box::new(SolidColor { color: Vec3(1.0, 1.0, 1.0) }) });
+	let rect = XZRect { p1: Vec2(-1.0, -1.0), p2: Vec2(1.0, 1.0), k: 0.0, material: light() };
+	// Scaling changes the solid angle of the light non-uniformly.
+	let place = Affine::scaling(Vec3(2.0, 1.0, 0.5)).then(&Affine::translation(Vec3(0.0, 3.0, 0.0)));
+	let scaled = Transform::new(Box::new(rect), place).unwrap();
+	let expected = XZRect { p1: Vec2(-2.0, -0.5), p2: Vec2(2.0, 0.5), k: 3.0, material: light() };
+
+	assert_eq!(scaled.pick_lights().len(), 1);
+	let origin = Vec3(0.5, 0.0, 0.2);
+	let mut rng = crate::rng::scene_rng(0);
+	for _ in 0..10 {
+		let dir = scaled.gen_random_point(&origin, &mut rng);
+		assert!((expected.pdf_eval(&origin, &dir) - scaled.pdf_eval(&origin, &dir)).abs() < 1e-9);
+	}
+
+	// The lights of a group are sampled one by one, each half as often.
+	let halves = HittableList { objects: vec![
+		Box::new(XZRect { p1: Vec2(-1.0, -1.0), p2: Vec2(0.0, 1.0), k: 0.0, material: light() }),
+		Box::new(XZRect { p1: Vec2(0.0, -1.0), p2: Vec2(1.0, 1.0), k: 0.0, material: light() }),
+	]};
+	let group = Transform::new(Box::new(halves), place).unwrap();
+	let lights = group.pick_lights();
+	assert_eq!(lights.len(), 2);
+	assert!((lights[0].power() + lights[1].power() - scaled.power()).abs() < 1e-9);
+	for i in 0..10 {
+		let dir = lights[i % 2].gen_random_point(&origin, &mut rng);
+		let pdf = lights.iter().map(|l| 0.5 * l.pdf_eval(&origin, &dir)).sum::<f64>();
+		assert!((expected.pdf_eval(&origin, &dir) - pdf).abs() < 1e-9);
+	}
+}
+
+#[test]
+fn motion_test() {
+	use crate::box_shape::*;
+	use crate::lambertian::gray;
+
+	let cube = || Box::new(BoxShape::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0), Box::new(gray())));
+	let m = Motion::new(cube(), Affine::identity(), Vec3(0.0, 0.0, 0.0), Vec3(4.0, 0.0, 0.0),
+		Vec3(0.0, 0.0, 0.0)).unwrap();
+	assert_eq!(m.bounding_box(), Some(AABB::new(Vec3(-1.0, -1.0, -1.0), Vec3(5.0, 1.0, 1.0))));
+	let ray = |time: f64| { Ray { orig: Vec3(3.0, 0.0, -5.0), dir: Vec3(0.0, 0.0, 1.0), time } };
+	assert!(m.hit(&ray(0.0), 0.0, f64::INFINITY).is_none());
+	assert_near(m.hit(&ray(0.75), 0.0, f64::INFINITY).unwrap().p, Vec3(3.0, 0.0, -1.0));
+	assert!(m.hit(&ray(2.0), 0.0, f64::INFINITY).is_some());
+
+	// Turning by 45 degrees around y brings an edge of the cube closer.
+	let m = Motion::new(cube(), Affine::identity(), Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 0.0),
+		Vec3(0.0, 90.0, 0.0)).unwrap();
+	let r = Ray { orig: Vec3(0.0, 0.0, -5.0), dir: Vec3(0.0, 0.0, 1.0), time: 0.5 };
+	assert_near(m.hit(&r, 0.0, f64::INFINITY).unwrap().p, Vec3(0.0, 0.0, -(2.0f64.sqrt())));
+	let b = m.bounding_box().unwrap();
+	assert!(b.max().0 >= 2.0f64.sqrt() && b.max().2 >= 3f64.sqrt());
+}
@@ -0,0 +1,135 @@
+use crate::aabb::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+use crate::hit::*;
+use crate::rng::Rng;
+
+static EPS: f64 = 1e-4;
+
+pub struct Triangle {
+	pub material: Box<dyn Material>,
+	// Corners, counter-clockwise when looking at the front face.
+	pub v: [Point3; 3],
+	// Normals at the corners for smooth shading. The flat geometric normal is used if missing.
+	pub normals: Option<[Vec3; 3]>,
+}
+
+// Möller–Trumbore ray-triangle intersection. Returns `t` and the barycentric
+// coordinates `(u, v)` of the hit point, which is `(1-u-v)*v0 + u*v1 + v*v2`.
+pub fn intersect(v: &[Point3; 3], r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+	let e1 = v[1] - v[0];
+	let e2 = v[2] - v[0];
+	let p = cross(r.dir, e2);
+	let det = dot(e1, p);
+	if det.abs() < 1e-12 {
+		// The ray is parallel to the triangle.
+		return None;
+	}
+	let inv_det = 1.0 / det;
+	let s = r.orig - v[0];
+	let u = dot(s, p) * inv_det;
+	if !(0.0..=1.0).contains(&u) {
+		return None;
+	}
+	let q = cross(s, e1);
+	let w = dot(r.dir, q) * inv_det;
+	if w < 0.0 || u + w > 1.0 {
+		return None;
+	}
+	let t = dot(e2, q) * inv_det;
+	if t < t_min || t > t_max {
+		return None;
+	}
+	Some((t, u, w))
+}
+
+// Builds the hit record for a hit at barycentric coordinates `(u, w)`.
+// `coord` are the surface coordinates of the hit point.
+pub fn hit_record<'a>(r: &Ray, t: f64, (u, w): (f64, f64), v: &[Point3; 3], normals: Option<[Vec3; 3]>,
+	coord: Vec2, material: &'a dyn Material) -> HitRecord<'a> {
+	let geometric = unit_vector(cross(v[1] - v[0], v[2] - v[0]));
+	let mut hr = HitRecord { p: r.at(t), normal: geometric, t, material, front_face: false, coord, object_id: 0 };
+	hr.set_face_normal(r, geometric);
+	if let Some(n) = normals {
+		let mut shading = unit_vector((1.0 - u - w) * n[0] + u * n[1] + w * n[2]);
+		// Keep the shading normal on the side of the surface the ray came from.
+		if dot(shading, hr.normal) < 0.0 {
+			shading = -1.0 * shading;
+		}
+		hr.normal = shading;
+	}
+	hr
+}
+
+pub fn bounding_box(v: &[Point3; 3]) -> AABB {
+	let min = Vec3(v[0].0.min(v[1].0).min(v[2].0), v[0].1.min(v[1].1).min(v[2].1), v[0].2.min(v[1].2).min(v[2].2));
+	let max = Vec3(v[0].0.max(v[1].0).max(v[2].0), v[0].1.max(v[1].1).max(v[2].1), v[0].2.max(v[1].2).max(v[2].2));
+	// Padded so that triangles lying in an axis plane do not get a flat box.
+	AABB::new(min + (-EPS), max + EPS)
+}
+
+pub fn area(v: &[Point3; 3]) -> f64 {
+	0.5 * cross(v[1] - v[0], v[2] - v[0]).length()
+}
+
+// Vector from `origin` to a uniformly distributed random point on the triangle.
+pub fn random_point(v: &[Point3; 3], origin: &Vec3, rng: &mut Rng) -> Vec3 {
+	let (mut a, mut b) = (random_f64(rng, 0.0, 1.0), random_f64(rng, 0.0, 1.0));
+	if a + b > 1.0 {
+		a = 1.0 - a;
+		b = 1.0 - b;
+	}
+	v[0] + a * (v[1] - v[0]) + b * (v[2] - v[0]) - *origin
+}
+
+// Solid angle density of `random_point` in direction `dir`.
+pub fn pdf_value(v: &[Point3; 3], origin: &Vec3, dir: &Vec3) -> f64 {
+	let (t, _, _) = if let Some(x) = intersect(v, &Ray { orig: *origin, dir: *dir, time: 0.0 }, 0.0001, f64::INFINITY) {
+		x
+	} else {
+		return 0.0;
+	};
+	let normal = unit_vector(cross(v[1] - v[0], v[2] - v[0]));
+	let d_squared = t * t * dir.length_squared();
+	let cos = dot(normal, *dir).abs() / dir.length();
+	d_squared / (cos * area(v))
+}
+
+impl Hittable for Triangle {
+	fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+		let (t, u, w) = intersect(&self.v, r, t_min, t_max)?;
+		Some(hit_record(r, t, (u, w), &self.v, self.normals, Vec2(u, w), self.material.as_ref()))
+	}
+	fn bounding_box(&self) -> Option<AABB> {
+		Some(bounding_box(&self.v))
+	}
+	fn gen_random_point(&self, origin: &Vec3, rng: &mut Rng) -> Vec3 {
+		random_point(&self.v, origin, rng)
+	}
+	fn pdf_eval(&self, origin: &Vec3, dir: &Vec3) -> f64 {
+		pdf_value(&self.v, origin, dir)
+	}
+	fn pick_lights(&self) -> Vec<&dyn Hittable> {
+		if self.material.is_light() {
+			vec![self]
+		} else {
+			vec![]
+		}
+	}
+	fn power(&self) -> f64 {
+		diffuse_power(self.material.as_ref(), area(&self.v), &((self.v[0] + self.v[1] + self.v[2]) / 3.0))
+	}
+}
+
+#[test]
+fn intersect_test() {
+	let v = [Vec3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0)];
+	let down = Vec3(0.0, 0.0, -1.0);
+
+	let (t, u, w) = intersect(&v, &Ray { orig: Vec3(0.25, 0.5, 2.0), dir: down, time: 0.0 }, 0.0, f64::INFINITY).unwrap();
+	assert_eq!((t, u, w), (2.0, 0.25, 0.5));
+	assert!(intersect(&v, &Ray { orig: Vec3(0.75, 0.5, 2.0), dir: down, time: 0.0 }, 0.0, f64::INFINITY).is_none());
+	assert!(intersect(&v, &Ray { orig: Vec3(0.25, 0.5, 2.0), dir: down, time: 0.0 }, 0.0, 1.0).is_none());
+	assert!(intersect(&v, &Ray { orig: Vec3(0.25, 0.5, 2.0), dir: Vec3(1.0, 0.0, 0.0), time: 0.0 }, 0.0, f64::INFINITY).is_none());
+}
@@ -1,5 +1,7 @@
 use std::ops;
-use rand::random;
+use rand::Rng as _;
+
+use crate::rng::Rng;
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Vec3(pub f64, pub f64, pub f64);
@@ -11,11 +13,11 @@ pub struct Vec2(pub f64, pub f64);
 
 impl Vec3 {
     pub fn length(self) -> f64 {
-        return (self.0 * self.0 + self.1*self.1 + self.2*self.2).sqrt();
+        (self.0 * self.0 + self.1*self.1 + self.2*self.2).sqrt()
     }
 
     pub fn length_squared(self) -> f64 {
-        return self.0 * self.0 + self.1*self.1 + self.2*self.2;
+        self.0 * self.0 + self.1*self.1 + self.2*self.2
     }
 
     pub fn near_zero(self) -> bool {
@@ -76,6 +78,11 @@ impl ops::Div<f64> for Vec3 {
     }
 }
 
+// Brightness of a linear color as perceived by the eye.
+pub fn luminance(c: Color) -> f64 {
+    0.2126*c.0 + 0.7152*c.1 + 0.0722*c.2
+}
+
 pub fn unit_vector(v: Vec3) -> Vec3 {
     v/v.length()
 }
@@ -91,16 +98,16 @@ pub fn cross(u: Vec3, v: Vec3) -> Vec3 {
         u.0*v.1 - u.1*v.0)
 }
 
-pub fn random_unit_vector() -> Vec3 {
-    unit_vector(random_in_unit_sphere())
+pub fn random_unit_vector(rng: &mut Rng) -> Vec3 {
+    unit_vector(random_in_unit_sphere(rng))
 }
 
-pub fn random_in_unit_sphere() -> Vec3 {
+pub fn random_in_unit_sphere(rng: &mut Rng) -> Vec3 {
     loop {
         let p = Vec3(
-            random::<f64>() * 2.0 - 1.0,
-            random::<f64>() * 2.0 - 1.0,
-            random::<f64>() * 2.0 - 1.0,
+            rng.gen::<f64>() * 2.0 - 1.0,
+            rng.gen::<f64>() * 2.0 - 1.0,
+            rng.gen::<f64>() * 2.0 - 1.0,
         );
         if p.length_squared() >= 1.0 {
             continue;
@@ -109,9 +116,9 @@ pub fn random_in_unit_sphere() -> Vec3 {
 	}
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
+pub fn random_in_unit_disk(rng: &mut Rng) -> Vec3 {
     loop {
-        let p = Vec3(random::<f64>()*2.0-1.0, random::<f64>()*2.0 - 1.0, 0.0);
+        let p = Vec3(rng.gen::<f64>()*2.0-1.0, rng.gen::<f64>()*2.0 - 1.0, 0.0);
         if p.length_squared()>1.0 {
             continue
         }
@@ -119,8 +126,8 @@ pub fn random_in_unit_disk() -> Vec3 {
     }
 }
 
-pub fn random_in_hemisphere(normal: Vec3) -> Vec3 {
-    let v = random_in_unit_sphere();
+pub fn random_in_hemisphere(rng: &mut Rng, normal: Vec3) -> Vec3 {
+    let v = random_in_unit_sphere(rng);
     if dot(v, normal) >= 0.0 {
         v
     } else {
@@ -128,19 +135,37 @@ pub fn random_in_hemisphere(normal: Vec3) -> Vec3 {
     }
 }
 
+// A direction uniformly distributed over the cone around the unit vector
+// `axis` with half angle acos(`cos_max`).
+pub fn random_in_cone(axis: Vec3, cos_max: f64, rng: &mut Rng) -> Vec3 {
+    let z = 1.0 - rng.gen::<f64>() * (1.0 - cos_max);
+    let r = (1.0 - z * z).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+    let a = if axis.0.abs() > 0.9 { Vec3(0.0, 1.0, 0.0) } else { Vec3(1.0, 0.0, 0.0) };
+    let v = unit_vector(cross(axis, a));
+    let u = cross(axis, v);
+    r * phi.cos() * u + r * phi.sin() * v + z * axis
+}
+
+// Fails the test unless `a` and `b` are equal up to rounding.
+#[cfg(test)]
+pub fn assert_near(a: Vec3, b: Vec3) {
+    assert!((a - b).near_zero(), "{:?} != {:?}", a, b);
+}
+
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - 2.0 * dot(v, n) * n
 }
 
-pub fn random_f64(a: f64, b: f64) -> f64 {
-    random::<f64>()*(b-a)+a
+pub fn random_f64(rng: &mut Rng, a: f64, b: f64) -> f64 {
+    rng.gen::<f64>()*(b-a)+a
 }
 
-pub fn random_vec3() -> Vec3 {
-    Vec3(random::<f64>(),random::<f64>(),random::<f64>(),)
+pub fn random_vec3(rng: &mut Rng) -> Vec3 {
+    Vec3(rng.gen::<f64>(),rng.gen::<f64>(),rng.gen::<f64>(),)
 }
 
-pub fn random_vec3_bounds(min: f64, max: f64) -> Vec3 {
+pub fn random_vec3_bounds(rng: &mut Rng, min: f64, max: f64) -> Vec3 {
     let d = max - min;
-    Vec3(random::<f64>()*d + min,random::<f64>()*d+min,random::<f64>()*d+min,)
+    Vec3(rng.gen::<f64>()*d + min,rng.gen::<f64>()*d+min,rng.gen::<f64>()*d+min,)
 }
@@ -0,0 +1,105 @@
+// Golden-image tests: every built-in scene is rendered at a tiny resolution
+// with a fixed seed and compared with its reference image in tests/golden/.
+//
+// After an intended change of the output, write new references with
+//   UPDATE_GOLDEN=1 cargo test --test golden
+// and look at them before committing. When a comparison fails, the render
+// and a difference image are written to the directory printed in the message.
+use std::path::{Path, PathBuf};
+
+use image::{Rgba, RgbaImage};
+use indicatif::ProgressBar;
+
+use in_one_weekend::bvh_node::*;
+use in_one_weekend::render::*;
+use in_one_weekend::rng;
+use in_one_weekend::scene_file::SceneSetup;
+use in_one_weekend::scenes;
+
+const WIDTH: usize = 64;
+const SAMPLES_PER_PIXEL: usize = 32;
+const SEED: u64 = 1;
+// Largest root mean square difference of the 8-bit sRGB channels,
+// scaled to [0, 1]. Leaves room for rounding differences between platforms,
+// which change a few paths but not the image as a whole.
+const TOLERANCE: f64 = 0.02;
+
+fn golden_dir() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+// Renders the middle frame of the built-in scene `name`.
+fn render(name: &str) -> RgbaImage {
+	let setup = scenes::builtin(name, &mut rng::scene_rng(SEED)).unwrap();
+	let SceneSetup { scene: mut s, view, animation: a, objects } = setup;
+	s.image_width = WIDTH;
+	s.samples_per_pixel = SAMPLES_PER_PIXEL;
+	s.save_temps = 0;
+	s.seed = SEED;
+	let world = BVHNode::new(objects);
+	let lights = s.lights(&world);
+	let frame = a.num_frames / 2;
+	let v = (a.f)(&view, frame as f64 / a.num_frames as f64);
+	build_frame(&ProgressBar::hidden(), &world, &lights, &v, &s, frame, None).unwrap().film.to_rgba8(&s.tone_map)
+}
+
+fn rmse(a: &RgbaImage, b: &RgbaImage) -> f64 {
+	let sum: f64 = a.as_raw().iter().zip(b.as_raw()).map(|(x, y)| {
+		let d = (*x as f64 - *y as f64) / 255.0;
+		d * d
+	}).sum();
+	(sum / a.as_raw().len() as f64).sqrt()
+}
+
+// Absolute difference, amplified so that small changes are visible.
+fn difference(a: &RgbaImage, b: &RgbaImage) -> RgbaImage {
+	RgbaImage::from_fn(a.width(), a.height(), |x, y| {
+		let (p, q) = (a.get_pixel(x, y), b.get_pixel(x, y));
+		let d = |i: usize| { (4 * (p[i] as i32 - q[i] as i32).unsigned_abs()).min(255) as u8 };
+		Rgba([d(0), d(1), d(2), 255])
+	})
+}
+
+#[test]
+fn builtin_scenes() {
+	let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+	let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+	let mut failures = vec![];
+	for name in scenes::NAMES {
+		let img = render(name);
+		let path = golden_dir().join(format!("{}.png", name));
+		if update {
+			img.save(&path).unwrap();
+			continue;
+		}
+		let reference = image::open(&path).unwrap_or_else(|e| {
+			panic!("{}: {}, run with UPDATE_GOLDEN=1 to create it", path.display(), e)
+		}).into_rgba8();
+		if reference.dimensions() != img.dimensions() {
+			failures.push(format!("{}: size {:?}, expected {:?}", name, img.dimensions(), reference.dimensions()));
+			continue;
+		}
+		let error = rmse(&img, &reference);
+		if error > TOLERANCE {
+			std::fs::create_dir_all(&out_dir).unwrap();
+			img.save(out_dir.join(format!("{}.png", name))).unwrap();
+			difference(&img, &reference).save(out_dir.join(format!("{}.diff.png", name))).unwrap();
+			failures.push(format!("{}: RMSE {:.4} exceeds {}, see {}", name, error, TOLERANCE, out_dir.display()));
+		}
+	}
+	assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn changes_are_detected() {
+	let a = render("cornell_box");
+	let mut b = a.clone();
+	assert_eq!(rmse(&a, &b), 0.0);
+	// Darkening a quarter of the image has to fail the comparison.
+	for p in b.pixels_mut().take((WIDTH * WIDTH) / 4) {
+		p[0] /= 2;
+		p[1] /= 2;
+		p[2] /= 2;
+	}
+	assert!(rmse(&a, &b) > TOLERANCE);
+}